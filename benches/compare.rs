@@ -8,7 +8,8 @@ fn bench_push(c: &mut Criterion) {
         b.iter_batched(
             || Overlay::new_both(1, 2), // both fg and bg already present
             |mut overlay| {
-                black_box(overlay.push(3)); // push evicts fg to bg
+                overlay.push(3); // push evicts fg to bg
+                black_box(overlay);
             },
             BatchSize::SmallInput,
         );
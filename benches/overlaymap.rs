@@ -143,7 +143,51 @@ fn flip(bencher: divan::Bencher) {
             map
         })
         .bench_values(|mut map| {
-            black_box(map.flip(&1));
+            map.flip(black_box(&1));
+            map
+        });
+}
+
+const BULK_LENS: [usize; 2] = [1_000, 100_000];
+
+fn bulk_pairs(len: usize) -> Vec<(u64, u64)> {
+    (0..len as u64)
+        .map(|i| (i.wrapping_mul(0x9e37_79b9_7f4a_7c15), i))
+        .collect()
+}
+
+#[divan::bench(args = BULK_LENS)]
+fn bulk_push_loop(bencher: divan::Bencher, len: usize) {
+    bencher
+        .with_inputs(|| bulk_pairs(len))
+        .bench_values(|pairs| {
+            let mut map = OverlayMap::<u64, u64>::new();
+            for (key, value) in pairs {
+                map.push(key, value);
+            }
+            map
+        });
+}
+
+#[divan::bench(args = BULK_LENS)]
+fn bulk_extend_reserve(bencher: divan::Bencher, len: usize) {
+    bencher
+        .with_inputs(|| bulk_pairs(len))
+        .bench_values(|pairs| {
+            let mut map = OverlayMap::<u64, u64>::new();
+            black_box(map.extend_reserve(pairs));
+            map
+        });
+}
+
+#[divan::bench(args = BULK_LENS)]
+fn bulk_push_batch(bencher: divan::Bencher, len: usize) {
+    bencher
+        .with_inputs(|| bulk_pairs(len))
+        .bench_values(|mut pairs| {
+            let mut map = OverlayMap::<u64, u64>::new();
+            black_box(map.push_batch(&mut pairs));
+            map
         });
 }
 
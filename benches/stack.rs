@@ -0,0 +1,109 @@
+use divan::{AllocProfiler, black_box};
+use overlay_map::{Overlay, OverlayStack};
+
+#[global_allocator]
+static ALLOC: AllocProfiler = AllocProfiler::system();
+
+#[divan::bench]
+fn overlay_push(bencher: divan::Bencher) {
+    bencher
+        .with_inputs(|| Overlay::new_both(1, 2))
+        .bench_values(|mut entry| {
+            entry.push(black_box(3));
+            entry
+        });
+}
+
+#[divan::bench(consts = [2, 4, 8])]
+fn stack_push<const N: usize>(bencher: divan::Bencher) {
+    bencher
+        .with_inputs(|| {
+            let mut entry = OverlayStack::<u64, N>::new_empty();
+            for i in 0..N as u64 {
+                entry.push(i);
+            }
+            entry
+        })
+        .bench_values(|mut entry| {
+            entry.push(black_box(3));
+            entry
+        });
+}
+
+#[divan::bench]
+fn overlay_pull(bencher: divan::Bencher) {
+    bencher
+        .with_inputs(|| Overlay::new_both(1, 2))
+        .bench_values(|mut entry| {
+            black_box(entry.pull());
+        });
+}
+
+#[divan::bench(consts = [2, 4, 8])]
+fn stack_pull<const N: usize>(bencher: divan::Bencher) {
+    bencher
+        .with_inputs(|| {
+            let mut entry = OverlayStack::<u64, N>::new_empty();
+            for i in 0..N as u64 {
+                entry.push(i);
+            }
+            entry
+        })
+        .bench_values(|mut entry| {
+            black_box(entry.pull());
+        });
+}
+
+#[divan::bench]
+fn overlay_swap(bencher: divan::Bencher) {
+    bencher
+        .with_inputs(|| Overlay::new_both(1, 2))
+        .bench_values(|mut entry| {
+            black_box(entry.swap(black_box(3)));
+        });
+}
+
+#[divan::bench(consts = [2, 4, 8])]
+fn stack_swap<const N: usize>(bencher: divan::Bencher) {
+    bencher
+        .with_inputs(|| {
+            let mut entry = OverlayStack::<u64, N>::new_empty();
+            for i in 0..N as u64 {
+                entry.push(i);
+            }
+            entry
+        })
+        .bench_values(|mut entry| {
+            black_box(entry.swap(black_box(3)));
+        });
+}
+
+#[divan::bench]
+fn overlay_flip(bencher: divan::Bencher) {
+    bencher
+        .with_inputs(|| Overlay::new_both(1, 2))
+        .bench_values(|mut entry| {
+            entry.flip();
+            entry
+        });
+}
+
+#[divan::bench(consts = [2, 4, 8])]
+fn stack_flip<const N: usize>(bencher: divan::Bencher) {
+    bencher
+        .with_inputs(|| {
+            let mut entry = OverlayStack::<u64, N>::new_empty();
+            for i in 0..N as u64 {
+                entry.push(i);
+            }
+            entry
+        })
+        .bench_values(|mut entry| {
+            entry.flip();
+            entry
+        });
+}
+
+fn main() {
+    divan::main();
+}
@@ -0,0 +1,110 @@
+use divan::{AllocProfiler, black_box};
+use nohash_hasher::BuildNoHashHasher;
+use overlay_map::{OverlayMap, OverlayVec};
+
+type Hasher = BuildNoHashHasher<usize>;
+
+const ENTITIES: usize = 1024;
+
+#[global_allocator]
+static ALLOC: AllocProfiler = AllocProfiler::system();
+
+fn filled_vec() -> OverlayVec<u64> {
+    (0..ENTITIES).map(|i| (i, i as u64)).collect()
+}
+
+fn filled_map() -> OverlayMap<usize, u64, Hasher> {
+    let mut map = OverlayMap::with_capacity_and_hasher(ENTITIES, Hasher::default());
+    map.extend((0..ENTITIES).map(|i| (i, i as u64)));
+    map
+}
+
+#[divan::bench]
+fn vec_get(bencher: divan::Bencher) {
+    bencher.with_inputs(filled_vec).bench_refs(|vec| {
+        for i in 0..ENTITIES {
+            black_box(vec.fg(black_box(i)));
+        }
+    });
+}
+
+#[divan::bench]
+fn map_get(bencher: divan::Bencher) {
+    bencher.with_inputs(filled_map).bench_refs(|map| {
+        for i in 0..ENTITIES {
+            black_box(map.fg(&black_box(i)));
+        }
+    });
+}
+
+#[divan::bench]
+fn vec_push_existing(bencher: divan::Bencher) {
+    bencher.with_inputs(filled_vec).bench_refs(|vec| {
+        for i in 0..ENTITIES {
+            black_box(vec.push_at(black_box(i), 1));
+        }
+    });
+}
+
+#[divan::bench]
+fn map_push_existing(bencher: divan::Bencher) {
+    bencher.with_inputs(filled_map).bench_refs(|map| {
+        for i in 0..ENTITIES {
+            black_box(map.push(black_box(i), 1));
+        }
+    });
+}
+
+#[divan::bench]
+fn vec_swap(bencher: divan::Bencher) {
+    bencher.with_inputs(filled_vec).bench_refs(|vec| {
+        for i in 0..ENTITIES {
+            black_box(vec.swap_at(black_box(i), 1));
+        }
+    });
+}
+
+#[divan::bench]
+fn map_swap(bencher: divan::Bencher) {
+    bencher.with_inputs(filled_map).bench_refs(|map| {
+        for i in 0..ENTITIES {
+            black_box(map.swap(black_box(i), 1));
+        }
+    });
+}
+
+#[divan::bench]
+fn vec_pull(bencher: divan::Bencher) {
+    bencher.with_inputs(filled_vec).bench_refs(|vec| {
+        for i in 0..ENTITIES {
+            black_box(vec.pull_at(black_box(i)));
+        }
+    });
+}
+
+#[divan::bench]
+fn map_pull(bencher: divan::Bencher) {
+    bencher.with_inputs(filled_map).bench_refs(|map| {
+        for i in 0..ENTITIES {
+            black_box(map.pull(&black_box(i)));
+        }
+    });
+}
+
+#[divan::bench]
+fn vec_iter(bencher: divan::Bencher) {
+    bencher
+        .with_inputs(filled_vec)
+        .bench_refs(|vec| vec.iter().map(|(_, e)| *e.fg_unchecked()).sum::<u64>());
+}
+
+#[divan::bench]
+fn map_iter(bencher: divan::Bencher) {
+    bencher
+        .with_inputs(filled_map)
+        .bench_refs(|map| map.iter().map(|(_, e)| *e.fg_unchecked()).sum::<u64>());
+}
+
+fn main() {
+    divan::main();
+}
@@ -0,0 +1,161 @@
+use std::hash::{BuildHasher, Hash};
+
+use hashbrown::{HashMap, hash_map::RawEntryMut};
+
+use crate::{Allocator, Overlay, OverlayMap, OverlayObserver, push_observed};
+
+/// How many keys [`OverlayMap::push_batch`] hashes ahead of inserting them.
+const HASH_CHUNK: usize = 32;
+
+/// The outcome of a bulk insertion with [`OverlayMap::push_batch`] or
+/// [`OverlayMap::extend_reserve`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BatchReport {
+    /// Pairs whose key was absent and was inserted.
+    pub inserted: usize,
+    /// Pairs pushed onto a key that already had a foreground.
+    pub replaced: usize,
+}
+
+impl BatchReport {
+    /// Total number of pairs pushed.
+    pub fn total(&self) -> usize {
+        self.inserted + self.replaced
+    }
+}
+
+impl<K, V, S, A, O> OverlayMap<K, V, S, A, HashMap<K, Overlay<V>, S, A>, O>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+    A: Allocator,
+    O: OverlayObserver<K, V>,
+{
+    /// Pushes every pair in `batch` into the map, draining it, and reports how many keys were
+    /// inserted and how many were pushed onto an existing foreground.
+    ///
+    /// This has the same effect as calling [`push`](Self::push) for each pair in order, but
+    /// reserves room for the whole batch up front, so the table grows at most once, and hashes
+    /// keys a chunk at a time ahead of inserting them, so the hashing of one chunk is not
+    /// stalled behind the table probes of the previous one. If some keys are already present
+    /// the reservation may overshoot; call [`shrink_to_fit`](Self::shrink_to_fit) afterwards if
+    /// that matters.
+    ///
+    /// The batch is left empty with its capacity intact, ready to be refilled.
+    ///
+    /// ```
+    /// use overlay_map::{BatchReport, OverlayMap};
+    ///
+    /// let mut map = OverlayMap::new();
+    /// map.push("a", 0);
+    ///
+    /// let mut batch = vec![("a", 1), ("b", 2), ("b", 3)];
+    /// let report = map.push_batch(&mut batch);
+    ///
+    /// assert_eq!(report, BatchReport { inserted: 1, replaced: 2 });
+    /// assert!(batch.is_empty());
+    /// assert_eq!((map.fg(&"a"), map.bg(&"a")), (Some(&1), Some(&0)));
+    /// assert_eq!((map.fg(&"b"), map.bg(&"b")), (Some(&3), Some(&2)));
+    /// ```
+    pub fn push_batch(&mut self, batch: &mut Vec<(K, V)>) -> BatchReport {
+        let mut report = BatchReport::default();
+        self.map.reserve(batch.len());
+
+        let mut hashes = [0; HASH_CHUNK];
+        let mut pairs = batch.drain(..);
+        while !pairs.as_slice().is_empty() {
+            let chunk = &pairs.as_slice()[..pairs.as_slice().len().min(HASH_CHUNK)];
+            for (hash, (key, _)) in hashes.iter_mut().zip(chunk) {
+                *hash = self.map.hasher().hash_one(key);
+            }
+
+            // `hashes` comes first so that `zip` takes exactly one chunk from `pairs`.
+            for (&hash, (key, value)) in hashes[..chunk.len()].iter().zip(pairs.by_ref()) {
+                match self.map.raw_entry_mut().from_key_hashed_nocheck(hash, &key) {
+                    RawEntryMut::Occupied(mut entry) => {
+                        push_observed(&mut self.observer, &key, entry.get_mut(), value);
+                        report.replaced += 1;
+                    }
+                    RawEntryMut::Vacant(entry) => {
+                        self.observer.on_insert(&key, &value);
+                        entry.insert_hashed_nocheck(hash, key, Overlay::new_fg(value));
+                        report.inserted += 1;
+                    }
+                }
+            }
+        }
+
+        report
+    }
+
+    /// Pushes every pair from `iter` into the map, reserving room for the iterator's size hint
+    /// up front, and reports how many keys were inserted and how many were pushed onto an
+    /// existing foreground.
+    ///
+    /// Unlike [`Extend`], which only reserves for half the hint when the map is not empty in
+    /// case most keys are already present, this reserves for all of it. Pairs are buffered and
+    /// pushed a chunk at a time with [`push_batch`](Self::push_batch).
+    ///
+    /// ```
+    /// use overlay_map::OverlayMap;
+    ///
+    /// let mut map = OverlayMap::new();
+    /// let report = map.extend_reserve((0..1000).map(|i| (i % 600, i)));
+    ///
+    /// assert_eq!((report.inserted, report.replaced), (600, 400));
+    /// assert_eq!(map.len(), 600);
+    /// assert_eq!(map.bg(&0), Some(&0));
+    /// ```
+    pub fn extend_reserve<I>(&mut self, iter: I) -> BatchReport
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let mut iter = iter.into_iter();
+        self.map.reserve(iter.size_hint().0);
+
+        let mut report = BatchReport::default();
+        let mut chunk = Vec::with_capacity(HASH_CHUNK);
+        loop {
+            chunk.extend(iter.by_ref().take(HASH_CHUNK));
+            if chunk.is_empty() {
+                return report;
+            }
+            let pushed = self.push_batch(&mut chunk);
+            report.inserted += pushed.inserted;
+            report.replaced += pushed.replaced;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::OverlayStats;
+
+    #[test]
+    fn batch_matches_pushing_one_at_a_time() {
+        let pairs: Vec<_> = (0..1000u32).map(|i| ((i * 7919) % 300, i)).collect();
+
+        let mut expected = OverlayMap::new().with_observer(OverlayStats::default());
+        for &(key, value) in &pairs {
+            expected.push(key, value);
+        }
+
+        let mut batched = OverlayMap::new().with_observer(OverlayStats::default());
+        let mut batch = pairs.clone();
+        let report = batched.push_batch(&mut batch);
+        assert_eq!(report.total(), pairs.len());
+        assert_eq!(report.inserted, expected.len());
+        assert!(batch.is_empty() && batch.capacity() >= pairs.len());
+
+        let mut extended = OverlayMap::new().with_observer(OverlayStats::default());
+        assert_eq!(extended.extend_reserve(pairs), report);
+
+        for map in [&batched, &extended] {
+            assert_eq!(map.storage(), expected.storage());
+            assert_eq!(map.observer().inserts, expected.observer().inserts);
+            assert_eq!(map.observer().pushes, expected.observer().pushes);
+            assert_eq!(map.observer().evictions, expected.observer().evictions);
+        }
+    }
+}
@@ -0,0 +1,333 @@
+use std::{
+    collections::{BTreeMap, btree_map},
+    ops::{Deref, DerefMut, Index, RangeBounds},
+};
+
+use crate::{Global, Overlay, OverlayMap, OverlayStorage, Upsert};
+
+/// An ordered two-layered map where each key holds a current (foreground) and optional
+/// historical (background) value.
+///
+/// `OverlayBTreeMap` wraps an [`OverlayMap`] stored in a [`BTreeMap`] and dereferences to
+/// it, so it offers the same push/pull/swap/flip API while keeping its keys sorted. This
+/// makes it suitable for time-indexed or lexicographically keyed state, where entries need to
+/// be traversed in order or queried by range.
+///
+/// Iteration yields each key together with its [`Overlay<V>`], so both layers are visible.
+///
+/// # Example
+///
+/// ```
+/// use overlay_map::OverlayBTreeMap;
+///
+/// let mut map = OverlayBTreeMap::new();
+/// map.push(30, "c");
+/// map.push(10, "a");
+/// map.push(20, "b");
+/// map.push(20, "b2");
+///
+/// let keys: Vec<_> = map.iter().map(|(k, _)| *k).collect();
+/// assert_eq!(keys, vec![10, 20, 30]);
+///
+/// let (key, entry) = map.range(15..25).next().unwrap();
+/// assert_eq!(*key, 20);
+/// assert_eq!(entry.fg(), Some(&"b2"));
+/// assert_eq!(entry.bg(), Some(&"b"));
+/// ```
+#[derive(Debug)]
+pub struct OverlayBTreeMap<K, V> {
+    map: OverlayMap<K, V, (), Global, BTreeMap<K, Overlay<V>>>,
+}
+
+impl<K, V> OverlayBTreeMap<K, V>
+where
+    K: Ord,
+{
+    /// Creates a new, empty `OverlayBTreeMap`.
+    pub fn new() -> Self {
+        Self {
+            map: OverlayMap::from_storage(BTreeMap::new()),
+        }
+    }
+
+    /// Consumes the wrapper, returning the underlying [`OverlayMap`].
+    pub fn into_map(self) -> OverlayMap<K, V, (), Global, BTreeMap<K, Overlay<V>>> {
+        self.map
+    }
+}
+
+impl<K, V, S, A, O> OverlayMap<K, V, S, A, BTreeMap<K, Overlay<V>>, O>
+where
+    K: Ord,
+{
+    /// Get an iterator over the entries whose keys fall within the given range, sorted by key.
+    ///
+    /// ```
+    /// use overlay_map::OverlayBTreeMap;
+    ///
+    /// let mut map = OverlayBTreeMap::new();
+    /// for (day, temp) in [(1, 12), (2, 15), (3, 11), (4, 18)] {
+    ///     map.push(day, temp);
+    /// }
+    ///
+    /// let midweek: Vec<_> = map.range(2..=3).map(|(_, e)| *e.fg_unchecked()).collect();
+    /// assert_eq!(midweek, vec![15, 11]);
+    /// ```
+    pub fn range<R>(&self, range: R) -> btree_map::Range<'_, K, Overlay<V>>
+    where
+        R: RangeBounds<K>,
+    {
+        self.map.range(range)
+    }
+
+    /// Returns the entry with the smallest key.
+    ///
+    /// ```
+    /// use overlay_map::OverlayBTreeMap;
+    ///
+    /// let mut map = OverlayBTreeMap::new();
+    /// map.push("b", 2);
+    /// map.push("a", 1);
+    ///
+    /// let (key, entry) = map.first_key_value().unwrap();
+    /// assert_eq!((*key, entry.fg()), ("a", Some(&1)));
+    /// ```
+    pub fn first_key_value(&self) -> Option<(&K, &Overlay<V>)> {
+        self.map.first_key_value()
+    }
+
+    /// Returns the entry with the largest key.
+    ///
+    /// ```
+    /// use overlay_map::OverlayBTreeMap;
+    ///
+    /// let mut map = OverlayBTreeMap::new();
+    /// map.push("b", 2);
+    /// map.push("a", 1);
+    ///
+    /// let (key, entry) = map.last_key_value().unwrap();
+    /// assert_eq!((*key, entry.fg()), ("b", Some(&2)));
+    /// ```
+    pub fn last_key_value(&self) -> Option<(&K, &Overlay<V>)> {
+        self.map.last_key_value()
+    }
+}
+
+impl<K, V> Default for OverlayBTreeMap<K, V>
+where
+    K: Ord,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V> Clone for OverlayBTreeMap<K, V>
+where
+    K: Clone,
+    V: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            map: self.map.clone(),
+        }
+    }
+}
+
+impl<K, V> PartialEq for OverlayBTreeMap<K, V>
+where
+    K: PartialEq,
+    V: PartialEq,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.map == other.map
+    }
+}
+
+impl<K, V> Eq for OverlayBTreeMap<K, V>
+where
+    K: Eq,
+    V: Eq,
+{
+}
+
+impl<K, V> Deref for OverlayBTreeMap<K, V> {
+    type Target = OverlayMap<K, V, (), Global, BTreeMap<K, Overlay<V>>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.map
+    }
+}
+
+impl<K, V> DerefMut for OverlayBTreeMap<K, V> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.map
+    }
+}
+
+impl<K, V> From<OverlayMap<K, V, (), Global, BTreeMap<K, Overlay<V>>>> for OverlayBTreeMap<K, V> {
+    fn from(map: OverlayMap<K, V, (), Global, BTreeMap<K, Overlay<V>>>) -> Self {
+        Self { map }
+    }
+}
+
+impl<K, V> Extend<(K, V)> for OverlayBTreeMap<K, V>
+where
+    K: Ord,
+{
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        self.map.extend(iter);
+    }
+}
+
+impl<K, V> FromIterator<(K, V)> for OverlayBTreeMap<K, V>
+where
+    K: Ord,
+{
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut map = Self::new();
+        map.extend(iter);
+        map
+    }
+}
+
+impl<K, V> Index<&K> for OverlayBTreeMap<K, V>
+where
+    K: Ord,
+{
+    type Output = V;
+
+    /// Returns a reference to the foreground value associated with the key.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the key is not present in the map.
+    #[inline]
+    fn index(&self, key: &K) -> &V {
+        &self.map[key]
+    }
+}
+
+impl<K, V> IntoIterator for OverlayBTreeMap<K, V> {
+    type Item = (K, Overlay<V>);
+    type IntoIter = btree_map::IntoIter<K, Overlay<V>>;
+
+    /// Consumes the map, yielding its entries sorted by key.
+    fn into_iter(self) -> Self::IntoIter {
+        self.map.map.into_iter()
+    }
+}
+
+impl<'a, K, V> IntoIterator for &'a OverlayBTreeMap<K, V> {
+    type Item = (&'a K, &'a Overlay<V>);
+    type IntoIter = btree_map::Iter<'a, K, Overlay<V>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.map.map.iter()
+    }
+}
+
+// SAFETY: `BTreeMap` is a plain map; every method forwards to it.
+unsafe impl<K, V> OverlayStorage<K, V> for BTreeMap<K, Overlay<V>>
+where
+    K: Ord,
+{
+    type Iter<'a>
+        = btree_map::Iter<'a, K, Overlay<V>>
+    where
+        Self: 'a,
+        K: 'a,
+        V: 'a;
+
+    #[inline]
+    fn len(&self) -> usize {
+        BTreeMap::len(self)
+    }
+
+    #[inline]
+    fn get(&self, key: &K) -> Option<&Overlay<V>> {
+        BTreeMap::get(self, key)
+    }
+
+    #[inline]
+    fn get_mut(&mut self, key: &K) -> Option<&mut Overlay<V>> {
+        BTreeMap::get_mut(self, key)
+    }
+
+    #[inline]
+    fn insert(&mut self, key: K, overlay: Overlay<V>) -> Option<Overlay<V>> {
+        BTreeMap::insert(self, key, overlay)
+    }
+
+    #[inline]
+    fn remove(&mut self, key: &K) -> Option<Overlay<V>> {
+        BTreeMap::remove(self, key)
+    }
+
+    #[inline]
+    fn upsert<R>(&mut self, key: K, value: V, f: impl FnOnce(&K, Upsert<'_, V>) -> R) -> R {
+        if let Some(entry) = BTreeMap::get_mut(self, &key) {
+            return f(&key, Upsert::Occupied(entry, value));
+        }
+        let result = f(&key, Upsert::Vacant(&value));
+        BTreeMap::insert(self, key, Overlay::new_fg(value));
+        result
+    }
+
+    #[inline]
+    fn get_or_insert_with(&mut self, key: K, f: impl FnOnce(&K) -> Overlay<V>) -> &mut Overlay<V> {
+        match BTreeMap::entry(self, key) {
+            btree_map::Entry::Occupied(entry) => entry.into_mut(),
+            btree_map::Entry::Vacant(entry) => {
+                let overlay = f(entry.key());
+                entry.insert(overlay)
+            }
+        }
+    }
+
+    #[inline]
+    fn update<R>(&mut self, key: &K, f: impl FnOnce(&mut Overlay<V>) -> Option<R>) -> Option<R> {
+        let entry = BTreeMap::get_mut(self, key)?;
+        let result = f(entry);
+        if entry.is_empty() {
+            BTreeMap::remove(self, key);
+        }
+        result
+    }
+
+    #[inline]
+    fn iter(&self) -> Self::Iter<'_> {
+        BTreeMap::iter(self)
+    }
+
+    #[inline]
+    fn retain(&mut self, f: impl FnMut(&K, &mut Overlay<V>) -> bool) {
+        BTreeMap::retain(self, f)
+    }
+
+    #[inline]
+    fn clear(&mut self) {
+        BTreeMap::clear(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ordered_iteration_exposes_both_layers() {
+        let mut map: OverlayBTreeMap<&str, i32> =
+            [("b", 1), ("a", 1), ("b", 2)].into_iter().collect();
+        map.flip(&"b");
+
+        let layers: Vec<_> = map
+            .iter()
+            .map(|(k, e)| (*k, e.fg().copied(), e.bg().copied()))
+            .collect();
+        assert_eq!(layers, vec![("a", Some(1), None), ("b", Some(1), Some(2))]);
+
+        assert_eq!(map.pull(&"a"), Some(1));
+        assert_eq!(map.first_key_value().map(|(k, _)| *k), Some("b"));
+    }
+}
@@ -0,0 +1,362 @@
+use std::{
+    num::{
+        NonZeroI8, NonZeroI16, NonZeroI32, NonZeroI64, NonZeroI128, NonZeroIsize, NonZeroU8,
+        NonZeroU16, NonZeroU32, NonZeroU64, NonZeroU128, NonZeroUsize,
+    },
+    ptr::NonNull,
+    rc::Rc,
+    sync::Arc,
+};
+
+use crate::Overlay;
+
+/// Types with a niche, so that `Option<Self>` is the same size as `Self`.
+///
+/// This is the bound on [`CompactOverlay`], which relies on the niche to track slot presence
+/// without a separate flag byte. Implementing it for a type without a niche is not unsafe; it
+/// only means the `CompactOverlay` will not be compact.
+///
+/// Implemented for references, [`Box`], [`Rc`], [`Arc`], [`NonNull`] and the `NonZero*`
+/// integers. Implement it for your own types that wrap one of these.
+pub trait Niche {}
+
+impl<T: ?Sized> Niche for &T {}
+impl<T: ?Sized> Niche for &mut T {}
+impl<T: ?Sized> Niche for Box<T> {}
+impl<T: ?Sized> Niche for Rc<T> {}
+impl<T: ?Sized> Niche for Arc<T> {}
+impl<T: ?Sized> Niche for NonNull<T> {}
+
+macro_rules! impl_niche {
+    ($($ty:ty),*) => {
+        $(impl Niche for $ty {})*
+    };
+}
+
+impl_niche!(
+    NonZeroU8,
+    NonZeroU16,
+    NonZeroU32,
+    NonZeroU64,
+    NonZeroU128,
+    NonZeroUsize,
+    NonZeroI8,
+    NonZeroI16,
+    NonZeroI32,
+    NonZeroI64,
+    NonZeroI128,
+    NonZeroIsize
+);
+
+/// A two-layer value container for [`Niche`] types, exactly `2 * size_of::<T>()` in size.
+///
+/// [`Overlay<T>`] keeps a flag byte next to its two slots, which is padded to `T`'s alignment,
+/// so an `Overlay<Box<T>>` takes three words. `CompactOverlay` stores each slot as an
+/// `Option<T>` instead, and lets the niche of `T` record whether the slot is present.
+///
+/// The API mirrors [`Overlay<T>`]. The one behavioural difference is that
+/// [`flip`](Self::flip) swaps the two values in memory rather than toggling a flag, which is
+/// still just a couple of word moves for the types this is meant for. The `_unchecked` methods
+/// panic instead of reading uninitialised memory when their precondition does not hold.
+///
+/// # Example
+///
+/// ```
+/// use std::mem::size_of;
+///
+/// use overlay_map::{CompactOverlay, Overlay};
+///
+/// assert_eq!(size_of::<CompactOverlay<Box<u64>>>(), 2 * size_of::<Box<u64>>());
+/// assert!(size_of::<Overlay<Box<u64>>>() > size_of::<CompactOverlay<Box<u64>>>());
+///
+/// let mut entry = CompactOverlay::new_fg(Box::new(1));
+/// entry.push(Box::new(2));
+///
+/// assert_eq!(entry.fg(), Some(&Box::new(2)));
+/// assert_eq!(entry.bg(), Some(&Box::new(1)));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CompactOverlay<T: Niche> {
+    fg: Option<T>,
+    bg: Option<T>,
+}
+
+impl<T: Niche> CompactOverlay<T> {
+    /// Creates a new `CompactOverlay` with no values.
+    pub fn new_empty() -> Self {
+        Self { fg: None, bg: None }
+    }
+
+    /// Creates a new `CompactOverlay` with a foreground value and no background.
+    pub fn new_fg(val: T) -> Self {
+        Self {
+            fg: Some(val),
+            bg: None,
+        }
+    }
+
+    /// Creates a new `CompactOverlay` with both foreground and background values.
+    pub fn new_both(fg: T, bg: T) -> Self {
+        Self {
+            fg: Some(fg),
+            bg: Some(bg),
+        }
+    }
+
+    /// Returns a reference to the current foreground value, if present.
+    #[inline]
+    pub fn fg(&self) -> Option<&T> {
+        self.fg.as_ref()
+    }
+
+    /// Returns a reference to the foreground value, assuming it is present.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the overlay is empty.
+    #[inline]
+    pub fn fg_unchecked(&self) -> &T {
+        self.fg.as_ref().expect("CompactOverlay has no foreground")
+    }
+
+    /// Returns a reference to the background value, if present.
+    #[inline]
+    pub fn bg(&self) -> Option<&T> {
+        self.bg.as_ref()
+    }
+
+    /// Returns a reference to the background value, assuming it is present.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the overlay has no background.
+    #[inline]
+    pub fn bg_unchecked(&self) -> &T {
+        self.bg.as_ref().expect("CompactOverlay has no background")
+    }
+
+    /// Returns `true` if there is no foreground value.
+    ///
+    /// A background without a foreground, as left by [`flip_unchecked`](Self::flip_unchecked)
+    /// on a foreground-only overlay, counts as empty.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.fg.is_none()
+    }
+
+    /// Returns `true` if both foreground and background values are currently present.
+    #[inline]
+    pub fn is_full(&self) -> bool {
+        self.fg.is_some() && self.bg.is_some()
+    }
+
+    /// Clears the overlay, dropping any foreground and background values.
+    #[inline]
+    pub fn clear(&mut self) {
+        self.fg = None;
+        self.bg = None;
+    }
+
+    /// Clears the overlay. Equivalent to [`clear`](Self::clear); provided for parity with
+    /// [`Overlay::clear_unchecked`].
+    #[inline]
+    pub fn clear_unchecked(&mut self) {
+        self.clear();
+    }
+
+    /// Push a value into the foreground layer, preserving the previous foreground in the
+    /// background. Any previous background value is dropped.
+    ///
+    /// ```
+    /// use std::num::NonZeroU32;
+    ///
+    /// use overlay_map::CompactOverlay;
+    ///
+    /// let one = NonZeroU32::new(1).unwrap();
+    /// let two = NonZeroU32::new(2).unwrap();
+    ///
+    /// let mut entry = CompactOverlay::new_fg(one);
+    /// entry.push(two);
+    /// assert_eq!(entry.fg(), Some(&two));
+    /// assert_eq!(entry.bg(), Some(&one));
+    /// ```
+    #[inline]
+    pub fn push(&mut self, val: T) {
+        self.bg = self.fg.replace(val);
+    }
+
+    /// Pull the current foreground value out, promoting the background to foreground if
+    /// present.
+    ///
+    /// Returns `None` if the foreground was not present.
+    #[inline]
+    pub fn pull(&mut self) -> Option<T> {
+        let pulled = self.fg.take();
+        self.fg = self.bg.take();
+        pulled
+    }
+
+    /// Pull the current foreground value, assuming it is present.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the overlay is empty.
+    #[inline]
+    pub fn pull_unchecked(&mut self) -> T {
+        self.pull().expect("CompactOverlay has no foreground")
+    }
+
+    /// Swap in a new foreground value, returning the old background if present.
+    ///
+    /// ```
+    /// use overlay_map::CompactOverlay;
+    ///
+    /// let mut entry = CompactOverlay::new_both(&"a", &"b");
+    /// assert_eq!(entry.swap(&"c"), Some(&"b"));
+    /// assert_eq!(entry.fg(), Some(&&"c"));
+    /// assert_eq!(entry.bg(), Some(&&"a"));
+    /// ```
+    #[inline]
+    pub fn swap(&mut self, val: T) -> Option<T> {
+        let evicted = self.bg.take();
+        self.push(val);
+        evicted
+    }
+
+    /// Get an iterator over the foreground and background values.
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.fg.iter().chain(self.bg.iter())
+    }
+
+    /// Flips the foreground and background layers, if both are present.
+    ///
+    /// If only one value is present, the overlay remains unchanged.
+    #[inline]
+    pub fn flip(&mut self) {
+        if self.bg.is_some() {
+            self.flip_unchecked();
+        }
+    }
+
+    /// Flips the foreground and background slots without checking if both are present.
+    ///
+    /// The caller must ensure both slots are present; flipping an overlay with only a
+    /// foreground leaves it with a background and no foreground, which the rest of the API
+    /// treats as empty.
+    #[inline]
+    pub fn flip_unchecked(&mut self) {
+        std::mem::swap(&mut self.fg, &mut self.bg);
+    }
+}
+
+impl<T: Niche> Default for CompactOverlay<T> {
+    fn default() -> Self {
+        Self::new_empty()
+    }
+}
+
+impl<T: Niche> From<T> for CompactOverlay<T> {
+    fn from(value: T) -> Self {
+        Self::new_fg(value)
+    }
+}
+
+impl<T: Niche> From<Overlay<T>> for CompactOverlay<T> {
+    /// Converts an [`Overlay`], promoting a lone background value to the foreground, since a
+    /// `CompactOverlay` only holds a background alongside a foreground.
+    fn from(overlay: Overlay<T>) -> Self {
+        match overlay.into_parts() {
+            (None, bg) => Self { fg: bg, bg: None },
+            (fg, bg) => Self { fg, bg },
+        }
+    }
+}
+
+impl<T: Niche> From<CompactOverlay<T>> for Overlay<T> {
+    fn from(compact: CompactOverlay<T>) -> Self {
+        Overlay::from_parts(compact.fg, compact.bg)
+    }
+}
+
+impl<T: Niche> IntoIterator for CompactOverlay<T> {
+    type Item = T;
+    type IntoIter = std::iter::Chain<std::option::IntoIter<T>, std::option::IntoIter<T>>;
+
+    /// Consumes the overlay, yielding the foreground value and then the background value.
+    fn into_iter(self) -> Self::IntoIter {
+        self.fg.into_iter().chain(self.bg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem::size_of;
+
+    use super::*;
+
+    #[test]
+    fn niche_types_are_two_words() {
+        assert_eq!(
+            size_of::<CompactOverlay<Box<u64>>>(),
+            2 * size_of::<Box<u64>>()
+        );
+        assert_eq!(
+            size_of::<CompactOverlay<Box<str>>>(),
+            2 * size_of::<Box<str>>()
+        );
+        assert_eq!(
+            size_of::<CompactOverlay<Arc<u64>>>(),
+            2 * size_of::<Arc<u64>>()
+        );
+        assert_eq!(
+            size_of::<CompactOverlay<Rc<u64>>>(),
+            2 * size_of::<Rc<u64>>()
+        );
+        assert_eq!(size_of::<CompactOverlay<&u8>>(), 2 * size_of::<&u8>());
+        assert_eq!(
+            size_of::<CompactOverlay<NonZeroU64>>(),
+            2 * size_of::<u64>()
+        );
+        assert_eq!(size_of::<CompactOverlay<NonZeroU8>>(), 2);
+        assert_eq!(size_of::<Overlay<Box<u64>>>(), 3 * size_of::<Box<u64>>());
+    }
+
+    #[test]
+    fn matches_overlay_behaviour() {
+        let mut compact = CompactOverlay::new_fg(Box::new(1));
+        let mut overlay = Overlay::new_fg(Box::new(1));
+
+        for i in 2..6 {
+            assert_eq!(compact.swap(Box::new(i)), overlay.swap(Box::new(i)));
+            compact.flip();
+            overlay.flip();
+            assert_eq!((compact.fg(), compact.bg()), (overlay.fg(), overlay.bg()));
+        }
+
+        let round_trip = Overlay::from(compact.clone());
+        assert_eq!(round_trip.fg(), compact.fg());
+        assert_eq!(round_trip.bg(), compact.bg());
+
+        assert_eq!(compact.pull(), overlay.pull());
+        assert_eq!(compact.pull(), overlay.pull());
+        assert!(compact.is_empty() && overlay.is_empty());
+        assert_eq!(compact.pull(), None);
+    }
+
+    #[test]
+    fn background_only_overlay_is_promoted() {
+        let compact = CompactOverlay::from(Overlay::new_bg(Box::new(1)));
+        assert_eq!(compact.fg(), Some(&Box::new(1)));
+        assert_eq!(compact.bg(), None);
+        assert!(!compact.is_empty());
+        assert!(!compact.is_full());
+    }
+
+    #[test]
+    fn unchecked_flip_of_a_lone_foreground_is_empty_but_not_full() {
+        let mut compact = CompactOverlay::new_fg(Box::new(1));
+        compact.flip_unchecked();
+        assert!(compact.is_empty());
+        assert!(!compact.is_full());
+    }
+}
@@ -0,0 +1,452 @@
+use std::{
+    hash::{BuildHasher, Hash},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use hashbrown::DefaultHashBuilder;
+
+use crate::{Overlay, OverlayMap};
+
+/// A source of monotonic time for [`ExpiringOverlayMap`].
+///
+/// Times are measured as a [`Duration`] since an arbitrary origin chosen by the clock, and
+/// must never go backwards. [`MonotonicClock`] reads [`Instant::now`]; [`ManualClock`] only
+/// moves when told to, which makes expiry deterministic in tests.
+pub trait Clock {
+    /// The current time, relative to the clock's origin.
+    fn now(&self) -> Duration;
+}
+
+/// A [`Clock`] backed by [`Instant`], with its origin at the moment it was created.
+#[derive(Debug, Clone, Copy)]
+pub struct MonotonicClock {
+    origin: Instant,
+}
+
+impl MonotonicClock {
+    /// Creates a clock whose origin is now.
+    pub fn new() -> Self {
+        Self {
+            origin: Instant::now(),
+        }
+    }
+}
+
+impl Default for MonotonicClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for MonotonicClock {
+    #[inline]
+    fn now(&self) -> Duration {
+        self.origin.elapsed()
+    }
+}
+
+/// A [`Clock`] that only moves when [`advance`](Self::advance) is called.
+///
+/// Clones share the same time, so a test can keep one clone and hand the other to the map.
+#[derive(Debug, Clone, Default)]
+pub struct ManualClock {
+    nanos: Arc<AtomicU64>,
+}
+
+impl ManualClock {
+    /// Creates a clock stopped at its origin.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Moves the clock forward by `by`.
+    pub fn advance(&self, by: Duration) {
+        let nanos = u64::try_from(by.as_nanos()).unwrap_or(u64::MAX);
+        self.nanos.fetch_add(nanos, Ordering::Relaxed);
+    }
+}
+
+impl Clock for ManualClock {
+    #[inline]
+    fn now(&self) -> Duration {
+        Duration::from_nanos(self.nanos.load(Ordering::Relaxed))
+    }
+}
+
+/// A value paired with the time and map generation at which it became the foreground.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Aged<V> {
+    at: Duration,
+    generation: u64,
+    value: V,
+}
+
+/// How long a background value may live after it was replaced.
+#[derive(Debug, Clone, Copy, Default)]
+struct Limits {
+    max_age: Option<Duration>,
+    max_generations: Option<u64>,
+}
+
+impl Limits {
+    /// A background became a background when the current foreground was stamped, so its age
+    /// is measured from the foreground's stamp.
+    #[inline]
+    fn expired<V>(&self, entry: &Overlay<Aged<V>>, now: Duration, generation: u64) -> bool {
+        if !entry.is_full() {
+            return false;
+        }
+        let fg = entry.fg_unchecked();
+        self.max_age
+            .is_some_and(|max| now.saturating_sub(fg.at) > max)
+            || self
+                .max_generations
+                .is_some_and(|max| generation - fg.generation > max)
+    }
+}
+
+/// An [`OverlayMap`] whose background values expire.
+///
+/// In a plain `OverlayMap`, a background lives until the next push to its key, however long
+/// that takes. `ExpiringOverlayMap` drops a background once it is older than a maximum age,
+/// measured with a [`Clock`], or older than a number of map generations. Either limit, both,
+/// or neither can be set. A background's age counts from the moment it was replaced by the
+/// current foreground. Foreground values never expire.
+///
+/// The map's generation advances by one on every push, swap, pull or flip, as in
+/// [`GenerationalOverlayMap`](crate::GenerationalOverlayMap).
+///
+/// Expiry is lazy: an expired background is hidden from [`bg`](Self::bg) straight away, and is
+/// dropped the next time its key is written to. Call [`purge_expired`](Self::purge_expired) to
+/// drop every expired background at once and free its memory.
+///
+/// # Example
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use overlay_map::{ExpiringOverlayMap, ManualClock};
+///
+/// let clock = ManualClock::new();
+/// let mut map = ExpiringOverlayMap::with_clock(clock.clone())
+///     .with_max_age(Duration::from_secs(60));
+///
+/// map.push("config", "v1");
+/// map.push("config", "v2");
+/// assert_eq!(map.bg(&"config"), Some(&"v1"));
+///
+/// clock.advance(Duration::from_secs(61));
+/// assert_eq!(map.bg(&"config"), None);
+/// assert_eq!(map.purge_expired(), 1);
+///
+/// // With its background gone, pulling the foreground removes the key.
+/// assert_eq!(map.pull(&"config"), Some("v2"));
+/// assert!(map.is_empty());
+/// ```
+#[derive(Debug)]
+pub struct ExpiringOverlayMap<K, V, C = MonotonicClock, S = DefaultHashBuilder>
+where
+    K: Eq + Hash,
+{
+    map: OverlayMap<K, Aged<V>, S>,
+    clock: C,
+    limits: Limits,
+    generation: u64,
+}
+
+impl<K, V> ExpiringOverlayMap<K, V, MonotonicClock, DefaultHashBuilder>
+where
+    K: Eq + Hash,
+{
+    /// Creates a new, empty `ExpiringOverlayMap` using the system's monotonic clock and the
+    /// default hasher.
+    ///
+    /// No limits are set, so nothing expires until [`with_max_age`](Self::with_max_age) or
+    /// [`with_max_generations`](Self::with_max_generations) is called.
+    pub fn new() -> Self {
+        Self::with_clock(MonotonicClock::new())
+    }
+}
+
+impl<K, V, C> ExpiringOverlayMap<K, V, C, DefaultHashBuilder>
+where
+    K: Eq + Hash,
+    C: Clock,
+{
+    /// Creates an empty `ExpiringOverlayMap` that reads time from the given clock.
+    pub fn with_clock(clock: C) -> Self {
+        Self::with_clock_and_hasher(clock, DefaultHashBuilder::default())
+    }
+}
+
+impl<K, V, C, S> ExpiringOverlayMap<K, V, C, S>
+where
+    K: Eq + Hash,
+    C: Clock,
+    S: BuildHasher + Default,
+{
+    /// Creates an empty `ExpiringOverlayMap` that reads time from the given clock and uses the
+    /// given hasher.
+    pub fn with_clock_and_hasher(clock: C, hasher: S) -> Self {
+        Self {
+            map: OverlayMap::with_hasher(hasher),
+            clock,
+            limits: Limits::default(),
+            generation: 0,
+        }
+    }
+
+    /// Expires backgrounds that were replaced more than `max_age` ago.
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.limits.max_age = Some(max_age);
+        self
+    }
+
+    /// Expires backgrounds that were replaced more than `max_generations` generations ago.
+    ///
+    /// ```
+    /// use overlay_map::ExpiringOverlayMap;
+    ///
+    /// let mut map = ExpiringOverlayMap::new().with_max_generations(2);
+    /// map.push("a", 1);
+    /// map.push("a", 2); // "a" is replaced at generation 2
+    /// map.push("b", 1);
+    /// map.push("c", 1);
+    /// assert_eq!(map.bg(&"a"), Some(&1));
+    ///
+    /// map.push("d", 1); // generation 5
+    /// assert_eq!(map.bg(&"a"), None);
+    /// ```
+    pub fn with_max_generations(mut self, max_generations: u64) -> Self {
+        self.limits.max_generations = Some(max_generations);
+        self
+    }
+
+    /// The maximum age of a background, if set.
+    pub fn max_age(&self) -> Option<Duration> {
+        self.limits.max_age
+    }
+
+    /// The maximum number of generations a background may live, if set.
+    pub fn max_generations(&self) -> Option<u64> {
+        self.limits.max_generations
+    }
+
+    /// The clock the map reads time from.
+    pub fn clock(&self) -> &C {
+        &self.clock
+    }
+
+    /// Number of unique keys in the map.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Check if the map is empty.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// The current generation of the map.
+    ///
+    /// Starts at `0` and is advanced by every push, swap, pull or flip that changes the map.
+    #[inline]
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Get an immutable reference to the foreground value associated with the key.
+    #[inline]
+    pub fn fg(&self, key: &K) -> Option<&V> {
+        self.map.fg(key).map(|aged| &aged.value)
+    }
+
+    /// Get an immutable reference to the background value associated with the key.
+    ///
+    /// Returns `None` if the background has expired, even if it has not been dropped yet.
+    #[inline]
+    pub fn bg(&self, key: &K) -> Option<&V> {
+        let entry = self.map.map.get(key)?;
+        if self.is_expired(entry) {
+            return None;
+        }
+        entry.bg().map(|aged| &aged.value)
+    }
+
+    /// Push a value into the foreground layer, preserving the previous value in the
+    /// background.
+    ///
+    /// See [`OverlayMap::push`].
+    #[inline]
+    pub fn push(&mut self, key: K, value: V) -> bool {
+        let value = self.stamp(value);
+        self.map.push(key, value)
+    }
+
+    /// Swap a value into the foreground layer, returning the evicted background value if
+    /// present.
+    ///
+    /// An expired background is dropped rather than returned.
+    ///
+    /// See [`OverlayMap::swap`].
+    pub fn swap(&mut self, key: K, value: V) -> Option<V> {
+        self.drop_expired(&key);
+        let value = self.stamp(value);
+        self.map.swap(key, value).map(|aged| aged.value)
+    }
+
+    /// Pulls the foreground value for a key, promoting the background to foreground.
+    ///
+    /// An expired background is dropped instead of promoted, so the key is removed. A promoted
+    /// background becomes current again, and its age restarts from now.
+    ///
+    /// See [`OverlayMap::pull`].
+    pub fn pull(&mut self, key: &K) -> Option<V> {
+        self.drop_expired(key);
+        let at = self.clock.now();
+        let entry = self.map.map.get_mut(key)?;
+        self.generation += 1;
+        let pulled = entry.pull_unchecked();
+        if entry.is_empty() {
+            self.map.map.remove(key);
+        } else {
+            restamp_fg(entry, at, self.generation);
+        }
+        Some(pulled.value)
+    }
+
+    /// Flips the foreground and background values for the given key, if both are present and
+    /// the background has not expired.
+    ///
+    /// The new foreground becomes current now, while the new background's age counts from the
+    /// flip.
+    ///
+    /// See [`OverlayMap::flip`].
+    pub fn flip(&mut self, key: &K) {
+        self.drop_expired(key);
+        let at = self.clock.now();
+        if let Some(entry) = self.map.map.get_mut(key) {
+            if entry.is_full() {
+                self.generation += 1;
+                entry.flip_unchecked();
+                restamp_fg(entry, at, self.generation);
+            }
+        }
+    }
+
+    /// Drops every expired background, returning how many were dropped.
+    ///
+    /// Foreground values are never expired, so no key is removed.
+    pub fn purge_expired(&mut self) -> usize {
+        let now = self.clock.now();
+        let mut purged = 0;
+        for entry in self.map.map.values_mut() {
+            if self.limits.expired(entry, now, self.generation) {
+                entry.take_bg();
+                purged += 1;
+            }
+        }
+        purged
+    }
+
+    #[inline]
+    fn is_expired(&self, entry: &Overlay<Aged<V>>) -> bool {
+        self.limits
+            .expired(entry, self.clock.now(), self.generation)
+    }
+
+    #[inline]
+    fn drop_expired(&mut self, key: &K) {
+        let now = self.clock.now();
+        if let Some(entry) = self.map.map.get_mut(key) {
+            if self.limits.expired(entry, now, self.generation) {
+                entry.take_bg();
+            }
+        }
+    }
+
+    #[inline]
+    fn stamp(&mut self, value: V) -> Aged<V> {
+        self.generation += 1;
+        Aged {
+            at: self.clock.now(),
+            generation: self.generation,
+            value,
+        }
+    }
+}
+
+#[inline]
+fn restamp_fg<V>(entry: &mut Overlay<Aged<V>>, at: Duration, generation: u64) {
+    let fg = entry.fg_unchecked_mut();
+    fg.at = at;
+    fg.generation = generation;
+}
+
+impl<K, V, C, S> Default for ExpiringOverlayMap<K, V, C, S>
+where
+    K: Eq + Hash,
+    C: Clock + Default,
+    S: BuildHasher + Default,
+{
+    fn default() -> Self {
+        Self::with_clock_and_hasher(C::default(), S::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expired_backgrounds_are_hidden_then_dropped() {
+        let clock = ManualClock::new();
+        let mut map =
+            ExpiringOverlayMap::with_clock(clock.clone()).with_max_age(Duration::from_secs(10));
+
+        map.push(1, "a");
+        clock.advance(Duration::from_secs(30));
+        map.push(1, "b");
+        map.push(2, "x");
+        map.push(2, "y");
+        assert_eq!(map.bg(&1), Some(&"a"));
+
+        clock.advance(Duration::from_secs(11));
+        assert_eq!(map.bg(&1), None);
+        assert_eq!(map.swap(1, "c"), None);
+        assert_eq!(map.bg(&1), Some(&"b"));
+
+        map.flip(&2);
+        assert_eq!(map.fg(&2), Some(&"y"));
+        assert_eq!(map.purge_expired(), 0);
+        assert_eq!(map.pull(&2), Some("y"));
+        assert!(map.fg(&2).is_none());
+
+        clock.advance(Duration::from_secs(11));
+        assert_eq!(map.purge_expired(), 1);
+        assert_eq!(map.len(), 1);
+    }
+
+    #[test]
+    fn generation_limit_counts_every_transition() {
+        let mut map = ExpiringOverlayMap::<_, _, ManualClock>::default().with_max_generations(1);
+        map.push("a", 1);
+        map.push("a", 2); // gen 2
+        map.push("b", 1); // gen 3
+        assert_eq!(map.bg(&"a"), Some(&1));
+
+        map.flip(&"a"); // gen 4, restamps "a"
+        assert_eq!(map.bg(&"a"), Some(&2));
+        map.pull(&"b"); // gen 5
+        map.push("b", 1); // gen 6
+        assert_eq!(map.bg(&"a"), None);
+        assert_eq!(map.max_age(), None);
+        assert_eq!(map.purge_expired(), 1);
+        assert_eq!(map.pull(&"a"), Some(1));
+        assert!(map.fg(&"a").is_none());
+    }
+}
@@ -0,0 +1,432 @@
+use std::hash::{BuildHasher, Hash};
+
+use hashbrown::{DefaultHashBuilder, HashMap, hash_map::RawEntryMut};
+
+use crate::{Overlay, OverlayMap};
+
+/// A value paired with the map generation at which it most recently became the foreground.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Stamped<V> {
+    generation: u64,
+    /// Earlier, closed periods during which the value was also the foreground, oldest first.
+    /// One is added each time a pull or flip brings the value back to the front.
+    earlier: Vec<Tenure>,
+    value: V,
+}
+
+impl<V> Stamped<V> {
+    fn new(generation: u64, value: V) -> Self {
+        Self {
+            generation,
+            earlier: Vec::new(),
+            value,
+        }
+    }
+
+    fn was_current_at(&self, generation: u64) -> bool {
+        self.earlier
+            .iter()
+            .any(|tenure| tenure.contains(generation))
+    }
+}
+
+/// The generations `from..until` during which a value was the foreground.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Tenure {
+    from: u64,
+    until: u64,
+}
+
+impl Tenure {
+    fn contains(&self, generation: u64) -> bool {
+        (self.from..self.until).contains(&generation)
+    }
+}
+
+/// An [`OverlayMap`] that stamps every layer with a map-wide generation number.
+///
+/// Every state transition (push, swap, pull or flip) advances the map's generation by one, and
+/// the value that becomes the foreground is stamped with the new generation. A layer's stamp
+/// therefore records *when it became current*, which makes the per-key foreground/background
+/// pair a consistent point-in-time view across all keys:
+///
+/// - the foreground is the value of the key from its stamp onwards
+/// - the background was the value of the key from its own stamp until the foreground's stamp
+///
+/// When a pull or flip brings the background back to the front, it is stamped again and also
+/// remembers every period it was current before, so [`fg_at`](Self::fg_at) keeps answering
+/// for those generations. Each flip therefore adds a small record to both values involved. Only the two layers of an [`Overlay`] are kept, so `fg_at` returns `None`
+/// for generations whose value has since been evicted or pulled.
+///
+/// Keys removed by a pull leave a tombstone recording the generation they were removed at, so
+/// that [`changed_since`](Self::changed_since) reports them. Tombstones are dropped when the
+/// key is inserted again, or explicitly with [`prune_removed`](Self::prune_removed).
+///
+/// Stamping is provided by this wrapper rather than as a mode of [`OverlayMap`] itself, so
+/// that maps which don't need it pay nothing for it. It covers the push, swap, pull and flip
+/// transitions; convert an existing map with [`From`].
+///
+/// # Example
+///
+/// ```
+/// use overlay_map::GenerationalOverlayMap;
+///
+/// let mut map = GenerationalOverlayMap::new();
+/// map.push("a", 1);
+/// map.push("c", 100);
+/// let checkpoint = map.generation();
+///
+/// map.push("b", 10);
+/// map.push("a", 2);
+/// map.pull(&"c");
+///
+/// assert_eq!(map.fg_at(&"a", checkpoint), Some(&1));
+/// assert_eq!(map.fg_at(&"b", checkpoint), None); // "b" did not exist yet
+/// assert_eq!(map.fg(&"a"), Some(&2));
+///
+/// let mut changed: Vec<_> = map.changed_since(checkpoint).collect();
+/// changed.sort();
+/// assert_eq!(changed, vec![&"a", &"b", &"c"]); // "c" was removed
+/// ```
+#[derive(Debug)]
+pub struct GenerationalOverlayMap<K, V, S = DefaultHashBuilder>
+where
+    K: Eq + Hash,
+{
+    map: OverlayMap<K, Stamped<V>, S>,
+    /// The generation at which each key removed by a pull was removed.
+    removed: HashMap<K, u64, S>,
+    generation: u64,
+}
+
+impl<K, V> GenerationalOverlayMap<K, V, DefaultHashBuilder>
+where
+    K: Eq + Hash,
+{
+    /// Creates a new, empty `GenerationalOverlayMap` using the default hasher.
+    pub fn new() -> Self {
+        Self::with_hasher(DefaultHashBuilder::default())
+    }
+}
+
+impl<K, V, S> GenerationalOverlayMap<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher + Default,
+{
+    /// Creates an empty `GenerationalOverlayMap` that will use the given hasher.
+    pub fn with_hasher(hasher: S) -> Self {
+        Self {
+            map: OverlayMap::with_hasher(hasher),
+            removed: HashMap::with_hasher(S::default()),
+            generation: 0,
+        }
+    }
+
+    /// Number of unique keys in the map.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Check if the map is empty.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// The current generation of the map.
+    ///
+    /// Starts at `0` and is advanced by every push, swap, pull or flip that changes the map.
+    #[inline]
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Get an immutable reference to the foreground value associated with the key.
+    #[inline]
+    pub fn fg(&self, key: &K) -> Option<&V> {
+        self.map.fg(key).map(|stamped| &stamped.value)
+    }
+
+    /// Get an immutable reference to the background value associated with the key.
+    #[inline]
+    pub fn bg(&self, key: &K) -> Option<&V> {
+        self.map.bg(key).map(|stamped| &stamped.value)
+    }
+
+    /// The generation at which the current foreground of the key most recently became current.
+    #[inline]
+    pub fn fg_generation(&self, key: &K) -> Option<u64> {
+        self.map.fg(key).map(|stamped| stamped.generation)
+    }
+
+    /// The generation at which the current background of the key most recently became the
+    /// foreground.
+    #[inline]
+    pub fn bg_generation(&self, key: &K) -> Option<u64> {
+        self.map.bg(key).map(|stamped| stamped.generation)
+    }
+
+    /// The generation at which the key was removed by a pull, if it is currently absent
+    /// because of one and its tombstone has not been pruned.
+    #[inline]
+    pub fn removed_generation(&self, key: &K) -> Option<u64> {
+        self.removed.get(key).copied()
+    }
+
+    /// Returns the value the key had as of the given generation.
+    ///
+    /// Returns `None` if the key had no value at that generation, or if that value has since
+    /// been evicted from the background or pulled.
+    ///
+    /// ```
+    /// use overlay_map::GenerationalOverlayMap;
+    ///
+    /// let mut map = GenerationalOverlayMap::new();
+    /// map.push("k", "v1"); // generation 1
+    /// map.push("k", "v2"); // generation 2
+    /// map.push("k", "v3"); // generation 3, "v1" is evicted
+    ///
+    /// assert_eq!(map.fg_at(&"k", 3), Some(&"v3"));
+    /// assert_eq!(map.fg_at(&"k", 2), Some(&"v2"));
+    /// assert_eq!(map.fg_at(&"k", 1), None);
+    ///
+    /// map.pull(&"k"); // generation 4, "v2" is current again
+    /// assert_eq!(map.fg_at(&"k", 4), Some(&"v2"));
+    /// assert_eq!(map.fg_at(&"k", 3), None); // "v3" was pulled
+    /// assert_eq!(map.fg_at(&"k", 2), Some(&"v2"));
+    /// ```
+    pub fn fg_at(&self, key: &K, generation: u64) -> Option<&V> {
+        let entry = self.map.map.get(key)?;
+        let fg = entry.fg_unchecked();
+        if generation >= fg.generation || fg.was_current_at(generation) {
+            return Some(&fg.value);
+        }
+        let bg = entry.bg()?;
+        let bg_tenure = Tenure {
+            from: bg.generation,
+            until: fg.generation,
+        };
+        (bg_tenure.contains(generation) || bg.was_current_at(generation)).then_some(&bg.value)
+    }
+
+    /// Returns an iterator over the keys whose foreground changed after the given generation,
+    /// including keys that were removed by a [`pull`](Self::pull) since then.
+    pub fn changed_since(&self, generation: u64) -> impl Iterator<Item = &K> {
+        let changed = self
+            .map
+            .map
+            .iter()
+            .filter(move |(_, entry)| entry.fg_unchecked().generation > generation)
+            .map(|(key, _)| key);
+        let removed = self
+            .removed
+            .iter()
+            .filter(move |&(_, &removed)| removed > generation)
+            .map(|(key, _)| key);
+        changed.chain(removed)
+    }
+
+    /// Drops the tombstones of keys removed at or before the given generation.
+    ///
+    /// Afterwards, [`changed_since`](Self::changed_since) no longer reports those keys for
+    /// generations before their removal.
+    pub fn prune_removed(&mut self, generation: u64) {
+        self.removed.retain(|_, &mut removed| removed > generation);
+    }
+
+    /// Push a value into the foreground layer, stamping it with the next generation.
+    ///
+    /// See [`OverlayMap::push`].
+    #[inline]
+    pub fn push(&mut self, key: K, value: V) -> bool {
+        let value = self.stamp(value);
+        self.forget_removal(&key);
+        self.map.push(key, value)
+    }
+
+    /// Swap a value into the foreground layer, stamping it with the next generation and
+    /// returning the evicted background value if present.
+    ///
+    /// See [`OverlayMap::swap`].
+    #[inline]
+    pub fn swap(&mut self, key: K, value: V) -> Option<V> {
+        let value = self.stamp(value);
+        self.forget_removal(&key);
+        self.map.swap(key, value).map(|stamped| stamped.value)
+    }
+
+    /// Pulls the foreground value for a key, promoting the background to foreground.
+    ///
+    /// The promoted value becomes current again at the next generation, so it is stamped with
+    /// it, while [`fg_at`](Self::fg_at) still reports it for the generations it was current
+    /// before. If the key is left empty it is removed, and a tombstone records the generation.
+    ///
+    /// See [`OverlayMap::pull`].
+    pub fn pull(&mut self, key: &K) -> Option<V> {
+        match self.map.map.raw_entry_mut().from_key(key) {
+            RawEntryMut::Occupied(mut occupied) => {
+                self.generation += 1;
+                let entry = occupied.get_mut();
+                let pulled = entry.pull_unchecked();
+                if entry.is_empty() {
+                    let (key, _) = occupied.remove_entry();
+                    self.removed.insert(key, self.generation);
+                } else {
+                    bring_to_front(entry, pulled.generation, self.generation);
+                }
+                Some(pulled.value)
+            }
+            RawEntryMut::Vacant(_) => None,
+        }
+    }
+
+    /// Flips the foreground and background values for the given key, if both are present.
+    ///
+    /// The new foreground is stamped with the next generation, while the new background keeps
+    /// its stamp, so [`fg_at`](Self::fg_at) still reports it for the generations in between.
+    ///
+    /// See [`OverlayMap::flip`].
+    pub fn flip(&mut self, key: &K) {
+        if let Some(entry) = self.map.map.get_mut(key) {
+            if entry.is_full() {
+                self.generation += 1;
+                let until = entry.fg_unchecked().generation;
+                entry.flip_unchecked();
+                bring_to_front(entry, until, self.generation);
+            }
+        }
+    }
+
+    #[inline]
+    fn stamp(&mut self, value: V) -> Stamped<V> {
+        self.generation += 1;
+        Stamped::new(self.generation, value)
+    }
+
+    #[inline]
+    fn forget_removal(&mut self, key: &K) {
+        if !self.removed.is_empty() {
+            self.removed.remove(key);
+        }
+    }
+}
+
+/// Stamps a former background that is now the foreground again with `generation`, adding the
+/// period it was current before to its earlier ones, which ended when the value above it became current at `until`.
+#[inline]
+fn bring_to_front<V>(entry: &mut Overlay<Stamped<V>>, until: u64, generation: u64) {
+    let fg = entry.fg_unchecked_mut();
+    fg.earlier.push(Tenure {
+        from: fg.generation,
+        until,
+    });
+    fg.generation = generation;
+}
+
+impl<K, V, S> Default for GenerationalOverlayMap<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher + Default,
+{
+    fn default() -> Self {
+        Self::with_hasher(S::default())
+    }
+}
+
+impl<K, V, S> From<OverlayMap<K, V, S>> for GenerationalOverlayMap<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher + Default,
+{
+    /// Wraps an existing map, stamping all of its layers with generation `0`.
+    fn from(map: OverlayMap<K, V, S>) -> Self {
+        let mut stamped = OverlayMap::with_capacity_and_hasher(map.len(), S::default());
+        for (key, entry) in map {
+            let (fg, bg) = entry.into_parts();
+            let stamp = |value| Stamped::new(0, value);
+            stamped
+                .map
+                .insert(key, Overlay::from_parts(fg.map(stamp), bg.map(stamp)));
+        }
+        Self {
+            map: stamped,
+            removed: HashMap::with_hasher(S::default()),
+            generation: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flip_and_pull_keep_earlier_tenures() {
+        let mut map = GenerationalOverlayMap::<&str, i32>::new();
+        map.push("k", 1); // gen 1
+        map.push("k", 2); // gen 2
+        map.flip(&"k"); // gen 3
+
+        assert_eq!(map.fg_at(&"k", 3), Some(&1));
+        assert_eq!(map.fg_at(&"k", 2), Some(&2));
+        assert_eq!(map.fg_at(&"k", 1), Some(&1));
+        assert_eq!(map.fg_at(&"k", 0), None);
+
+        assert_eq!(map.pull(&"k"), Some(1)); // gen 4
+        assert_eq!(map.fg_generation(&"k"), Some(4));
+        assert_eq!(map.fg_at(&"k", 4), Some(&2));
+        assert_eq!(map.fg_at(&"k", 3), None); // 1 was pulled
+        assert_eq!(map.fg_at(&"k", 2), Some(&2));
+        assert_eq!(map.changed_since(3).count(), 1);
+        assert_eq!(map.changed_since(4).count(), 0);
+    }
+
+    #[test]
+    fn repeated_flips_keep_every_tenure() {
+        let mut map = GenerationalOverlayMap::<&str, i32>::new();
+        map.push("k", 1); // gen 1
+        map.push("k", 2); // gen 2
+        map.flip(&"k"); // gen 3
+        map.flip(&"k"); // gen 4
+        map.flip(&"k"); // gen 5
+
+        assert_eq!(map.fg(&"k"), Some(&1));
+        let history: Vec<_> = (0..=5).map(|g| map.fg_at(&"k", g).copied()).collect();
+        assert_eq!(history, [None, Some(1), Some(2), Some(1), Some(2), Some(1)]);
+    }
+
+    #[test]
+    fn pulled_keys_are_reported_until_reinserted_or_pruned() {
+        let mut map = GenerationalOverlayMap::<&str, i32>::new();
+        map.push("a", 1); // gen 1
+        map.push("b", 2); // gen 2
+        map.pull(&"a"); // gen 3
+
+        assert_eq!(map.removed_generation(&"a"), Some(3));
+        assert_eq!(map.changed_since(2).collect::<Vec<_>>(), vec![&"a"]);
+        assert_eq!(map.changed_since(3).count(), 0);
+
+        map.prune_removed(3);
+        assert_eq!(map.changed_since(2).count(), 0);
+
+        map.pull(&"b"); // gen 4
+        map.push("b", 5); // gen 5
+        assert_eq!(map.removed_generation(&"b"), None);
+        assert_eq!(map.changed_since(4).collect::<Vec<_>>(), vec![&"b"]);
+    }
+
+    #[test]
+    fn from_overlay_map_stamps_generation_zero() {
+        let mut plain = OverlayMap::new();
+        plain.push("k", 1);
+        plain.push("k", 2);
+
+        let mut map = GenerationalOverlayMap::from(plain);
+        assert_eq!(map.fg_generation(&"k"), Some(0));
+        assert_eq!(map.bg(&"k"), Some(&1));
+
+        map.swap("k", 3);
+        assert_eq!(map.fg_at(&"k", 0), Some(&2));
+        assert_eq!(map.changed_since(0).collect::<Vec<_>>(), vec![&"k"]);
+    }
+}
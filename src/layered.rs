@@ -0,0 +1,409 @@
+use std::{
+    hash::{BuildHasher, Hash},
+    sync::Arc,
+};
+
+use hashbrown::{DefaultHashBuilder, HashMap};
+
+use crate::Overlay;
+
+/// A layer of a [`LayeredMap`] entry: a private value, or the key's value in the shared base.
+#[derive(Debug, Clone)]
+enum Layer<V> {
+    Base,
+    Own(V),
+}
+
+/// A copy-on-write map whose initial state is a shared, immutable base and whose changes are
+/// kept in a private delta.
+///
+/// Many `LayeredMap`s can share the same base through an [`Arc`] without copying it. Every
+/// write goes to the private delta, so the base is never modified. Each key behaves exactly
+/// like a key of an [`OverlayMap`](crate::OverlayMap) that started out holding the base value
+/// as its foreground:
+///
+/// - [`push`](Self::push) moves the current value, base or private, to the background
+/// - [`swap`](Self::swap) returns the evicted background
+/// - [`pull`](Self::pull) promotes the background, and pulling the last value hides the key,
+///   even if it comes from the base
+///
+/// The delta only stores keys whose state differs from the base. A key can be returned to its
+/// base state with [`revert`](Self::revert).
+///
+/// Base values are borrowed, not copied, while they sit in either layer. Methods that hand a
+/// value back to the caller clone it if it comes from the base, so they require `V: Clone`.
+///
+/// # Example
+///
+/// ```
+/// use std::sync::Arc;
+///
+/// use hashbrown::HashMap;
+/// use overlay_map::LayeredMap;
+///
+/// let defaults = Arc::new(HashMap::from([("volume", 5), ("brightness", 7)]));
+///
+/// let mut worker = LayeredMap::new(defaults.clone());
+/// worker.push("volume", 9);
+///
+/// assert_eq!(worker.fg(&"volume"), Some(&9));
+/// assert_eq!(worker.bg(&"volume"), Some(&5));
+/// assert_eq!(worker.fg(&"brightness"), Some(&7));
+/// assert_eq!(worker.bg(&"brightness"), None);
+///
+/// assert_eq!(worker.pull(&"volume"), Some(9));
+/// assert_eq!(worker.fg(&"volume"), Some(&5));
+///
+/// assert_eq!(worker.pull(&"brightness"), Some(7));
+/// assert_eq!(worker.fg(&"brightness"), None); // hidden, but still in the base
+/// assert_eq!(defaults[&"brightness"], 7);
+/// ```
+#[derive(Debug)]
+pub struct LayeredMap<K, V, S = DefaultHashBuilder> {
+    base: Arc<HashMap<K, V, S>>,
+    /// Keys whose state differs from the base. An empty overlay hides a base key.
+    delta: HashMap<K, Overlay<Layer<V>>, S>,
+    len: usize,
+}
+
+impl<K, V, S> LayeredMap<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher + Clone,
+{
+    /// Creates a `LayeredMap` with no overrides on top of the given shared base.
+    pub fn new(base: Arc<HashMap<K, V, S>>) -> Self {
+        let delta = HashMap::with_hasher(base.hasher().clone());
+        let len = base.len();
+        Self { base, delta, len }
+    }
+
+    /// Returns the shared base map.
+    pub fn base(&self) -> &Arc<HashMap<K, V, S>> {
+        &self.base
+    }
+
+    /// Number of keys visible through the map, across both layers.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Check if the map is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns `true` if the key's state differs from the base, including keys hidden by a
+    /// pull.
+    #[inline]
+    pub fn is_overridden(&self, key: &K) -> bool {
+        self.delta.contains_key(key)
+    }
+
+    /// Get an immutable reference to the foreground value associated with the key.
+    #[inline]
+    pub fn fg(&self, key: &K) -> Option<&V> {
+        match self.delta.get(key) {
+            Some(entry) => entry.fg().map(|layer| self.resolve(key, layer)),
+            None => self.base.get(key),
+        }
+    }
+
+    /// Get an immutable reference to the background value associated with the key.
+    #[inline]
+    pub fn bg(&self, key: &K) -> Option<&V> {
+        let layer = self.delta.get(key)?.bg()?;
+        Some(self.resolve(key, layer))
+    }
+
+    /// Push a value into the foreground layer.
+    ///
+    /// The current foreground, whether private or from the base, moves to the background and
+    /// any previous background is dropped from view.
+    ///
+    /// Returns `true` if there was already a foreground value.
+    ///
+    /// See [`OverlayMap::push`](crate::OverlayMap::push).
+    pub fn push(&mut self, key: K, value: V) -> bool {
+        let entry = self.entry(key);
+        let existed = entry.fg().is_some();
+        entry.push(Layer::Own(value));
+        if !existed {
+            self.len += 1;
+        }
+        existed
+    }
+
+    /// Flips the foreground and background values for the given key, if both are present.
+    ///
+    /// See [`OverlayMap::flip`](crate::OverlayMap::flip).
+    pub fn flip(&mut self, key: &K) {
+        if let Some(entry) = self.delta.get_mut(key) {
+            entry.flip();
+        }
+    }
+
+    /// Discards the private changes to a key, returning it to its base state.
+    ///
+    /// Returns `true` if the key had been changed.
+    pub fn revert(&mut self, key: &K) -> bool {
+        let Some(entry) = self.delta.remove(key) else {
+            return false;
+        };
+        let visible = entry.fg().is_some();
+        let in_base = self.base.contains_key(key);
+        if visible && !in_base {
+            self.len -= 1;
+        } else if !visible && in_base {
+            self.len += 1;
+        }
+        true
+    }
+
+    /// Get an iterator over the foreground value of every visible key, in arbitrary order.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        let changed = self.delta.iter().filter_map(|(key, entry)| {
+            let fg = entry.fg()?;
+            Some((key, self.resolve(key, fg)))
+        });
+        let unchanged = self
+            .base
+            .iter()
+            .filter(|(key, _)| !self.delta.contains_key(*key));
+        changed.chain(unchanged)
+    }
+
+    /// Discards all private changes, leaving only the base.
+    pub fn reset(&mut self) {
+        self.delta.clear();
+        self.len = self.base.len();
+    }
+
+    /// Returns the delta entry for a key, starting from its base state if it has none.
+    fn entry(&mut self, key: K) -> &mut Overlay<Layer<V>> {
+        let in_base = self.base.contains_key(&key);
+        self.delta.entry(key).or_insert_with(|| {
+            if in_base {
+                Overlay::new_fg(Layer::Base)
+            } else {
+                Overlay::new_empty()
+            }
+        })
+    }
+
+    #[inline]
+    fn resolve<'a>(&'a self, key: &K, layer: &'a Layer<V>) -> &'a V {
+        match layer {
+            Layer::Own(value) => value,
+            Layer::Base => self
+                .base
+                .get(key)
+                .expect("base layers only exist for base keys"),
+        }
+    }
+}
+
+impl<K, V, S> LayeredMap<K, V, S>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    S: BuildHasher + Clone,
+{
+    /// Swap a value into the foreground layer, returning the evicted background value if
+    /// present.
+    ///
+    /// A background evicted from the base is cloned.
+    ///
+    /// See [`OverlayMap::swap`](crate::OverlayMap::swap).
+    pub fn swap(&mut self, key: K, value: V) -> Option<V> {
+        let entry = self.entry(key.clone());
+        let existed = entry.fg().is_some();
+        let evicted = entry.swap(Layer::Own(value));
+        if !existed {
+            self.len += 1;
+        }
+        evicted.map(|layer| self.take(&key, layer))
+    }
+
+    /// Pulls the foreground value for a key, promoting the background to foreground.
+    ///
+    /// Pulling the last value of a key hides it, even if the value came from the base, which
+    /// is then cloned.
+    ///
+    /// See [`OverlayMap::pull`](crate::OverlayMap::pull).
+    pub fn pull(&mut self, key: &K) -> Option<V> {
+        let in_base = self.base.contains_key(key);
+        let entry = match self.delta.get_mut(key) {
+            Some(entry) => entry,
+            None if in_base => self
+                .delta
+                .entry(key.clone())
+                .or_insert(Overlay::new_fg(Layer::Base)),
+            None => return None,
+        };
+        let pulled = entry.pull()?;
+
+        let back_to_base = match (entry.fg(), entry.bg()) {
+            (Some(Layer::Base), None) => in_base,
+            (None, _) => !in_base,
+            _ => false,
+        };
+        if entry.fg().is_none() {
+            self.len -= 1;
+        }
+        if back_to_base {
+            self.delta.remove(key);
+        }
+        Some(self.take(key, pulled))
+    }
+
+    /// Creates a new `LayeredMap` that shares this map's base and starts with a copy of its
+    /// private changes.
+    ///
+    /// Only the private delta is cloned; the base is shared by reference count.
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    ///
+    /// use hashbrown::HashMap;
+    /// use overlay_map::LayeredMap;
+    ///
+    /// let mut parent = LayeredMap::new(Arc::new(HashMap::from([("a", 1)])));
+    /// parent.push("b", 2);
+    ///
+    /// let mut child = parent.fork();
+    /// child.push("a", 10);
+    ///
+    /// assert!(Arc::ptr_eq(parent.base(), child.base()));
+    /// assert_eq!(parent.fg(&"a"), Some(&1));
+    /// assert_eq!(child.fg(&"a"), Some(&10));
+    /// assert_eq!(child.fg(&"b"), Some(&2));
+    /// ```
+    pub fn fork(&self) -> Self {
+        Self {
+            base: self.base.clone(),
+            delta: self.delta.clone(),
+            len: self.len,
+        }
+    }
+
+    /// Materialises the foreground view of this map into a new base.
+    ///
+    /// Backgrounds are discarded and hidden keys are left out. If this map holds the only
+    /// reference to its base, the base is updated in place; otherwise it is cloned first.
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    ///
+    /// use hashbrown::HashMap;
+    /// use overlay_map::LayeredMap;
+    ///
+    /// let mut map = LayeredMap::new(Arc::new(HashMap::from([("a", 1), ("b", 2), ("d", 4)])));
+    /// map.push("b", 20);
+    /// map.push("c", 30);
+    /// map.pull(&"d");
+    ///
+    /// let next = LayeredMap::new(map.flatten());
+    /// assert_eq!(next.fg(&"a"), Some(&1));
+    /// assert_eq!(next.fg(&"b"), Some(&20));
+    /// assert_eq!(next.bg(&"b"), None);
+    /// assert_eq!(next.fg(&"d"), None);
+    /// assert_eq!(next.len(), 3);
+    /// ```
+    pub fn flatten(self) -> Arc<HashMap<K, V, S>> {
+        let mut base = Arc::unwrap_or_clone(self.base);
+        for (key, entry) in self.delta {
+            match entry.into_parts().0 {
+                Some(Layer::Own(value)) => {
+                    base.insert(key, value);
+                }
+                Some(Layer::Base) => {}
+                None => {
+                    base.remove(&key);
+                }
+            }
+        }
+        Arc::new(base)
+    }
+
+    /// Turns a layer that left the map into a value, cloning it out of the base if need be.
+    fn take(&self, key: &K, layer: Layer<V>) -> V {
+        match layer {
+            Layer::Own(value) => value,
+            Layer::Base => self.resolve(key, &Layer::Base).clone(),
+        }
+    }
+}
+
+impl<K, V, S> From<HashMap<K, V, S>> for LayeredMap<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher + Clone,
+{
+    fn from(base: HashMap<K, V, S>) -> Self {
+        Self::new(Arc::new(base))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::OverlayMap;
+
+    #[test]
+    fn behaves_like_an_overlay_map_seeded_with_the_base() {
+        let base: HashMap<u32, u32> = (0..8).map(|key| (key, key + 100)).collect();
+        let mut layered = LayeredMap::from(base.clone());
+        let mut model: OverlayMap<u32, u32> = base.into_iter().collect();
+
+        let mut rng = 0x9e37_79b9_u32;
+        for _ in 0..2000 {
+            rng ^= rng << 13;
+            rng ^= rng >> 17;
+            rng ^= rng << 5;
+            let key = rng % 12;
+            match rng % 5 {
+                0 | 1 => assert_eq!(layered.push(key, rng), model.push(key, rng)),
+                2 => assert_eq!(layered.swap(key, rng), model.swap(key, rng)),
+                3 => assert_eq!(layered.pull(&key), model.pull(&key)),
+                _ => {
+                    layered.flip(&key);
+                    model.flip(&key);
+                }
+            }
+            assert_eq!(layered.fg(&key), model.fg(&key));
+            assert_eq!(layered.bg(&key), model.bg(&key));
+            assert_eq!(layered.len(), model.len());
+        }
+
+        let mut visible: Vec<_> = layered.iter().collect();
+        let mut expected: Vec<_> = model.iter().map(|(k, e)| (k, e.fg_unchecked())).collect();
+        visible.sort();
+        expected.sort();
+        assert_eq!(visible, expected);
+    }
+
+    #[test]
+    fn pulled_base_keys_stay_hidden_until_reverted() {
+        let mut map = LayeredMap::from(HashMap::from([("a", 1)]));
+
+        assert_eq!(map.pull(&"a"), Some(1));
+        assert_eq!(map.pull(&"a"), None);
+        assert_eq!(map.fg(&"a"), None);
+        assert!(map.is_empty());
+        assert_eq!(map.iter().count(), 0);
+
+        assert!(!map.push("a", 2));
+        assert_eq!(map.bg(&"a"), None);
+
+        assert!(map.revert(&"a"));
+        assert_eq!(map.fg(&"a"), Some(&1));
+        assert!(!map.is_overridden(&"a"));
+        assert_eq!(map.len(), 1);
+
+        // Returning to the base state through the map's own transitions drops the override.
+        map.push("a", 3);
+        map.pull(&"a");
+        assert!(!map.is_overridden(&"a"));
+    }
+}
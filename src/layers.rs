@@ -0,0 +1,189 @@
+use std::hash::{BuildHasher, Hash};
+
+use hashbrown::{HashMap, hash_map::RawEntryMut};
+
+use crate::{Overlay, OverlayMap};
+
+/// Decides what [`OverlayMap::from_layers`] does with keys that only appear in the background
+/// layer.
+///
+/// Every entry in an [`OverlayMap`] must have a foreground, so such keys cannot be stored as
+/// they are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BgOnlyPolicy {
+    /// Drop the background value; the key is not inserted.
+    Discard,
+    /// Insert the background value as the key's foreground.
+    Promote,
+}
+
+impl<K, V, S> OverlayMap<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher + Default,
+{
+    /// Splits the map into its foreground and background layers.
+    ///
+    /// Every key appears in the foreground layer; only keys with a background value appear in
+    /// the background layer. Values are moved, not cloned.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use overlay_map::OverlayMap;
+    ///
+    /// let mut map = OverlayMap::new();
+    /// map.push("a", 1);
+    /// map.push("a", 2);
+    /// map.push("b", 3);
+    ///
+    /// let (fg, bg) = map.into_layers();
+    /// assert_eq!(fg.len(), 2);
+    /// assert_eq!(fg[&"a"], 2);
+    /// assert_eq!(bg.len(), 1);
+    /// assert_eq!(bg[&"a"], 1);
+    /// ```
+    pub fn into_layers(self) -> (HashMap<K, V, S>, HashMap<K, V, S>)
+    where
+        K: Clone,
+    {
+        let mut fg = HashMap::with_capacity_and_hasher(self.len(), S::default());
+        let mut bg = HashMap::with_hasher(S::default());
+
+        for (key, entry) in self {
+            let mut values = entry.into_iter();
+            if let Some(value) = values.next() {
+                if let Some(value) = values.next() {
+                    bg.insert(key.clone(), value);
+                }
+                fg.insert(key, value);
+            }
+        }
+
+        (fg, bg)
+    }
+
+    /// Returns a borrowed view of the foreground layer.
+    ///
+    /// ```
+    /// use overlay_map::OverlayMap;
+    ///
+    /// let mut map = OverlayMap::new();
+    /// map.push("a", 1);
+    /// map.push("a", 2);
+    ///
+    /// let snapshot = map.fg_snapshot();
+    /// assert_eq!(snapshot[&"a"], &2);
+    /// ```
+    pub fn fg_snapshot(&self) -> HashMap<&K, &V, S> {
+        let mut snapshot = HashMap::with_capacity_and_hasher(self.len(), S::default());
+        snapshot.extend(
+            self.map
+                .iter()
+                .map(|(key, entry)| (key, entry.fg_unchecked())),
+        );
+        snapshot
+    }
+
+    /// Builds a map by zipping a foreground and a background layer into overlays.
+    ///
+    /// Keys present in both layers get both values. Keys only present in `fg` get a
+    /// foreground without a background. Keys only present in `bg` are handled according to
+    /// `policy`. If a layer yields the same key more than once, the last value wins.
+    ///
+    /// This is the reverse of [`into_layers`](Self::into_layers).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use overlay_map::{BgOnlyPolicy, OverlayMap};
+    ///
+    /// let fg = [("a", 2), ("b", 3)];
+    /// let bg = [("a", 1), ("c", 9)];
+    ///
+    /// let map = OverlayMap::<_, _>::from_layers(fg, bg, BgOnlyPolicy::Discard);
+    /// assert_eq!(map.fg(&"a"), Some(&2));
+    /// assert_eq!(map.bg(&"a"), Some(&1));
+    /// assert_eq!(map.bg(&"b"), None);
+    /// assert_eq!(map.fg(&"c"), None);
+    ///
+    /// let map = OverlayMap::<_, _>::from_layers(fg, bg, BgOnlyPolicy::Promote);
+    /// assert_eq!(map.fg(&"c"), Some(&9));
+    /// ```
+    pub fn from_layers<F, B>(fg: F, bg: B, policy: BgOnlyPolicy) -> Self
+    where
+        F: IntoIterator<Item = (K, V)>,
+        B: IntoIterator<Item = (K, V)>,
+    {
+        let fg = fg.into_iter();
+        let mut map = Self::with_capacity(fg.size_hint().0);
+
+        for (key, value) in fg {
+            map.map.insert(key, Overlay::new_fg(value));
+        }
+
+        // Promoted values are held back until the whole background layer has been seen, so
+        // that a repeated background-only key is never mistaken for one with a foreground.
+        let mut promoted = HashMap::with_hasher(S::default());
+
+        for (key, value) in bg {
+            match map.map.raw_entry_mut().from_key(&key) {
+                RawEntryMut::Occupied(mut occupied) => {
+                    let entry = occupied.get_mut();
+                    let fg = entry.pull_unchecked();
+                    *entry = Overlay::new_both(fg, value);
+                }
+                RawEntryMut::Vacant(_) => {
+                    if policy == BgOnlyPolicy::Promote {
+                        promoted.insert(key, value);
+                    }
+                }
+            }
+        }
+
+        for (key, value) in promoted {
+            map.map.insert(key, Overlay::new_fg(value));
+        }
+
+        map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layers_round_trip() {
+        let mut map = OverlayMap::<&str, String>::new();
+        map.push("a", "a1".into());
+        map.push("a", "a2".into());
+        map.push("b", "b1".into());
+        map.push("c", "c1".into());
+        map.push("c", "c2".into());
+        map.flip(&"c");
+
+        let (fg, bg) = map.clone().into_layers();
+        let rebuilt = OverlayMap::<_, _>::from_layers(fg, bg, BgOnlyPolicy::Discard);
+        assert_eq!(rebuilt.fg(&"c"), Some(&"c1".to_string()));
+        assert_eq!(rebuilt.bg(&"c"), Some(&"c2".to_string()));
+        assert_eq!(rebuilt.len(), map.len());
+        for key in ["a", "b", "c"] {
+            assert_eq!(rebuilt.fg(&key), map.fg(&key));
+            assert_eq!(rebuilt.bg(&key), map.bg(&key));
+        }
+    }
+
+    #[test]
+    fn repeated_bg_only_keys_promote_the_last_value() {
+        let fg = [("a", 1)];
+        let bg = [("b", 2), ("a", 0), ("b", 3)];
+
+        let map = OverlayMap::<_, _>::from_layers(fg, bg, BgOnlyPolicy::Promote);
+        assert_eq!(map.fg(&"b"), Some(&3));
+        assert_eq!(map.bg(&"b"), None);
+        assert_eq!(map.fg(&"a"), Some(&1));
+        assert_eq!(map.bg(&"a"), Some(&0));
+        assert_eq!(map.len(), 2);
+    }
+}
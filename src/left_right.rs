@@ -0,0 +1,471 @@
+//! Wait-free concurrent reads over an [`OverlayMap`] using left-right double buffering.
+//!
+//! The map is kept in two copies. Readers always read the *published* copy while the single
+//! writer mutates the other one. Calling [`WriteHandle::publish`] swaps the two copies, waits
+//! for any reader still inside the old copy to leave, and then replays the pending operations
+//! onto it so both copies converge again.
+
+use std::{
+    cell::{Cell, UnsafeCell},
+    hash::{BuildHasher, Hash},
+    marker::PhantomData,
+    ops::Deref,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread,
+};
+
+use hashbrown::DefaultHashBuilder;
+
+use crate::{Op, OverlayMap};
+
+struct Shared<K, V, S>
+where
+    K: Eq + Hash,
+{
+    maps: [UnsafeCell<OverlayMap<K, V, S>>; 2],
+    read_index: AtomicUsize,
+    epochs: Mutex<Vec<Arc<AtomicUsize>>>,
+}
+
+/// The published copy is only ever read through `&`, and the unpublished copy is only ever
+/// touched by the single `WriteHandle`, which waits for all readers to leave a copy before
+/// mutating it.
+unsafe impl<K, V, S> Sync for Shared<K, V, S>
+where
+    K: Eq + Hash + Send + Sync,
+    V: Send + Sync,
+    S: Send + Sync,
+{
+}
+
+unsafe impl<K, V, S> Send for Shared<K, V, S>
+where
+    K: Eq + Hash + Send + Sync,
+    V: Send + Sync,
+    S: Send + Sync,
+{
+}
+
+/// The single writer of a left-right [`OverlayMap`].
+///
+/// All mutations are applied to a private copy of the map and only become visible to
+/// [`ReadHandle`]s once [`publish`](Self::publish) is called. Every key keeps the usual
+/// foreground/background semantics in both copies, because the writer replays exactly the
+/// same push, pull, swap and flip transitions onto each of them.
+///
+/// Keys and values must be `Clone`, since each mutation has to be applied twice.
+///
+/// # Example
+///
+/// ```
+/// use overlay_map::{OverlayMap, WriteHandle};
+///
+/// let mut writer = WriteHandle::new(OverlayMap::<&str, i32>::new());
+/// let reader = writer.reader();
+///
+/// writer.push("hp", 100);
+/// assert_eq!(reader.enter().fg(&"hp"), None); // not yet published
+///
+/// writer.publish();
+/// assert_eq!(reader.enter().fg(&"hp"), Some(&100));
+///
+/// writer.push("hp", 80);
+/// writer.publish();
+///
+/// let map = reader.enter();
+/// assert_eq!(map.fg(&"hp"), Some(&80));
+/// assert_eq!(map.bg(&"hp"), Some(&100));
+/// ```
+pub struct WriteHandle<K, V, S = DefaultHashBuilder>
+where
+    K: Eq + Hash,
+{
+    shared: Arc<Shared<K, V, S>>,
+    /// Mutations applied to the unpublished copy, to be replayed onto the other one.
+    log: Vec<Op<K, V>>,
+}
+
+impl<K, V, S> WriteHandle<K, V, S>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    S: BuildHasher + Default + Clone,
+{
+    /// Creates a writer from an existing map, which becomes the initially published state.
+    pub fn new(map: OverlayMap<K, V, S>) -> Self {
+        let copy = map.clone();
+        Self {
+            shared: Arc::new(Shared {
+                maps: [UnsafeCell::new(map), UnsafeCell::new(copy)],
+                read_index: AtomicUsize::new(0),
+                epochs: Mutex::new(Vec::new()),
+            }),
+            log: Vec::new(),
+        }
+    }
+
+    /// Creates a new [`ReadHandle`] observing the published copy of this map.
+    pub fn reader(&self) -> ReadHandle<K, V, S> {
+        ReadHandle::register(&self.shared)
+    }
+
+    /// Returns the writer's view of the map, including changes that have not been published.
+    pub fn pending(&self) -> &OverlayMap<K, V, S> {
+        unsafe { &*self.shared.maps[self.write_index()].get() }
+    }
+
+    /// Returns `true` if there are changes that have not been published yet.
+    pub fn has_pending(&self) -> bool {
+        !self.log.is_empty()
+    }
+
+    /// Push a value into the foreground layer of the unpublished copy.
+    ///
+    /// See [`OverlayMap::push`].
+    pub fn push(&mut self, key: K, value: V) -> bool {
+        let pushed = self.write_map().push(key.clone(), value.clone());
+        self.log.push(Op::Push(key, value));
+        pushed
+    }
+
+    /// Conditionally push a new value into the unpublished copy based on its current value.
+    ///
+    /// See [`OverlayMap::push_if`].
+    pub fn push_if<F>(&mut self, key: &K, predicate: F) -> bool
+    where
+        F: FnOnce(&V) -> Option<V>,
+    {
+        let Some(value) = self.pending().fg(key).and_then(predicate) else {
+            return false;
+        };
+        self.push(key.clone(), value)
+    }
+
+    /// Pull the foreground value for a key from the unpublished copy.
+    ///
+    /// See [`OverlayMap::pull`].
+    pub fn pull(&mut self, key: &K) -> Option<V> {
+        let pulled = self.write_map().pull(key)?;
+        self.log.push(Op::Pull(key.clone()));
+        Some(pulled)
+    }
+
+    /// Conditionally pull the foreground value for a key from the unpublished copy.
+    ///
+    /// See [`OverlayMap::pull_if`].
+    pub fn pull_if<F>(&mut self, key: &K, predicate: F) -> Option<V>
+    where
+        F: FnOnce(&V) -> bool,
+    {
+        let pulled = self.write_map().pull_if(key, predicate)?;
+        self.log.push(Op::Pull(key.clone()));
+        Some(pulled)
+    }
+
+    /// Swap a value into the foreground layer of the unpublished copy, returning the evicted
+    /// background value if present.
+    ///
+    /// See [`OverlayMap::swap`].
+    pub fn swap(&mut self, key: K, value: V) -> Option<V> {
+        let evicted = self.write_map().swap(key.clone(), value.clone());
+        self.log.push(Op::Swap(key, value));
+        evicted
+    }
+
+    /// Conditionally swap a new value into the unpublished copy based on its current value.
+    ///
+    /// See [`OverlayMap::swap_if`].
+    pub fn swap_if<F>(&mut self, key: &K, predicate: F) -> Option<V>
+    where
+        F: FnOnce(&V) -> Option<V>,
+    {
+        let value = self.pending().fg(key).and_then(predicate)?;
+        self.swap(key.clone(), value)
+    }
+
+    /// Flips the foreground and background values for the given key in the unpublished copy.
+    ///
+    /// See [`OverlayMap::flip`].
+    pub fn flip(&mut self, key: &K) {
+        self.write_map().flip(key);
+        self.log.push(Op::Flip(key.clone()));
+    }
+
+    /// Makes all pending changes visible to readers.
+    ///
+    /// The copies are swapped atomically, so readers either observe all of the changes made
+    /// since the last publish or none of them. This call blocks until every reader that was
+    /// still reading the previously published copy has dropped its [`ReadGuard`], and then
+    /// replays the pending changes onto that copy. Readers themselves never block.
+    pub fn publish(&mut self) {
+        if self.log.is_empty() {
+            return;
+        }
+
+        let stale = self.shared.read_index.load(Ordering::SeqCst);
+        self.shared.read_index.store(stale ^ 1, Ordering::SeqCst);
+        self.wait_for_readers();
+
+        let map = unsafe { &mut *self.shared.maps[stale].get() };
+        for op in self.log.drain(..) {
+            map.apply(op);
+        }
+    }
+}
+
+impl<K, V, S> WriteHandle<K, V, S>
+where
+    K: Eq + Hash,
+{
+    #[inline]
+    fn write_index(&self) -> usize {
+        self.shared.read_index.load(Ordering::SeqCst) ^ 1
+    }
+
+    #[inline]
+    fn write_map(&mut self) -> &mut OverlayMap<K, V, S> {
+        unsafe { &mut *self.shared.maps[self.write_index()].get() }
+    }
+
+    /// Blocks until no reader is inside the copy that was published before the last swap.
+    ///
+    /// A reader with an odd epoch is inside a read section. Any such reader may have loaded
+    /// the old read index, so we wait until its epoch moves on; readers entering afterwards
+    /// are guaranteed to observe the new index.
+    ///
+    /// The registry lock is only held while taking a snapshot of the epochs, so new readers
+    /// can register while we wait.
+    fn wait_for_readers(&self) {
+        let epochs: Vec<_> = {
+            let mut epochs = self.shared.epochs.lock().unwrap();
+            epochs.retain(|epoch| Arc::strong_count(epoch) > 1);
+            epochs.clone()
+        };
+
+        for epoch in &epochs {
+            let seen = epoch.load(Ordering::SeqCst);
+            if seen % 2 == 0 {
+                continue;
+            }
+            while epoch.load(Ordering::SeqCst) == seen {
+                thread::yield_now();
+            }
+        }
+    }
+}
+
+/// A handle for reading the published copy of a left-right [`OverlayMap`].
+///
+/// Reads never block and never wait for the writer. Each handle is meant to be owned by a
+/// single thread; clone it to give other threads their own handle.
+///
+/// Read sections may be nested: the handle stays inside the copy it first entered until its
+/// outermost [`ReadGuard`] is dropped.
+pub struct ReadHandle<K, V, S = DefaultHashBuilder>
+where
+    K: Eq + Hash,
+{
+    shared: Arc<Shared<K, V, S>>,
+    epoch: Arc<AtomicUsize>,
+    /// Number of live guards. Only the outermost enter and exit move the epoch, so that it
+    /// stays odd for as long as any guard of this handle is alive.
+    depth: Cell<usize>,
+    /// The copy entered by the outermost guard, reused by nested ones.
+    index: Cell<usize>,
+    _not_sync: PhantomData<Cell<()>>,
+}
+
+impl<K, V, S> ReadHandle<K, V, S>
+where
+    K: Eq + Hash,
+{
+    fn register(shared: &Arc<Shared<K, V, S>>) -> Self {
+        let epoch = Arc::new(AtomicUsize::new(0));
+        shared.epochs.lock().unwrap().push(epoch.clone());
+        Self {
+            shared: shared.clone(),
+            epoch,
+            depth: Cell::new(0),
+            index: Cell::new(0),
+            _not_sync: PhantomData,
+        }
+    }
+
+    /// Enters a read section, returning a guard that dereferences to the published map.
+    ///
+    /// The writer cannot reclaim this copy while the guard is alive, so keep read sections
+    /// short to avoid delaying [`WriteHandle::publish`].
+    pub fn enter(&self) -> ReadGuard<'_, K, V, S> {
+        let depth = self.depth.get();
+        if depth == 0 {
+            self.epoch.fetch_add(1, Ordering::SeqCst);
+            self.index
+                .set(self.shared.read_index.load(Ordering::SeqCst));
+        }
+        self.depth.set(depth + 1);
+        ReadGuard {
+            map: unsafe { &*self.shared.maps[self.index.get()].get() },
+            handle: self,
+        }
+    }
+}
+
+impl<K, V, S> Clone for ReadHandle<K, V, S>
+where
+    K: Eq + Hash,
+{
+    fn clone(&self) -> Self {
+        Self::register(&self.shared)
+    }
+}
+
+/// A read section over the published copy of a left-right [`OverlayMap`].
+///
+/// Dereferences to the [`OverlayMap`], so all of its read-only methods are available.
+pub struct ReadGuard<'a, K, V, S = DefaultHashBuilder>
+where
+    K: Eq + Hash,
+{
+    map: &'a OverlayMap<K, V, S>,
+    handle: &'a ReadHandle<K, V, S>,
+}
+
+impl<K, V, S> Deref for ReadGuard<'_, K, V, S>
+where
+    K: Eq + Hash,
+{
+    type Target = OverlayMap<K, V, S>;
+
+    fn deref(&self) -> &Self::Target {
+        self.map
+    }
+}
+
+impl<K, V, S> Drop for ReadGuard<'_, K, V, S>
+where
+    K: Eq + Hash,
+{
+    fn drop(&mut self) {
+        let depth = self.handle.depth.get() - 1;
+        self.handle.depth.set(depth);
+        if depth == 0 {
+            self.handle.epoch.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+
+    use super::*;
+
+    #[test]
+    fn publish_preserves_layers_in_both_copies() {
+        let mut writer = WriteHandle::new(OverlayMap::<&str, i32>::new());
+        let reader = writer.reader();
+
+        writer.push("a", 1);
+        writer.push("a", 2);
+        writer.publish();
+        writer.flip(&"a");
+        writer.publish();
+
+        let map = reader.enter();
+        assert_eq!(map.fg(&"a"), Some(&1));
+        assert_eq!(map.bg(&"a"), Some(&2));
+        assert_eq!(writer.pending(), &*map);
+    }
+
+    #[test]
+    fn publish_waits_for_every_nested_guard() {
+        use std::{sync::atomic::AtomicBool, time::Duration};
+
+        let mut writer = WriteHandle::new(OverlayMap::<u32, u32>::new());
+        let reader = writer.reader();
+        let published = Arc::new(AtomicBool::new(false));
+
+        let outer = reader.enter();
+        let inner = reader.enter();
+        let publisher = {
+            let published = published.clone();
+            thread::spawn(move || {
+                writer.push(0, 1);
+                writer.publish();
+                published.store(true, Ordering::SeqCst);
+            })
+        };
+
+        thread::sleep(Duration::from_millis(50));
+        assert!(!published.load(Ordering::SeqCst));
+        assert_eq!(inner.fg(&0), None);
+        drop(inner);
+
+        thread::sleep(Duration::from_millis(50));
+        assert!(!published.load(Ordering::SeqCst));
+        assert_eq!(outer.fg(&0), None);
+        drop(outer);
+
+        publisher.join().unwrap();
+        assert!(published.load(Ordering::SeqCst));
+        assert_eq!(reader.enter().fg(&0), Some(&1));
+    }
+
+    #[test]
+    fn nested_guards_stay_in_the_outer_copy() {
+        let mut writer = WriteHandle::new(OverlayMap::<u32, u32>::new());
+        let reader = writer.reader();
+        let shared = writer.shared.clone();
+
+        let outer = reader.enter();
+        let publisher = thread::spawn(move || {
+            writer.push(0, 1);
+            writer.publish();
+        });
+        while shared.read_index.load(Ordering::SeqCst) == 0 {
+            thread::yield_now();
+        }
+
+        let inner = reader.enter();
+        assert_eq!(inner.fg(&0), None);
+        assert!(ptr::eq(&*inner, &*outer));
+        drop(inner);
+        drop(outer);
+
+        publisher.join().unwrap();
+        assert_eq!(reader.enter().fg(&0), Some(&1));
+    }
+
+    #[test]
+    fn readers_on_other_threads_see_published_writes() {
+        let mut writer = WriteHandle::new(OverlayMap::<u32, u32>::new());
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let reader = writer.reader();
+                thread::spawn(move || {
+                    loop {
+                        let map = reader.enter();
+                        if let Some(&fg) = map.fg(&0) {
+                            assert_eq!(map.bg(&0), fg.checked_sub(1).as_ref());
+                            if fg == 100 {
+                                break;
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        for i in 0..=100 {
+            writer.push(0, i);
+            writer.publish();
+        }
+
+        for reader in readers {
+            reader.join().unwrap();
+        }
+    }
+}
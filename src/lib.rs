@@ -27,11 +27,64 @@
 //! ```
 
 use std::{
-    hash::{BuildHasher, Hash},
-    mem::MaybeUninit,
+    cmp::Ordering,
+    fmt,
+    hash::{BuildHasher, DefaultHasher, Hash, Hasher},
+    marker::PhantomData,
+    mem::{self, MaybeUninit},
+    ops::Index,
 };
 
-use hashbrown::{DefaultHashBuilder, HashMap, hash_map::RawEntryMut};
+use hashbrown::{DefaultHashBuilder, HashMap};
+
+pub use allocator_api2::alloc::{Allocator, Global};
+pub use hashbrown::TryReserveError;
+
+mod batch;
+mod btree;
+mod compact;
+mod expiry;
+mod generation;
+mod layered;
+mod layers;
+mod left_right;
+mod merge;
+mod observer;
+mod op;
+mod persist;
+mod persistent;
+mod recycle;
+mod stack;
+mod stats;
+mod storage;
+mod subscribe;
+mod undo;
+mod vec;
+
+pub use batch::BatchReport;
+pub use btree::OverlayBTreeMap;
+pub use compact::{CompactOverlay, Niche};
+pub use expiry::{Clock, ExpiringOverlayMap, ManualClock, MonotonicClock};
+pub use generation::GenerationalOverlayMap;
+pub use layered::LayeredMap;
+pub use layers::BgOnlyPolicy;
+pub use left_right::{ReadGuard, ReadHandle, WriteHandle};
+pub use merge::{MergePolicy, MergeReport, MergeResolution, MergeResolver};
+pub use observer::OverlayObserver;
+pub use op::{Op, OpResult, RecordingOverlayMap};
+pub use persist::{DurableOverlayMap, Persist};
+pub use persistent::{PersistentOverlayIter, PersistentOverlayMap};
+pub use recycle::RecyclePool;
+pub use stack::{OverlayStack, OverlayStackIntoIter, OverlayStackMap};
+pub use stats::{HeapSize, HeapUsage, Occupancy, OverlayStats};
+pub use storage::{InsertionOrdered, OverlayStorage, Upsert};
+pub use subscribe::{BlockingIter, Change, ChangeKind, Subscription, WatchedOverlayMap};
+pub use undo::UndoOverlayMap;
+pub use vec::OverlayVec;
+
+/// An [`OverlayMap`] that iterates in insertion order, backed by [`InsertionOrdered`].
+pub type InsertionOrderedOverlayMap<K, V, S = DefaultHashBuilder> =
+    OverlayMap<K, V, S, Global, InsertionOrdered<K, V, S>>;
 
 /// A two-layered map where each key holds a current (foreground) and optional historical (background) value.
 ///
@@ -53,6 +106,17 @@ use hashbrown::{DefaultHashBuilder, HashMap, hash_map::RawEntryMut};
 /// - Zero-cost foreground/background transitions
 /// - Map keys only retained when a value is present
 ///
+/// # Storage
+///
+/// Entries live in a storage backend implementing [`OverlayStorage`], chosen with the `M`
+/// type parameter. It defaults to hashbrown's `HashMap`; [`OverlayBTreeMap`] wraps a map
+/// stored in a `BTreeMap` to keep keys sorted, and [`InsertionOrderedOverlayMap`] iterates in
+/// insertion order. The backend only affects lookup cost and iteration order — every method
+/// behaves the same on all of them.
+///
+/// The `A` type parameter selects the [`Allocator`] used by the default hash backend; see
+/// [`new_in`](Self::new_in).
+///
 /// # Example
 ///
 /// ```
@@ -79,27 +143,34 @@ use hashbrown::{DefaultHashBuilder, HashMap, hash_map::RawEntryMut};
 /// assert_eq!(pulled, Some(1));
 /// assert_eq!(map.fg(&"player"), None);
 /// ```
-#[derive(Debug, Default)]
-pub struct OverlayMap<K, V, S = DefaultHashBuilder>
-where
-    K: Eq + Hash,
-{
-    map: HashMap<K, Overlay<V>, S>,
+pub struct OverlayMap<
+    K,
+    V,
+    S = DefaultHashBuilder,
+    A = Global,
+    M = HashMap<K, Overlay<V>, S, A>,
+    O = (),
+> {
+    map: M,
+    observer: O,
+    marker: PhantomData<(K, V, S, A)>,
 }
 
 /// `OverlayMap` is `Sync` because all access to internal state is gated through `&self`
 /// for read-only operations, and mutation requires exclusive access via `&mut self`.
 ///
-/// - The underlying `HashMap` is not `Sync` by default, but we do not expose any interior
-///   mutability or unsynchronized shared mutation.
+/// - The underlying storage is only required to be `Sync` itself; we do not expose any
+///   interior mutability or unsynchronized shared mutation.
 /// - All mutation methods (e.g., `push`, `pull`, `swap`) require `&mut self`.
 /// - Shared access via `&OverlayMap` only allows read-only operations like `fg`, `bg`, `len`,
 ///   and `is_empty`, which do not mutate internal state.
 /// - `Overlay<T>` is also safe for concurrent read access and does not use interior mutability.
-unsafe impl<K, V, S> Sync for OverlayMap<K, V, S>
+unsafe impl<K, V, S, A, M, O> Sync for OverlayMap<K, V, S, A, M, O>
 where
-    K: Eq + Hash + Sync,
+    M: Sync,
+    O: Sync,
     S: Sync,
+    A: Sync,
 {
 }
 
@@ -125,17 +196,130 @@ where
 
     /// Creates an empty `OverlayMap` that will use the given hasher.
     pub fn with_hasher(hasher: S) -> Self {
-        Self {
-            map: HashMap::with_hasher(hasher),
-        }
+        Self::from_storage(HashMap::with_hasher(hasher))
     }
 
     /// Creates an empty `OverlayMap` with the specified capacity and hasher.
     pub fn with_capacity_and_hasher(capacity: usize, hasher: S) -> Self {
+        Self::from_storage(HashMap::with_capacity_and_hasher(capacity, hasher))
+    }
+}
+
+impl<K, V, S, A> OverlayMap<K, V, S, A>
+where
+    K: Eq + Hash,
+    S: BuildHasher + Default,
+    A: Allocator,
+{
+    /// Creates an empty `OverlayMap` that allocates its entries in `alloc`.
+    ///
+    /// This places all overlay state — keys and both layers of every value — in the given
+    /// allocator, such as a bump arena or a per-request pool.
+    ///
+    /// ```
+    /// use overlay_map::{Global, OverlayMap};
+    ///
+    /// let mut map = OverlayMap::<_, _>::with_capacity_in(8, Global);
+    /// map.push("a", 1);
+    /// map.push("a", 2);
+    /// assert_eq!(map.bg(&"a"), Some(&1));
+    /// assert!(map.capacity() >= 8);
+    /// ```
+    pub fn new_in(alloc: A) -> Self {
+        Self::with_hasher_in(S::default(), alloc)
+    }
+
+    /// Creates an empty `OverlayMap` with the specified capacity, allocating in `alloc`.
+    pub fn with_capacity_in(capacity: usize, alloc: A) -> Self {
+        Self::with_capacity_and_hasher_in(capacity, S::default(), alloc)
+    }
+
+    /// Creates an empty `OverlayMap` with the given hasher, allocating in `alloc`.
+    pub fn with_hasher_in(hasher: S, alloc: A) -> Self {
+        Self::from_storage(HashMap::with_hasher_in(hasher, alloc))
+    }
+
+    /// Creates an empty `OverlayMap` with the specified capacity and hasher, allocating in
+    /// `alloc`.
+    pub fn with_capacity_and_hasher_in(capacity: usize, hasher: S, alloc: A) -> Self {
+        Self::from_storage(HashMap::with_capacity_and_hasher_in(
+            capacity, hasher, alloc,
+        ))
+    }
+
+    /// Returns a reference to the map's allocator.
+    pub fn allocator(&self) -> &A {
+        self.map.allocator()
+    }
+}
+
+impl<K, V, S, A, M> OverlayMap<K, V, S, A, M>
+where
+    M: OverlayStorage<K, V>,
+{
+    /// Creates an `OverlayMap` on top of the given storage backend.
+    ///
+    /// Entries already in `storage` that have no foreground value are dropped, since every
+    /// key in the map must have one.
+    ///
+    /// ```
+    /// use overlay_map::{InsertionOrdered, InsertionOrderedOverlayMap};
+    ///
+    /// let mut map = InsertionOrderedOverlayMap::from_storage(InsertionOrdered::new());
+    /// map.push("b", 1);
+    /// map.push("a", 2);
+    ///
+    /// let keys: Vec<_> = map.iter().map(|(k, _)| *k).collect();
+    /// assert_eq!(keys, vec!["b", "a"]);
+    /// ```
+    pub fn from_storage(mut storage: M) -> Self {
+        storage.retain(|_, entry| entry.fg().is_some());
         Self {
-            map: HashMap::with_capacity_and_hasher(capacity, hasher),
+            map: storage,
+            observer: (),
+            marker: PhantomData,
         }
     }
+}
+
+impl<K, V, S, A, M, O> OverlayMap<K, V, S, A, M, O>
+where
+    M: OverlayStorage<K, V>,
+    O: OverlayObserver<K, V>,
+{
+    /// Replaces the map's observer, which is called on every subsequent state transition.
+    ///
+    /// See [`OverlayObserver`] for the hooks and when they run.
+    pub fn with_observer<O2>(self, observer: O2) -> OverlayMap<K, V, S, A, M, O2>
+    where
+        O2: OverlayObserver<K, V>,
+    {
+        OverlayMap {
+            map: self.map,
+            observer,
+            marker: PhantomData,
+        }
+    }
+
+    /// Returns a reference to the map's observer.
+    pub fn observer(&self) -> &O {
+        &self.observer
+    }
+
+    /// Returns a mutable reference to the map's observer.
+    pub fn observer_mut(&mut self) -> &mut O {
+        &mut self.observer
+    }
+
+    /// Returns the storage backend holding the map's entries.
+    pub fn storage(&self) -> &M {
+        &self.map
+    }
+
+    /// Consumes the map, returning its storage backend.
+    pub fn into_storage(self) -> M {
+        self.map
+    }
 
     /// Number of unique keys in the map.
     pub fn len(&self) -> usize {
@@ -147,6 +331,52 @@ where
         self.map.is_empty()
     }
 
+    /// Number of keys the map can hold without reallocating.
+    pub fn capacity(&self) -> usize {
+        self.map.capacity()
+    }
+
+    /// Reserves capacity for at least `additional` more keys.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the new capacity overflows `usize`, and aborts if the allocation fails.
+    /// Use [`try_reserve`](Self::try_reserve) to handle these cases instead.
+    pub fn reserve(&mut self, additional: usize) {
+        self.map.reserve(additional);
+    }
+
+    /// Tries to reserve capacity for at least `additional` more keys.
+    ///
+    /// Returns an error instead of panicking or aborting if the capacity overflows or the
+    /// allocator reports a failure. The map is left unchanged on error.
+    ///
+    /// ```
+    /// use overlay_map::{OverlayMap, TryReserveError};
+    ///
+    /// let mut map = OverlayMap::<u64, u64>::new();
+    /// map.try_reserve(16).expect("small reservation");
+    /// assert!(map.capacity() >= 16);
+    ///
+    /// let err = map.try_reserve(usize::MAX).unwrap_err();
+    /// assert_eq!(err, TryReserveError::CapacityOverflow);
+    /// ```
+    pub fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        self.map.try_reserve(additional)
+    }
+
+    /// Shrinks the capacity of the map as much as possible.
+    pub fn shrink_to_fit(&mut self) {
+        self.map.shrink_to_fit();
+    }
+
+    /// Shrinks the capacity of the map to at least `min_capacity` keys.
+    ///
+    /// The capacity never drops below the number of keys in the map.
+    pub fn shrink_to(&mut self, min_capacity: usize) {
+        self.map.shrink_to(min_capacity);
+    }
+
     /// Get an immutable reference to the value associated with the key.
     ///
     /// Returns `None` if the key was not found in the map.
@@ -174,16 +404,92 @@ where
     /// background now definitely exists).
     #[inline]
     pub fn push(&mut self, key: K, value: V) -> bool {
-        match self.map.raw_entry_mut().from_key(&key) {
-            RawEntryMut::Occupied(mut occupied) => {
-                occupied.get_mut().push(value);
+        let observer = &mut self.observer;
+        self.map.upsert(key, value, |key, slot| match slot {
+            Upsert::Occupied(entry, value) => {
+                push_observed(observer, key, entry, value);
                 true
             }
-            RawEntryMut::Vacant(vacant) => {
-                vacant.insert(key, Overlay::new_fg(value));
+            Upsert::Vacant(value) => {
+                observer.on_insert(key, value);
                 false
             }
+        })
+    }
+
+    /// Fallible version of [`push`](Self::push) that returns an error instead of aborting if
+    /// a new key cannot be allocated.
+    ///
+    /// Pushing onto an existing key never allocates. On error the map is left unchanged and
+    /// `value` is dropped.
+    ///
+    /// ```
+    /// use overlay_map::OverlayMap;
+    ///
+    /// let mut map = OverlayMap::new();
+    /// assert_eq!(map.try_push("a", 1), Ok(false));
+    /// assert_eq!(map.try_push("a", 2), Ok(true));
+    /// assert_eq!(map.bg(&"a"), Some(&1));
+    /// ```
+    pub fn try_push(&mut self, key: K, value: V) -> Result<bool, TryReserveError> {
+        if let Some(entry) = self.map.get_mut(&key) {
+            push_observed(&mut self.observer, &key, entry, value);
+            return Ok(true);
+        }
+        self.map.try_reserve(1)?;
+        self.observer.on_insert(&key, &value);
+        self.map.insert(key, Overlay::new_fg(value));
+        Ok(false)
+    }
+
+    /// Push a value built by `f` into the foreground layer, reusing a spare value if one is
+    /// available.
+    ///
+    /// `f` receives the value that would otherwise be dropped or freed: the key's current
+    /// background if it has one, and otherwise whatever the observer's
+    /// [`recycle`](OverlayObserver::recycle) hook supplies — such as a value evicted earlier
+    /// and kept in a [`RecyclePool`]. This lets large buffers be refilled in place instead
+    /// of being freed and reallocated on every push.
+    ///
+    /// Returns `true` if there was already a foreground value.
+    ///
+    /// ```
+    /// use overlay_map::{OverlayMap, RecyclePool};
+    ///
+    /// let mut frames = OverlayMap::new().with_observer(RecyclePool::new(4));
+    /// let fill = |byte: u8| move |recycled: Option<Vec<u8>>| {
+    ///     let mut buf = recycled.unwrap_or_else(|| Vec::with_capacity(1024));
+    ///     buf.clear();
+    ///     buf.resize(1024, byte);
+    ///     buf
+    /// };
+    ///
+    /// frames.push_with("camera", fill(1));
+    /// frames.push_with("camera", fill(2));
+    /// let first = frames.bg(&"camera").unwrap().as_ptr();
+    ///
+    /// // The background buffer is refilled instead of freed.
+    /// frames.push_with("camera", fill(3));
+    /// assert_eq!(frames.fg(&"camera").unwrap().as_ptr(), first);
+    /// assert_eq!(frames.fg(&"camera").unwrap()[0], 3);
+    /// ```
+    pub fn push_with<F>(&mut self, key: K, f: F) -> bool
+    where
+        F: FnOnce(Option<V>) -> V,
+    {
+        if let Some(entry) = self.map.get_mut(&key) {
+            let recycled = match entry.take_bg() {
+                Some(bg) => Some(bg),
+                None => self.observer.recycle(&key),
+            };
+            entry.push(f(recycled));
+            self.observer.on_push(&key, entry.fg_unchecked());
+            return true;
         }
+        let value = f(self.observer.recycle(&key));
+        self.observer.on_insert(&key, &value);
+        self.map.insert(key, Overlay::new_fg(value));
+        false
     }
 
     /// Conditionally push a new value into the foreground based on the current
@@ -206,7 +512,7 @@ where
 
         match predicate(entry.fg_unchecked()) {
             Some(new) => {
-                entry.push(new);
+                push_observed(&mut self.observer, key, entry, new);
                 true
             }
             None => false,
@@ -252,17 +558,9 @@ where
     /// ```
     #[inline]
     pub fn pull(&mut self, key: &K) -> Option<V> {
-        match self.map.raw_entry_mut().from_key(key) {
-            RawEntryMut::Occupied(mut occupied) => {
-                let entry = occupied.get_mut();
-                let evicted = entry.pull_unchecked();
-                if entry.is_empty() {
-                    occupied.remove();
-                }
-                Some(evicted)
-            }
-            RawEntryMut::Vacant(_) => None,
-        }
+        let observer = &mut self.observer;
+        self.map
+            .update(key, |entry| Some(pull_observed(observer, key, entry)))
     }
 
     /// Conditionally pulls the foreground value for a key, promoting the background if present.
@@ -312,21 +610,14 @@ where
     where
         F: FnOnce(&V) -> bool,
     {
-        match self.map.raw_entry_mut().from_key(key) {
-            RawEntryMut::Occupied(mut occupied) => {
-                let entry = occupied.get_mut();
-                if predicate(entry.fg_unchecked()) {
-                    let evicted = entry.pull_unchecked();
-                    if entry.is_empty() {
-                        occupied.remove();
-                    }
-                    Some(evicted)
-                } else {
-                    None
-                }
+        let observer = &mut self.observer;
+        self.map.update(key, |entry| {
+            if predicate(entry.fg_unchecked()) {
+                Some(pull_observed(observer, key, entry))
+            } else {
+                None
             }
-            RawEntryMut::Vacant(_) => None,
-        }
+        })
     }
 
     /// Swap a value into the foreground layer, preserving the previous value in
@@ -337,13 +628,35 @@ where
     /// cloning occurs. The old background value is returned if present.
     #[inline]
     pub fn swap(&mut self, key: K, value: V) -> Option<V> {
-        match self.map.raw_entry_mut().from_key(&key) {
-            RawEntryMut::Occupied(mut occupied) => occupied.get_mut().swap(value),
-            RawEntryMut::Vacant(vacant) => {
-                vacant.insert(key, Overlay::new_fg(value));
+        let observer = &mut self.observer;
+        self.map.upsert(key, value, |key, slot| match slot {
+            Upsert::Occupied(entry, value) => {
+                let evicted = entry.swap(value);
+                observer.on_swap(key, entry.fg_unchecked());
+                evicted
+            }
+            Upsert::Vacant(value) => {
+                observer.on_insert(key, value);
                 None
             }
+        })
+    }
+
+    /// Fallible version of [`swap`](Self::swap) that returns an error instead of aborting if
+    /// a new key cannot be allocated.
+    ///
+    /// Swapping into an existing key never allocates. On error the map is left unchanged and
+    /// `value` is dropped.
+    pub fn try_swap(&mut self, key: K, value: V) -> Result<Option<V>, TryReserveError> {
+        if let Some(entry) = self.map.get_mut(&key) {
+            let evicted = entry.swap(value);
+            self.observer.on_swap(&key, entry.fg_unchecked());
+            return Ok(evicted);
         }
+        self.map.try_reserve(1)?;
+        self.observer.on_insert(&key, &value);
+        self.map.insert(key, Overlay::new_fg(value));
+        Ok(None)
     }
 
     /// Conditionally swap a new value into the foreground based on the current
@@ -360,10 +673,10 @@ where
         F: FnOnce(&V) -> Option<V>,
     {
         let entry = self.map.get_mut(key)?;
-        match predicate(entry.fg_unchecked()) {
-            Some(new) => entry.swap(new),
-            None => None,
-        }
+        let new = predicate(entry.fg_unchecked())?;
+        let evicted = entry.swap(new);
+        self.observer.on_swap(key, entry.fg_unchecked());
+        evicted
     }
 
     /// Flips the foreground and background values for the given key, if present.
@@ -396,10 +709,163 @@ where
     /// ```
     pub fn flip(&mut self, key: &K) {
         if let Some(entry) = self.map.get_mut(key) {
-            entry.flip();
+            if entry.is_full() {
+                entry.flip_unchecked();
+                self.observer.on_flip(key);
+            }
         }
     }
 
+    /// Removes a key from the map, returning its entry with both layers if it was present.
+    ///
+    /// ```
+    /// use overlay_map::OverlayMap;
+    ///
+    /// let mut map = OverlayMap::new();
+    /// map.push("a", 1);
+    /// map.push("a", 2);
+    ///
+    /// let entry = map.remove(&"a").unwrap();
+    /// assert_eq!((entry.fg(), entry.bg()), (Some(&2), Some(&1)));
+    /// assert!(map.is_empty());
+    /// ```
+    pub fn remove(&mut self, key: &K) -> Option<Overlay<V>> {
+        let entry = self.map.remove(key)?;
+        self.observer.on_remove(key);
+        Some(entry)
+    }
+
+    /// Replaces the foreground value for a key in place, returning the previous foreground.
+    ///
+    /// Unlike [`push`](Self::push), the background is left untouched. If the key is absent,
+    /// it is inserted with `value` as its foreground and `None` is returned.
+    ///
+    /// ```
+    /// use overlay_map::OverlayMap;
+    ///
+    /// let mut map = OverlayMap::new();
+    /// map.push("a", 1);
+    /// map.push("a", 2);
+    ///
+    /// assert_eq!(map.replace_fg("a", 3), Some(2));
+    /// assert_eq!((map.fg(&"a"), map.bg(&"a")), (Some(&3), Some(&1)));
+    /// assert_eq!(map.replace_fg("b", 4), None);
+    /// ```
+    pub fn replace_fg(&mut self, key: K, value: V) -> Option<V> {
+        let observer = &mut self.observer;
+        self.map.upsert(key, value, |key, slot| match slot {
+            Upsert::Occupied(entry, value) => {
+                let replaced = entry.replace_fg(value);
+                observer.on_replace_fg(key, entry.fg_unchecked());
+                replaced
+            }
+            Upsert::Vacant(value) => {
+                observer.on_insert(key, value);
+                None
+            }
+        })
+    }
+
+    /// Takes the foreground value for a key out of the map, removing the key.
+    ///
+    /// Every key in the map must have a foreground, so the entry goes with it and any
+    /// background is dropped. Use [`pull`](Self::pull) to promote the background instead, or
+    /// [`remove`](Self::remove) to keep both values.
+    ///
+    /// ```
+    /// use overlay_map::OverlayMap;
+    ///
+    /// let mut map = OverlayMap::new();
+    /// map.push("a", 1);
+    /// map.push("a", 2);
+    ///
+    /// assert_eq!(map.take_fg(&"a"), Some(2));
+    /// assert!(map.is_empty());
+    /// ```
+    pub fn take_fg(&mut self, key: &K) -> Option<V> {
+        let mut entry = self.map.remove(key)?;
+        let fg = entry.take_fg();
+        if let Some(value) = &fg {
+            self.observer.on_pull(key, value);
+        }
+        if let Some(bg) = entry.take_bg() {
+            self.observer.on_evict_bg(key, bg);
+        }
+        self.observer.on_remove(key);
+        fg
+    }
+
+    /// Takes the background value for a key out, leaving its foreground in place.
+    ///
+    /// ```
+    /// use overlay_map::OverlayMap;
+    ///
+    /// let mut map = OverlayMap::new();
+    /// map.push("a", 1);
+    /// map.push("a", 2);
+    ///
+    /// assert_eq!(map.take_bg(&"a"), Some(1));
+    /// assert_eq!((map.fg(&"a"), map.bg(&"a")), (Some(&2), None));
+    /// ```
+    pub fn take_bg(&mut self, key: &K) -> Option<V> {
+        let bg = self.map.get_mut(key)?.take_bg()?;
+        self.observer.on_take_bg(key, &bg);
+        Some(bg)
+    }
+
+    /// Sets the background value under a key's existing foreground, returning the previous
+    /// background if present.
+    ///
+    /// A background can't be stored without a foreground, so if the key is absent the map is
+    /// left unchanged and `value` is handed back as the error.
+    ///
+    /// ```
+    /// use overlay_map::OverlayMap;
+    ///
+    /// let mut map = OverlayMap::new();
+    /// map.push("a", 1);
+    ///
+    /// assert_eq!(map.set_bg(&"a", 0), Ok(None));
+    /// assert_eq!((map.fg(&"a"), map.bg(&"a")), (Some(&1), Some(&0)));
+    /// assert_eq!(map.set_bg(&"b", 0), Err(0));
+    /// ```
+    pub fn set_bg(&mut self, key: &K, value: V) -> Result<Option<V>, V> {
+        let Some(entry) = self.map.get_mut(key) else {
+            return Err(value);
+        };
+        let replaced = entry.set_bg(value);
+        if let Some(replaced) = &replaced {
+            self.observer.on_take_bg(key, replaced);
+        }
+        self.observer.on_set_bg(key, entry.bg_unchecked());
+        Ok(replaced)
+    }
+
+    /// Returns a mutable reference to the foreground value for a key, first inserting the
+    /// result of `f` if the key is absent.
+    ///
+    /// ```
+    /// use overlay_map::OverlayMap;
+    ///
+    /// let mut map = OverlayMap::new();
+    /// *map.get_or_push_with("hits", || 0) += 1;
+    /// *map.get_or_push_with("hits", || 0) += 1;
+    /// assert_eq!(map.fg(&"hits"), Some(&2));
+    /// ```
+    pub fn get_or_push_with<F>(&mut self, key: K, f: F) -> &mut V
+    where
+        F: FnOnce() -> V,
+    {
+        let observer = &mut self.observer;
+        self.map
+            .get_or_insert_with(key, |key| {
+                let value = f();
+                observer.on_insert(key, &value);
+                Overlay::new_fg(value)
+            })
+            .fg_unchecked_mut()
+    }
+
     /// Extends the map with a sequence of key-value pairs, counting foreground replacements.
     ///
     /// Each `(K, V)` pair is pushed into the foreground. If a key already exists,
@@ -409,7 +875,10 @@ where
     /// This method returns the number of keys that were already present — i.e., how many
     /// pushes replaced an existing foreground value.
     ///
-    /// No cloning or heap allocation is performed beyond what's necessary for the `HashMap`.
+    /// No cloning or heap allocation is performed beyond what's necessary for the storage,
+    /// which reserves room for the iterator's size hint up front. For large batches on the
+    /// default storage, [`push_batch`](Self::push_batch) and
+    /// [`extend_reserve`](Self::extend_reserve) are faster.
     ///
     /// # Example
     /// ```
@@ -429,50 +898,121 @@ where
     where
         I: IntoIterator<Item = (K, V)>,
     {
+        let iter = iter.into_iter();
+        self.reserve_for_extend(iter.size_hint().0);
         let mut replaced = 0;
         for (key, val) in iter {
             replaced += self.push(key, val) as usize;
         }
         replaced
     }
+
+    /// Reserves room for an extension by `hint` pairs, assuming about half of them replace
+    /// existing keys unless the map is empty, as hashbrown's own `Extend` does.
+    fn reserve_for_extend(&mut self, hint: usize) {
+        let additional = if self.map.is_empty() {
+            hint
+        } else {
+            hint.div_ceil(2)
+        };
+        self.map.reserve(additional);
+    }
+
+    /// Get an iterator over the entries of the map, in the storage backend's order.
+    ///
+    /// Each key is yielded together with its [`Overlay<V>`], so both layers are visible.
+    pub fn iter(&self) -> M::Iter<'_> {
+        self.map.iter()
+    }
+}
+
+impl<K, V, S, A, M, O> fmt::Debug for OverlayMap<K, V, S, A, M, O>
+where
+    M: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OverlayMap")
+            .field("map", &self.map)
+            .finish()
+    }
+}
+
+impl<K, V, S, A, M, O> Default for OverlayMap<K, V, S, A, M, O>
+where
+    M: Default,
+    O: Default,
+{
+    fn default() -> Self {
+        Self {
+            map: M::default(),
+            observer: O::default(),
+            marker: PhantomData,
+        }
+    }
 }
 
-impl<K, V, S> Clone for OverlayMap<K, V, S>
+impl<K, V, S, A, M, O> Clone for OverlayMap<K, V, S, A, M, O>
 where
-    K: Clone + Eq + Hash,
-    V: Clone,
-    S: Clone + BuildHasher,
+    M: Clone,
+    O: Clone,
 {
     fn clone(&self) -> Self {
         Self {
             map: self.map.clone(),
+            observer: self.observer.clone(),
+            marker: PhantomData,
         }
     }
 }
 
-impl<K, V, S> PartialEq for OverlayMap<K, V, S>
+impl<K, V, S, A, M, O> PartialEq for OverlayMap<K, V, S, A, M, O>
 where
-    K: Eq + Hash,
-    V: PartialEq,
-    S: BuildHasher,
+    M: PartialEq,
 {
+    /// Compares the entries of both maps; observers are not compared.
     fn eq(&self, other: &Self) -> bool {
         self.map == other.map
     }
 }
 
-impl<K, V, S> Eq for OverlayMap<K, V, S>
+impl<K, V, S, A, M, O> Eq for OverlayMap<K, V, S, A, M, O> where M: Eq {}
+
+impl<K, V, S, A, M, O> Hash for OverlayMap<K, V, S, A, M, O>
 where
-    K: Eq + Hash,
-    V: Eq,
-    S: BuildHasher,
+    K: Hash,
+    V: Hash,
+    M: OverlayStorage<K, V>,
 {
+    /// Hashes the entries of the map independently of their iteration order, so equal maps
+    /// hash equally whatever order their entries were inserted in; observers are not hashed.
+    ///
+    /// ```
+    /// use std::hash::{BuildHasher, RandomState};
+    ///
+    /// use overlay_map::OverlayMap;
+    ///
+    /// let a = OverlayMap::from([("a", 1), ("b", 2)]);
+    /// let b = OverlayMap::from([("b", 2), ("a", 1)]);
+    /// let state = RandomState::new();
+    /// assert_eq!(state.hash_one(&a), state.hash_one(&b));
+    /// ```
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // Each entry is hashed on its own with a fixed-key hasher and the results are summed,
+        // which commutes; the sum is then fed to `state` along with the length.
+        let sum = self.map.iter().fold(0u64, |sum, entry| {
+            let mut hasher = DefaultHasher::new();
+            entry.hash(&mut hasher);
+            sum.wrapping_add(hasher.finish())
+        });
+        state.write_usize(self.map.len());
+        state.write_u64(sum);
+    }
 }
 
-impl<K, V, S> Extend<(K, V)> for OverlayMap<K, V, S>
+impl<K, V, S, A, M, O> Extend<(K, V)> for OverlayMap<K, V, S, A, M, O>
 where
-    K: Eq + Hash,
-    S: BuildHasher + Default,
+    M: OverlayStorage<K, V>,
+    O: OverlayObserver<K, V>,
 {
     /// Inserts each `(K, V)` pair into the map by pushing the value into the foreground layer.
     ///
@@ -481,7 +1021,8 @@ where
     /// new value becomes the foreground. If the key is new, it is inserted.
     ///
     /// This implementation does **not** return any count of replaced entries — if you need that,
-    /// use [`extend_count`](Self::extend_count) instead.
+    /// use [`extend_count`](Self::extend_count) instead, or
+    /// [`extend_reserve`](Self::extend_reserve) for counts of both inserted and replaced keys.
     ///
     /// # Example
     /// ```
@@ -494,25 +1035,245 @@ where
     /// assert_eq!(map.fg(&"y"), Some(&2));
     /// ```
     fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        let iter = iter.into_iter();
+        self.reserve_for_extend(iter.size_hint().0);
         for (k, v) in iter {
             self.push(k, v);
         }
     }
 }
 
-impl<K, V, S> IntoIterator for OverlayMap<K, V, S>
+impl<K, V, S, A, M, O> IntoIterator for OverlayMap<K, V, S, A, M, O>
 where
-    K: Eq + Hash,
-    S: BuildHasher,
+    M: OverlayStorage<K, V>,
 {
     type Item = (K, Overlay<V>);
-    type IntoIter = hashbrown::hash_map::IntoIter<K, Overlay<V>>;
+    type IntoIter = M::IntoIter;
 
     fn into_iter(self) -> Self::IntoIter {
         self.map.into_iter()
     }
 }
 
+impl<'a, K, V, S, A, M, O> IntoIterator for &'a OverlayMap<K, V, S, A, M, O>
+where
+    M: OverlayStorage<K, V>,
+{
+    type Item = (&'a K, &'a Overlay<V>);
+    type IntoIter = M::Iter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.map.iter()
+    }
+}
+
+impl<K, V, S, A, M, O> Extend<(K, Overlay<V>)> for OverlayMap<K, V, S, A, M, O>
+where
+    M: OverlayStorage<K, V>,
+    O: OverlayObserver<K, V>,
+{
+    /// Inserts each `(K, Overlay<V>)` pair into the map, replacing any existing entry wholesale.
+    ///
+    /// Overlays without a foreground value are skipped, since every key in the map must have
+    /// one. This accepts the output of the consuming [`IntoIterator`] implementation, so entries
+    /// can be moved between maps with both layers intact.
+    ///
+    /// # Example
+    /// ```
+    /// use overlay_map::{Overlay, OverlayMap};
+    ///
+    /// let mut map = OverlayMap::new();
+    /// map.push("x", 1);
+    /// map.extend([("x", Overlay::new_both(10, 9)), ("y", Overlay::new_empty())]);
+    ///
+    /// assert_eq!(map.fg(&"x"), Some(&10));
+    /// assert_eq!(map.bg(&"x"), Some(&9));
+    /// assert_eq!(map.fg(&"y"), None);
+    /// ```
+    fn extend<I: IntoIterator<Item = (K, Overlay<V>)>>(&mut self, iter: I) {
+        for (key, overlay) in iter {
+            if overlay.fg().is_none() {
+                continue;
+            }
+            match self.map.get_mut(&key) {
+                Some(entry) => {
+                    let replaced = mem::replace(entry, overlay);
+                    for value in replaced {
+                        self.observer.on_evict_bg(&key, value);
+                    }
+                    self.observer.on_push(&key, entry.fg_unchecked());
+                }
+                None => {
+                    self.observer.on_insert(&key, overlay.fg_unchecked());
+                    self.map.insert(key, overlay);
+                }
+            }
+        }
+    }
+}
+
+impl<K, V, S, A, M, O> FromIterator<(K, V)> for OverlayMap<K, V, S, A, M, O>
+where
+    M: OverlayStorage<K, V> + Default,
+    O: OverlayObserver<K, V> + Default,
+{
+    /// Creates a map by pushing each `(K, V)` pair in order.
+    ///
+    /// Repeated keys behave exactly like repeated pushes: the earlier value is moved to the
+    /// background.
+    ///
+    /// # Example
+    /// ```
+    /// use overlay_map::OverlayMap;
+    ///
+    /// let map: OverlayMap<_, _> = [("a", 1), ("b", 2), ("a", 3)].into_iter().collect();
+    ///
+    /// assert_eq!(map.fg(&"a"), Some(&3));
+    /// assert_eq!(map.bg(&"a"), Some(&1));
+    /// assert_eq!(map.fg(&"b"), Some(&2));
+    /// ```
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let iter = iter.into_iter();
+        let mut map = Self::default();
+        map.map.reserve(iter.size_hint().0);
+        map.extend(iter);
+        map
+    }
+}
+
+impl<K, V, S, A, M, O> FromIterator<(K, Overlay<V>)> for OverlayMap<K, V, S, A, M, O>
+where
+    M: OverlayStorage<K, V> + Default,
+    O: OverlayObserver<K, V> + Default,
+{
+    /// Creates a map from `(K, Overlay<V>)` pairs, such as the output of consuming another
+    /// map with [`IntoIterator`].
+    ///
+    /// # Example
+    /// ```
+    /// use overlay_map::OverlayMap;
+    ///
+    /// let mut map = OverlayMap::new();
+    /// map.push("a", 1);
+    /// map.push("a", 2);
+    ///
+    /// let round_trip: OverlayMap<_, _> = map.clone().into_iter().collect();
+    /// assert_eq!(round_trip, map);
+    /// ```
+    fn from_iter<I: IntoIterator<Item = (K, Overlay<V>)>>(iter: I) -> Self {
+        let iter = iter.into_iter();
+        let mut map = Self::default();
+        map.map.reserve(iter.size_hint().0);
+        map.extend(iter);
+        map
+    }
+}
+
+impl<K, V, S> From<HashMap<K, V, S>> for OverlayMap<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher + Clone,
+{
+    /// Creates a map whose foreground layer is the given `HashMap`, with no backgrounds.
+    ///
+    /// The new map uses a clone of the source map's hasher.
+    ///
+    /// # Example
+    /// ```
+    /// use hashbrown::HashMap;
+    /// use overlay_map::OverlayMap;
+    ///
+    /// let map = OverlayMap::from(HashMap::from([("a", 1)]));
+    /// assert_eq!(map.fg(&"a"), Some(&1));
+    /// assert_eq!(map.bg(&"a"), None);
+    /// ```
+    fn from(map: HashMap<K, V, S>) -> Self {
+        let mut overlay = Self::from_storage(HashMap::with_capacity_and_hasher(
+            map.len(),
+            map.hasher().clone(),
+        ));
+        for (k, v) in map {
+            overlay.map.insert(k, Overlay::new_fg(v));
+        }
+        overlay
+    }
+}
+
+impl<K, V, const N: usize> From<[(K, V); N]> for OverlayMap<K, V, DefaultHashBuilder>
+where
+    K: Eq + Hash,
+{
+    /// Creates a map by pushing each `(K, V)` pair of the array in order.
+    ///
+    /// # Example
+    /// ```
+    /// use overlay_map::OverlayMap;
+    ///
+    /// let map = OverlayMap::from([("a", 1), ("b", 2)]);
+    /// assert_eq!(map.fg(&"a"), Some(&1));
+    /// assert_eq!(map.len(), 2);
+    /// ```
+    fn from(arr: [(K, V); N]) -> Self {
+        arr.into_iter().collect()
+    }
+}
+
+impl<K, V, S, A, M, O> Index<&K> for OverlayMap<K, V, S, A, M, O>
+where
+    M: OverlayStorage<K, V>,
+    O: OverlayObserver<K, V>,
+{
+    type Output = V;
+
+    /// Returns a reference to the foreground value associated with the key.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the key is not present in the map.
+    ///
+    /// # Example
+    /// ```
+    /// use overlay_map::OverlayMap;
+    ///
+    /// let mut map = OverlayMap::new();
+    /// map.push("a", 1);
+    /// map.push("a", 2);
+    /// assert_eq!(map[&"a"], 2);
+    /// ```
+    #[inline]
+    fn index(&self, key: &K) -> &V {
+        self.fg(key).expect("key not found in OverlayMap")
+    }
+}
+
+/// Pushes `value` onto `entry`, handing the evicted background to the observer.
+#[inline]
+fn push_observed<K, V, O>(observer: &mut O, key: &K, entry: &mut Overlay<V>, value: V)
+where
+    O: OverlayObserver<K, V>,
+{
+    let evicted = entry.swap(value);
+    observer.on_push(key, entry.fg_unchecked());
+    if let Some(evicted) = evicted {
+        observer.on_evict_bg(key, evicted);
+    }
+}
+
+/// Pulls the foreground of a non-empty `entry`, notifying the observer of the pull and of
+/// the key's removal if the entry is left empty.
+#[inline]
+fn pull_observed<K, V, O>(observer: &mut O, key: &K, entry: &mut Overlay<V>) -> V
+where
+    O: OverlayObserver<K, V>,
+{
+    let pulled = entry.pull_unchecked();
+    observer.on_pull(key, &pulled);
+    if entry.is_empty() {
+        observer.on_remove(key);
+    }
+    pulled
+}
+
 const SLOT0_PRESENT: u8 = 1 << 0;
 const SLOT1_PRESENT: u8 = 1 << 1;
 const SLOT_MASK: u8 = SLOT0_PRESENT | SLOT1_PRESENT;
@@ -609,6 +1370,63 @@ impl<T> Overlay<T> {
         }
     }
 
+    /// Creates a new `Overlay` with a background value and no foreground.
+    ///
+    /// An overlay without a foreground can't be stored in an [`OverlayMap`]; give it one with
+    /// [`replace_fg`](Self::replace_fg) or [`get_or_push_with`](Self::get_or_push_with) first.
+    ///
+    /// ```
+    /// use overlay_map::Overlay;
+    ///
+    /// let mut entry = Overlay::new_bg("bg");
+    /// assert_eq!(entry.fg(), None);
+    /// assert_eq!(entry.bg(), Some(&"bg"));
+    ///
+    /// entry.replace_fg("fg");
+    /// assert_eq!(entry, Overlay::new_both("fg", "bg"));
+    /// ```
+    pub fn new_bg(val: T) -> Self {
+        Self {
+            bits: SLOT1_PRESENT,
+            slots: [MaybeUninit::uninit(), MaybeUninit::new(val)],
+        }
+    }
+
+    /// Creates a new `Overlay` from an optional foreground and background.
+    ///
+    /// This is the inverse of [`into_parts`](Self::into_parts).
+    ///
+    /// ```
+    /// use overlay_map::Overlay;
+    ///
+    /// assert_eq!(Overlay::from_parts(Some(1), Some(2)), Overlay::new_both(1, 2));
+    /// assert_eq!(Overlay::from_parts(Some(1), None), Overlay::new_fg(1));
+    /// assert_eq!(Overlay::from_parts(None, Some(2)), Overlay::new_bg(2));
+    /// assert!(Overlay::<i32>::from_parts(None, None).is_empty());
+    /// ```
+    pub fn from_parts(fg: Option<T>, bg: Option<T>) -> Self {
+        match (fg, bg) {
+            (Some(fg), Some(bg)) => Self::new_both(fg, bg),
+            (Some(fg), None) => Self::new_fg(fg),
+            (None, Some(bg)) => Self::new_bg(bg),
+            (None, None) => Self::new_empty(),
+        }
+    }
+
+    /// Consumes the overlay, returning its foreground and background values.
+    ///
+    /// ```
+    /// use overlay_map::Overlay;
+    ///
+    /// let mut entry = Overlay::new_both("a", "b");
+    /// entry.flip();
+    /// assert_eq!(entry.into_parts(), (Some("b"), Some("a")));
+    /// ```
+    pub fn into_parts(mut self) -> (Option<T>, Option<T>) {
+        let fg = self.take_fg();
+        (fg, self.take_bg())
+    }
+
     /// Returns a reference to the current foreground value, if present.
     ///
     /// This returns `Some(&T)` only if the foreground slot contains a value.
@@ -663,6 +1481,14 @@ impl<T> Overlay<T> {
         unsafe { self.slots[idx].assume_init_ref() }
     }
 
+    /// Returns a mutable reference to the foreground value **without checking** if it is
+    /// present.
+    #[inline]
+    pub(crate) fn fg_unchecked_mut(&mut self) -> &mut T {
+        let idx = self.fg_index();
+        unsafe { self.slots[idx].assume_init_mut() }
+    }
+
     /// Returns a reference to the background value, if present.
     ///
     /// Returns `Some(&T)` only if the background slot is initialized.
@@ -930,6 +1756,129 @@ impl<T> Overlay<T> {
         }
     }
 
+    /// Replaces the foreground value in place, returning the previous foreground if present.
+    ///
+    /// Unlike [`push`](Self::push), the background is left untouched.
+    ///
+    /// ```
+    /// use overlay_map::Overlay;
+    ///
+    /// let mut entry = Overlay::new_both("a", "b");
+    /// assert_eq!(entry.replace_fg("c"), Some("a"));
+    /// assert_eq!(entry.fg(), Some(&"c"));
+    /// assert_eq!(entry.bg(), Some(&"b"));
+    /// ```
+    #[inline]
+    pub fn replace_fg(&mut self, val: T) -> Option<T> {
+        let fgi = self.fg_index();
+        if self.is_slot_present(fgi) {
+            Some(mem::replace(
+                unsafe { self.slots[fgi].assume_init_mut() },
+                val,
+            ))
+        } else {
+            self.slots[fgi] = MaybeUninit::new(val);
+            self.bits |= 1 << fgi;
+            None
+        }
+    }
+
+    /// Takes the foreground value out, leaving the background in place.
+    ///
+    /// Unlike [`pull`](Self::pull), the background is not promoted, so the overlay is left
+    /// with a background and no foreground if both were present.
+    ///
+    /// ```
+    /// use overlay_map::Overlay;
+    ///
+    /// let mut entry = Overlay::new_both("a", "b");
+    /// assert_eq!(entry.take_fg(), Some("a"));
+    /// assert_eq!(entry.fg(), None);
+    /// assert_eq!(entry.bg(), Some(&"b"));
+    /// ```
+    #[inline]
+    pub fn take_fg(&mut self) -> Option<T> {
+        let fgi = self.fg_index();
+        if self.is_slot_present(fgi) {
+            self.bits &= !(1 << fgi);
+            Some(unsafe { self.slots[fgi].assume_init_read() })
+        } else {
+            None
+        }
+    }
+
+    /// Takes the background value out, leaving the foreground in place.
+    ///
+    /// ```
+    /// use overlay_map::Overlay;
+    ///
+    /// let mut entry = Overlay::new_both("a", "b");
+    /// assert_eq!(entry.take_bg(), Some("b"));
+    /// assert_eq!(entry, Overlay::new_fg("a"));
+    /// ```
+    #[inline]
+    pub fn take_bg(&mut self) -> Option<T> {
+        let bgi = self.bg_index();
+        if self.is_slot_present(bgi) {
+            self.bits &= !(1 << bgi);
+            Some(unsafe { self.slots[bgi].assume_init_read() })
+        } else {
+            None
+        }
+    }
+
+    /// Sets the background value under the current foreground, returning the previous
+    /// background if present.
+    ///
+    /// ```
+    /// use overlay_map::Overlay;
+    ///
+    /// let mut entry = Overlay::new_fg("a");
+    /// assert_eq!(entry.set_bg("b"), None);
+    /// assert_eq!(entry.set_bg("c"), Some("b"));
+    /// assert_eq!(entry, Overlay::new_both("a", "c"));
+    /// ```
+    #[inline]
+    pub fn set_bg(&mut self, val: T) -> Option<T> {
+        let bgi = self.bg_index();
+        if self.is_slot_present(bgi) {
+            Some(mem::replace(
+                unsafe { self.slots[bgi].assume_init_mut() },
+                val,
+            ))
+        } else {
+            self.slots[bgi] = MaybeUninit::new(val);
+            self.bits |= 1 << bgi;
+            None
+        }
+    }
+
+    /// Returns a mutable reference to the foreground value, first inserting the result of `f`
+    /// if there is none.
+    ///
+    /// Any background is left in place.
+    ///
+    /// ```
+    /// use overlay_map::Overlay;
+    ///
+    /// let mut entry = Overlay::new_empty();
+    /// *entry.get_or_push_with(|| 1) += 10;
+    /// *entry.get_or_push_with(|| 100) += 10;
+    /// assert_eq!(entry.fg(), Some(&21));
+    /// ```
+    #[inline]
+    pub fn get_or_push_with<F>(&mut self, f: F) -> &mut T
+    where
+        F: FnOnce() -> T,
+    {
+        let fgi = self.fg_index();
+        if !self.is_slot_present(fgi) {
+            self.slots[fgi] = MaybeUninit::new(f());
+            self.bits |= 1 << fgi;
+        }
+        unsafe { self.slots[fgi].assume_init_mut() }
+    }
+
     /// Get an iterator over the foreground and background values.
     ///
     /// ```
@@ -1074,16 +2023,55 @@ impl<T: Clone> Clone for Overlay<T> {
 }
 
 impl<T: PartialEq> PartialEq for Overlay<T> {
+    /// Compares the logical `(fg, bg)` pairs, regardless of which physical slot holds each
+    /// layer.
+    ///
+    /// ```
+    /// use overlay_map::Overlay;
+    ///
+    /// let mut flipped = Overlay::new_both(1, 2);
+    /// flipped.pull();
+    /// assert_eq!(flipped, Overlay::new_fg(2));
+    /// ```
     fn eq(&self, other: &Self) -> bool {
-        if (self.bits & SLOT_MASK) != (other.bits & SLOT_MASK) {
-            return false;
-        }
         self.fg() == other.fg() && self.bg() == other.bg()
     }
 }
 
 impl<T: Eq> Eq for Overlay<T> {}
 
+impl<T: Hash> Hash for Overlay<T> {
+    /// Hashes the logical `(fg, bg)` pair, consistent with [`PartialEq`].
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.fg().hash(state);
+        self.bg().hash(state);
+    }
+}
+
+impl<T: PartialOrd> PartialOrd for Overlay<T> {
+    /// Orders overlays lexicographically by foreground, then background, with an absent layer
+    /// ordered before any value.
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        (self.fg(), self.bg()).partial_cmp(&(other.fg(), other.bg()))
+    }
+}
+
+impl<T: Ord> Ord for Overlay<T> {
+    /// Orders overlays lexicographically by foreground, then background, with an absent layer
+    /// ordered before any value.
+    ///
+    /// ```
+    /// use overlay_map::Overlay;
+    ///
+    /// assert!(Overlay::<u32>::new_empty() < Overlay::new_fg(0));
+    /// assert!(Overlay::new_fg(1) < Overlay::new_both(1, 0));
+    /// assert!(Overlay::new_both(1, 9) < Overlay::new_fg(2));
+    /// ```
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.fg(), self.bg()).cmp(&(other.fg(), other.bg()))
+    }
+}
+
 impl<V> Drop for Overlay<V> {
     fn drop(&mut self) {
         if (self.bits & SLOT0_PRESENT) != 0 {
@@ -1104,7 +2092,7 @@ impl<T> Iterator for OverlayIntoIter<T> {
     type Item = T;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.overlay.pull()
+        self.overlay.pull().or_else(|| self.overlay.take_bg())
     }
 }
 
@@ -1170,6 +2158,17 @@ mod tests {
         assert_eq!(map.bg(&"c"), None);
     }
 
+    #[test]
+    fn collect_round_trips_both_layers() {
+        let map: OverlayMap<&str, i32> = [("a", 1), ("a", 2), ("b", 3)].into_iter().collect();
+        let collected: OverlayMap<&str, i32> = map.clone().into_iter().collect();
+
+        assert_eq!(collected[&"a"], 2);
+        assert_eq!(collected.bg(&"a"), Some(&1));
+        assert_eq!(collected[&"b"], 3);
+        assert_eq!(collected.len(), 2);
+    }
+
     #[test]
     fn push_if_and_swap_if_logic() {
         let mut map = OverlayMap::<&str, i32>::new();
@@ -1185,4 +2184,181 @@ mod tests {
         assert_eq!(map.fg(&"key"), Some(&42));
         assert_eq!(map.bg(&"key"), Some(&11));
     }
+
+    #[test]
+    fn fallible_capacity_management() {
+        let mut map = OverlayMap::<u64, u64>::with_capacity(64);
+        assert!(map.capacity() >= 64);
+
+        assert_eq!(map.try_swap(1, 10), Ok(None));
+        assert_eq!(map.try_swap(1, 11), Ok(None));
+        assert_eq!(map.try_swap(1, 12), Ok(Some(10)));
+        assert!(matches!(
+            map.try_reserve(usize::MAX),
+            Err(TryReserveError::CapacityOverflow)
+        ));
+        assert_eq!(map.len(), 1);
+
+        map.shrink_to_fit();
+        assert!(map.capacity() < 64);
+        map.reserve(8);
+        assert!(map.capacity() >= 9);
+        map.shrink_to(4);
+        assert!(map.capacity() >= 4);
+
+        let mut ordered = InsertionOrderedOverlayMap::<u64, u64>::default();
+        assert!(ordered.try_reserve(usize::MAX / 2).is_err());
+        assert_eq!(ordered.try_push(1, 1), Ok(false));
+        assert_eq!(ordered[&1], 1);
+    }
+
+    #[test]
+    fn custom_allocator_holds_entries() {
+        use std::{alloc::Layout, cell::Cell, ptr::NonNull};
+
+        use allocator_api2::alloc::AllocError;
+
+        struct Counting<'a>(&'a Cell<usize>);
+
+        unsafe impl Allocator for Counting<'_> {
+            fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+                self.0.set(self.0.get() + 1);
+                Global.allocate(layout)
+            }
+
+            unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+                self.0.set(self.0.get() - 1);
+                unsafe { Global.deallocate(ptr, layout) }
+            }
+        }
+
+        let live = Cell::new(0);
+        {
+            let mut map = OverlayMap::<u64, String, DefaultHashBuilder, _>::new_in(Counting(&live));
+            assert_eq!(live.get(), 0);
+            map.extend((0..32).map(|i| (i, i.to_string())));
+            map.push(0, "zero".into());
+            assert!(live.get() > 0);
+            assert_eq!(map.bg(&0).map(String::as_str), Some("0"));
+        }
+        assert_eq!(live.get(), 0);
+    }
+
+    fn hash_of<T: Hash>(value: &T) -> u64 {
+        std::hash::BuildHasherDefault::<DefaultHasher>::default().hash_one(value)
+    }
+
+    #[test]
+    fn overlay_equality_ignores_physical_slots() {
+        // fg only, held in slot 1 after a push + pull cycle.
+        let mut pulled = Overlay::new_fg(1);
+        pulled.push(2);
+        assert_eq!(pulled.pull(), Some(2));
+        pulled.push(3);
+        assert_eq!(pulled.pull(), Some(3));
+        let fresh = Overlay::new_fg(1);
+        assert_eq!(pulled, fresh);
+        assert_eq!(hash_of(&pulled), hash_of(&fresh));
+        assert_eq!(pulled.cmp(&fresh), Ordering::Equal);
+
+        // Both layers, with fg in slot 1 after a flip.
+        let mut flipped = Overlay::new_both(2, 1);
+        flipped.flip();
+        let fresh = Overlay::new_both(1, 2);
+        assert_eq!(flipped, fresh);
+        assert_eq!(hash_of(&flipped), hash_of(&fresh));
+        assert_eq!(flipped.partial_cmp(&fresh), Some(Ordering::Equal));
+
+        // Emptied from either slot.
+        let mut emptied = Overlay::new_both(1, 2);
+        emptied.pull();
+        emptied.pull();
+        assert_eq!(emptied, Overlay::new_empty());
+        assert_eq!(hash_of(&emptied), hash_of(&Overlay::<i32>::new_empty()));
+
+        assert_ne!(Overlay::new_both(1, 2), Overlay::new_both(2, 1));
+        assert_ne!(Overlay::new_fg(1), Overlay::new_both(1, 1));
+        assert!(Overlay::new_both(1, 2) < Overlay::new_both(2, 1));
+    }
+
+    #[test]
+    fn map_hash_is_logical_and_order_independent() {
+        let mut a = OverlayMap::<u32, u32>::new();
+        let mut b = OverlayMap::<u32, u32>::new();
+        for i in 0..64 {
+            a.push(i, i);
+            a.push(i, i + 1);
+            b.push(63 - i, 63 - i + 1);
+            b.push(63 - i, 63 - i);
+            b.flip(&(63 - i));
+        }
+        assert_eq!(a, b);
+        assert_eq!(hash_of(&a), hash_of(&b));
+
+        a.pull(&0);
+        assert_ne!(hash_of(&a), hash_of(&b));
+        b.pull(&0);
+        assert_eq!(hash_of(&a), hash_of(&b));
+    }
+
+    #[test]
+    fn slot_api_on_flipped_overlays() {
+        use std::rc::Rc;
+
+        let value = Rc::new(());
+        {
+            let mut entry = Overlay::new_both(Rc::clone(&value), Rc::clone(&value));
+            entry.flip();
+            assert!(entry.replace_fg(Rc::clone(&value)).is_some());
+            assert!(entry.set_bg(Rc::clone(&value)).is_some());
+            assert!(entry.take_fg().is_some());
+            assert_eq!(Rc::strong_count(&value), 2);
+            entry.get_or_push_with(|| Rc::clone(&value));
+            assert!(entry.is_full());
+
+            let (fg, bg) = entry.into_parts();
+            let mut entry = Overlay::from_parts(None, bg);
+            assert_eq!(entry.fg(), None);
+            assert_eq!(entry.pull(), None);
+            assert_eq!(entry.clone().into_iter().count(), 1);
+            entry.replace_fg(fg.unwrap());
+            assert_eq!(Rc::strong_count(&value), 3);
+        }
+        assert_eq!(Rc::strong_count(&value), 1);
+
+        // Overlays without a foreground never make it into a map.
+        let mut map: OverlayMap<_, _> = [("a", Overlay::new_bg(1)), ("b", Overlay::new_both(2, 3))]
+            .into_iter()
+            .collect();
+        assert_eq!(map.len(), 1);
+        map.extend([("b", Overlay::new_bg(4))]);
+        assert_eq!((map.fg(&"b"), map.bg(&"b")), (Some(&2), Some(&3)));
+    }
+
+    #[test]
+    fn from_hash_map_keeps_its_hasher() {
+        use std::hash::BuildHasherDefault;
+
+        #[derive(Clone, Default)]
+        struct Seeded(
+            u64,
+            BuildHasherDefault<std::collections::hash_map::DefaultHasher>,
+        );
+
+        impl BuildHasher for Seeded {
+            type Hasher = std::collections::hash_map::DefaultHasher;
+
+            fn build_hasher(&self) -> Self::Hasher {
+                let mut hasher = self.1.build_hasher();
+                hasher.write_u64(self.0);
+                hasher
+            }
+        }
+
+        let mut source = HashMap::with_hasher(Seeded(42, Default::default()));
+        source.insert("a", 1);
+        let map = OverlayMap::from(source);
+        assert_eq!(map.map.hasher().0, 42);
+        assert_eq!(map.fg(&"a"), Some(&1));
+    }
 }
@@ -0,0 +1,283 @@
+use std::mem;
+
+use crate::{Overlay, OverlayMap, OverlayObserver, OverlayStorage, push_observed};
+
+/// A user-supplied conflict resolver for [`MergePolicy::Resolve`].
+///
+/// Receives the key, our entry and the incoming entry, and decides what to keep.
+pub type MergeResolver<'a, K, V> =
+    dyn FnMut(&K, &Overlay<V>, Overlay<V>) -> MergeResolution<V> + 'a;
+
+/// The outcome of a [`MergeResolver`] for one key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MergeResolution<V> {
+    /// Keep our entry untouched. The key is reported as [`kept`](MergeReport::kept).
+    Keep,
+    /// Replace our entry with the given one, such as the incoming entry. The key is reported
+    /// as [`updated`](MergeReport::updated), or as [`removed`](MergeReport::removed) if the
+    /// overlay has no foreground.
+    Replace(Overlay<V>),
+    /// Remove the key. It is reported as [`removed`](MergeReport::removed).
+    Remove,
+}
+
+/// Decides how [`OverlayMap::merge_from`] combines an incoming entry with an existing one.
+///
+/// The policy only applies to keys present in both maps. Keys that only exist in the
+/// incoming map are always inserted as-is, background included.
+pub enum MergePolicy<'a, K, V> {
+    /// Push the incoming foreground onto ours, moving our foreground to the background.
+    /// The incoming background is dropped.
+    Push,
+    /// Replace our entry wholesale with the incoming one, including its background.
+    Replace,
+    /// Keep our entry untouched and drop the incoming one.
+    KeepOurs,
+    /// Call the resolver with our entry and the incoming one, and apply the
+    /// [`MergeResolution`] it returns. Our entry stays in the map while the resolver runs, so a
+    /// panicking resolver leaves it untouched.
+    Resolve(&'a mut MergeResolver<'a, K, V>),
+}
+
+/// The keys affected by an [`OverlayMap::merge_from`], grouped by outcome.
+///
+/// Keys are listed in the iteration order of the incoming map.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeReport<K> {
+    /// Keys that only existed in the incoming map and were inserted.
+    pub inserted: Vec<K>,
+    /// Keys whose entry was changed by the policy.
+    pub updated: Vec<K>,
+    /// Keys whose entry was left untouched by the policy.
+    pub kept: Vec<K>,
+    /// Keys that were removed by the resolver.
+    pub removed: Vec<K>,
+}
+
+impl<K> MergeReport<K> {
+    /// Total number of keys that were inserted, updated or removed.
+    pub fn changed(&self) -> usize {
+        self.inserted.len() + self.updated.len() + self.removed.len()
+    }
+}
+
+impl<K> Default for MergeReport<K> {
+    fn default() -> Self {
+        Self {
+            inserted: Vec::new(),
+            updated: Vec::new(),
+            kept: Vec::new(),
+            removed: Vec::new(),
+        }
+    }
+}
+
+impl<K, V, S, A, M, O> OverlayMap<K, V, S, A, M, O>
+where
+    K: Clone,
+    M: OverlayStorage<K, V>,
+    O: OverlayObserver<K, V>,
+{
+    /// Merges every entry of `other` into this map, using `policy` for keys present in both.
+    ///
+    /// Unlike [`extend`](Extend::extend), which only sees foreground values, this moves whole
+    /// [`Overlay`]s across, so incoming backgrounds are preserved wherever the policy keeps
+    /// them. No values are cloned.
+    ///
+    /// Returns a [`MergeReport`] listing the affected keys. The map's observer is notified of
+    /// every insertion, update and removal, and receives the values our entries lose.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use overlay_map::{MergePolicy, OverlayMap};
+    ///
+    /// let mut ours = OverlayMap::new();
+    /// ours.push("a", 1);
+    /// ours.push("b", 2);
+    ///
+    /// let mut theirs = OverlayMap::new();
+    /// theirs.push("b", 20);
+    /// theirs.push("c", 30);
+    /// theirs.push("c", 31);
+    ///
+    /// let report = ours.merge_from(theirs, MergePolicy::Push);
+    /// assert_eq!(report.inserted, vec!["c"]);
+    /// assert_eq!(report.updated, vec!["b"]);
+    ///
+    /// assert_eq!(ours.fg(&"b"), Some(&20));
+    /// assert_eq!(ours.bg(&"b"), Some(&2));
+    /// assert_eq!(ours.fg(&"c"), Some(&31));
+    /// assert_eq!(ours.bg(&"c"), Some(&30)); // incoming background preserved
+    /// ```
+    ///
+    /// Resolving conflicts by hand:
+    ///
+    /// ```
+    /// use overlay_map::{MergePolicy, MergeResolution, Overlay, OverlayMap};
+    ///
+    /// let mut ours = OverlayMap::new();
+    /// ours.push("score", 10);
+    ///
+    /// let mut theirs = OverlayMap::new();
+    /// theirs.push("score", 7);
+    ///
+    /// let mut keep_highest = |_: &&str, ours: &Overlay<i32>, theirs: Overlay<i32>| {
+    ///     if theirs.fg() > ours.fg() {
+    ///         MergeResolution::Replace(theirs)
+    ///     } else {
+    ///         MergeResolution::Keep
+    ///     }
+    /// };
+    /// let report = ours.merge_from(theirs, MergePolicy::Resolve(&mut keep_highest));
+    ///
+    /// assert_eq!(report.kept, vec!["score"]);
+    /// assert_eq!(ours.fg(&"score"), Some(&10));
+    /// ```
+    pub fn merge_from<S2, A2, M2, O2>(
+        &mut self,
+        other: OverlayMap<K, V, S2, A2, M2, O2>,
+        mut policy: MergePolicy<'_, K, V>,
+    ) -> MergeReport<K>
+    where
+        M2: OverlayStorage<K, V>,
+    {
+        let mut report = MergeReport::default();
+
+        for (key, mut theirs) in other {
+            if theirs.fg().is_none() {
+                continue;
+            }
+
+            let Some(ours) = self.map.get_mut(&key) else {
+                self.observer.on_insert(&key, theirs.fg_unchecked());
+                self.map.insert(key.clone(), theirs);
+                report.inserted.push(key);
+                continue;
+            };
+
+            match &mut policy {
+                MergePolicy::Push => {
+                    push_observed(&mut self.observer, &key, ours, theirs.pull_unchecked());
+                    report.updated.push(key);
+                }
+                MergePolicy::Replace => {
+                    for value in mem::replace(ours, theirs) {
+                        self.observer.on_evict_bg(&key, value);
+                    }
+                    self.observer.on_push(&key, ours.fg_unchecked());
+                    report.updated.push(key);
+                }
+                MergePolicy::KeepOurs => {
+                    report.kept.push(key);
+                }
+                MergePolicy::Resolve(resolve) => match resolve(&key, ours, theirs) {
+                    MergeResolution::Keep => report.kept.push(key),
+                    MergeResolution::Replace(resolved) if resolved.fg().is_some() => {
+                        for value in mem::replace(ours, resolved) {
+                            self.observer.on_evict_bg(&key, value);
+                        }
+                        self.observer.on_push(&key, ours.fg_unchecked());
+                        report.updated.push(key);
+                    }
+                    MergeResolution::Replace(_) | MergeResolution::Remove => {
+                        for value in self.map.remove(&key).into_iter().flatten() {
+                            self.observer.on_evict_bg(&key, value);
+                        }
+                        self.observer.on_remove(&key);
+                        report.removed.push(key);
+                    }
+                },
+            }
+        }
+
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::OverlayStats;
+
+    #[test]
+    fn replace_keep_and_resolve_removal() {
+        let mut ours = OverlayMap::<&str, i32>::new();
+        ours.push("a", 1);
+        ours.push("b", 2);
+
+        let mut theirs = OverlayMap::<&str, i32>::new();
+        theirs.push("a", 10);
+        theirs.push("a", 11);
+        theirs.push("b", 20);
+
+        let report = ours.merge_from(theirs.clone(), MergePolicy::KeepOurs);
+        assert_eq!(report.changed(), 0);
+        assert_eq!(report.kept.len(), 2);
+        assert_eq!(ours.fg(&"a"), Some(&1));
+
+        ours.merge_from(theirs, MergePolicy::Replace);
+        assert_eq!(ours.fg(&"a"), Some(&11));
+        assert_eq!(ours.bg(&"a"), Some(&10));
+
+        let mut drop_all = |_: &&str, _: &Overlay<i32>, _: Overlay<i32>| MergeResolution::Remove;
+        let mut theirs = OverlayMap::new();
+        theirs.push("b", 0);
+        let report = ours.merge_from(theirs, MergePolicy::Resolve(&mut drop_all));
+        assert_eq!(report.removed, vec!["b"]);
+        assert_eq!(ours.fg(&"b"), None);
+        assert_eq!(ours.len(), 1);
+    }
+
+    #[test]
+    fn resolver_outcomes_are_reported_and_observed() {
+        let mut ours = OverlayMap::<&str, i32>::new().with_observer(OverlayStats::new());
+        for (key, value) in [("keep", 1), ("replace", 2), ("replace", 3), ("remove", 4)] {
+            ours.push(key, value);
+        }
+        let mut theirs = OverlayMap::new();
+        for key in ["keep", "replace", "remove"] {
+            theirs.push(key, 0);
+        }
+
+        let mut resolve = |key: &&str, _: &Overlay<i32>, theirs: Overlay<i32>| match *key {
+            "keep" => MergeResolution::Keep,
+            "replace" => MergeResolution::Replace(theirs),
+            _ => MergeResolution::Remove,
+        };
+        let mut report = ours.merge_from(theirs, MergePolicy::Resolve(&mut resolve));
+        report.kept.sort();
+
+        assert_eq!(report.kept, vec!["keep"]);
+        assert_eq!(report.updated, vec!["replace"]);
+        assert_eq!(report.removed, vec!["remove"]);
+        assert_eq!(ours.fg(&"keep"), Some(&1));
+        assert_eq!((ours.fg(&"replace"), ours.bg(&"replace")), (Some(&0), None));
+        // Both layers of "replace" and the foreground of "remove" are evicted.
+        assert_eq!(ours.observer().evictions, 3);
+    }
+
+    #[test]
+    fn panicking_resolver_leaves_our_entry_untouched() {
+        use std::panic::{AssertUnwindSafe, catch_unwind};
+
+        let mut ours = OverlayMap::<&str, i32>::new();
+        ours.push("a", 1);
+        ours.push("b", 2);
+
+        let mut theirs = OverlayMap::new();
+        theirs.push("a", 10);
+
+        let mut explode = |_: &&str, _: &Overlay<i32>, _: Overlay<i32>| -> MergeResolution<i32> {
+            panic!("resolver failed")
+        };
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            ours.merge_from(theirs, MergePolicy::Resolve(&mut explode))
+        }));
+
+        assert!(result.is_err());
+        assert_eq!(ours.fg(&"a"), Some(&1));
+        assert_eq!(ours.fg(&"b"), Some(&2));
+        assert_eq!(ours.len(), 2);
+    }
+}
@@ -0,0 +1,230 @@
+/// Hooks called by an [`OverlayMap`](crate::OverlayMap) on every state transition.
+///
+/// Attach an observer with [`OverlayMap::with_observer`](crate::OverlayMap::with_observer) to
+/// feed audit logs, metrics or cache invalidation without wrapping every call site. Every
+/// method has an empty default, so implementations only override the hooks they need. The
+/// default observer, `()`, ignores everything and compiles away.
+///
+/// Hooks run after the map has been updated, except [`on_insert`](Self::on_insert), which
+/// runs just before the new entry is stored.
+///
+/// # Ownership of evicted values
+///
+/// Values that leave the map without being returned to the caller — the background dropped
+/// by a [`push`](crate::OverlayMap::push), or the layers of an entry replaced wholesale — are
+/// handed to [`on_evict_bg`](Self::on_evict_bg) by value. Values returned to the caller, such
+/// as the result of [`pull`](crate::OverlayMap::pull) or [`swap`](crate::OverlayMap::swap),
+/// are only shown to the observer by reference.
+///
+/// # Example
+///
+/// ```
+/// use overlay_map::{OverlayMap, OverlayObserver};
+///
+/// #[derive(Default)]
+/// struct Audit(Vec<String>);
+///
+/// impl OverlayObserver<&str, i32> for Audit {
+///     fn on_insert(&mut self, key: &&str, value: &i32) {
+///         self.0.push(format!("insert {key}={value}"));
+///     }
+///
+///     fn on_evict_bg(&mut self, key: &&str, value: i32) {
+///         self.0.push(format!("evict {key}={value}"));
+///     }
+///
+///     fn on_remove(&mut self, key: &&str) {
+///         self.0.push(format!("remove {key}"));
+///     }
+/// }
+///
+/// let mut map = OverlayMap::new().with_observer(Audit::default());
+/// map.push("a", 1);
+/// map.push("a", 2);
+/// map.push("a", 3);
+/// map.pull(&"a");
+/// map.pull(&"a");
+///
+/// assert_eq!(map.observer().0, ["insert a=1", "evict a=1", "remove a"]);
+/// ```
+pub trait OverlayObserver<K, V> {
+    /// A key that was absent is about to be inserted with `value` as its foreground.
+    fn on_insert(&mut self, key: &K, value: &V) {
+        let _ = (key, value);
+    }
+
+    /// An existing key received `value` as its new foreground through a push.
+    fn on_push(&mut self, key: &K, value: &V) {
+        let _ = (key, value);
+    }
+
+    /// An existing key received `value` as its new foreground through a swap.
+    ///
+    /// The evicted background, if any, is returned to the caller rather than passed to
+    /// [`on_evict_bg`](Self::on_evict_bg). Defaults to [`on_push`](Self::on_push).
+    fn on_swap(&mut self, key: &K, value: &V) {
+        self.on_push(key, value);
+    }
+
+    /// An existing key's foreground was replaced in place with `value`, leaving its
+    /// background untouched.
+    ///
+    /// The previous foreground is returned to the caller.
+    fn on_replace_fg(&mut self, key: &K, value: &V) {
+        let _ = (key, value);
+    }
+
+    /// `value` was set as the background beneath an existing key's foreground.
+    ///
+    /// A background it replaced is first reported to [`on_take_bg`](Self::on_take_bg).
+    fn on_set_bg(&mut self, key: &K, value: &V) {
+        let _ = (key, value);
+    }
+
+    /// The background `value` was taken out from under the key's foreground and is being
+    /// returned to the caller.
+    fn on_take_bg(&mut self, key: &K, value: &V) {
+        let _ = (key, value);
+    }
+
+    /// A value was dropped from the map without being returned to the caller.
+    ///
+    /// The observer takes ownership of it.
+    fn on_evict_bg(&mut self, key: &K, value: V) {
+        let _ = (key, value);
+    }
+
+    /// The foreground `value` was pulled from the key and is being returned to the caller.
+    fn on_pull(&mut self, key: &K, value: &V) {
+        let _ = (key, value);
+    }
+
+    /// The foreground and background of the key were flipped.
+    fn on_flip(&mut self, key: &K) {
+        let _ = key;
+    }
+
+    /// The key was removed from the map.
+    fn on_remove(&mut self, key: &K) {
+        let _ = key;
+    }
+
+    /// Supplies a spare value for [`push_with`](crate::OverlayMap::push_with) to refill, when
+    /// the key has no background of its own to reuse.
+    ///
+    /// Returns `None` by default. [`RecyclePool`](crate::RecyclePool) returns values it
+    /// collected from [`on_evict_bg`](Self::on_evict_bg).
+    fn recycle(&mut self, key: &K) -> Option<V> {
+        let _ = key;
+        None
+    }
+}
+
+impl<K, V> OverlayObserver<K, V> for () {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Overlay, OverlayMap};
+
+    #[derive(Debug, Default, PartialEq)]
+    struct Log(Vec<(&'static str, u32, Option<String>)>);
+
+    impl OverlayObserver<u32, String> for Log {
+        fn on_insert(&mut self, key: &u32, value: &String) {
+            self.0.push(("insert", *key, Some(value.clone())));
+        }
+
+        fn on_push(&mut self, key: &u32, value: &String) {
+            self.0.push(("push", *key, Some(value.clone())));
+        }
+
+        fn on_evict_bg(&mut self, key: &u32, value: String) {
+            self.0.push(("evict", *key, Some(value)));
+        }
+
+        fn on_pull(&mut self, key: &u32, value: &String) {
+            self.0.push(("pull", *key, Some(value.clone())));
+        }
+
+        fn on_flip(&mut self, key: &u32) {
+            self.0.push(("flip", *key, None));
+        }
+
+        fn on_remove(&mut self, key: &u32) {
+            self.0.push(("remove", *key, None));
+        }
+
+        fn on_replace_fg(&mut self, key: &u32, value: &String) {
+            self.0.push(("replace_fg", *key, Some(value.clone())));
+        }
+
+        fn on_set_bg(&mut self, key: &u32, value: &String) {
+            self.0.push(("set_bg", *key, Some(value.clone())));
+        }
+
+        fn on_take_bg(&mut self, key: &u32, value: &String) {
+            self.0.push(("take_bg", *key, Some(value.clone())));
+        }
+    }
+
+    #[test]
+    fn every_transition_is_observed() {
+        let mut map = OverlayMap::new().with_observer(Log::default());
+        let s = |v: &str| Some(v.to_string());
+
+        map.push(1, "a".to_string());
+        map.push(1, "b".to_string());
+        map.flip(&1);
+        map.flip(&2);
+        assert_eq!(map.swap(1, "c".to_string()), s("b"));
+        map.push(1, "d".to_string());
+        assert!(map.push_if(&1, |_| Some("e".to_string())));
+        assert_eq!(map.pull_if(&1, |v| v == "e"), s("e"));
+        assert_eq!(map.pull(&1), s("d"));
+        map.extend([(1, Overlay::new_fg("f".to_string()))]);
+        map.extend([(1, Overlay::new_both("g".to_string(), "h".to_string()))]);
+
+        let expected = vec![
+            ("insert", 1, s("a")),
+            ("push", 1, s("b")),
+            ("flip", 1, None),
+            ("push", 1, s("c")),
+            ("push", 1, s("d")),
+            ("evict", 1, s("a")),
+            ("push", 1, s("e")),
+            ("evict", 1, s("c")),
+            ("pull", 1, s("e")),
+            ("pull", 1, s("d")),
+            ("remove", 1, None),
+            ("insert", 1, s("f")),
+            ("evict", 1, s("f")),
+            ("push", 1, s("g")),
+        ];
+        assert_eq!(map.observer().0, expected);
+    }
+
+    #[test]
+    fn single_layer_edits_are_observed() {
+        let mut map = OverlayMap::new().with_observer(Log::default());
+        let s = |v: &str| Some(v.to_string());
+
+        map.get_or_push_with(1, || "a".to_string());
+        map.get_or_push_with(1, || unreachable!());
+        assert_eq!(map.replace_fg(1, "b".to_string()), s("a"));
+        assert_eq!(map.set_bg(&1, "c".to_string()), Ok(None));
+        assert_eq!(map.set_bg(&1, "d".to_string()), Ok(s("c")));
+        assert_eq!(map.take_bg(&1), s("d"));
+        assert_eq!(map.take_bg(&1), None);
+
+        let expected = vec![
+            ("insert", 1, s("a")),
+            ("replace_fg", 1, s("b")),
+            ("set_bg", 1, s("c")),
+            ("take_bg", 1, s("c")),
+            ("set_bg", 1, s("d")),
+            ("take_bg", 1, s("d")),
+        ];
+        assert_eq!(map.observer().0, expected);
+    }
+}
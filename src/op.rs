@@ -0,0 +1,308 @@
+use std::{
+    hash::{BuildHasher, Hash},
+    ops::Deref,
+};
+
+use hashbrown::DefaultHashBuilder;
+
+use crate::{Overlay, OverlayMap, OverlayObserver, OverlayStorage};
+
+/// A state transition of an [`OverlayMap`], as data.
+///
+/// Every variant corresponds to the `OverlayMap` method of the same name, and applying it
+/// with [`OverlayMap::apply`] has exactly the same effect as calling that method. Because
+/// the transitions are deterministic, applying the same sequence of ops to two equal maps
+/// leaves them equal, which makes an op stream suitable for replication, journaling and
+/// replaying recorded sessions in tests.
+///
+/// Conditional methods such as [`push_if`](OverlayMap::push_if) take a closure and have no
+/// `Op`; record the transition they performed instead, as
+/// [`RecordingOverlayMap`] does.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Op<K, V> {
+    /// See [`OverlayMap::push`].
+    Push(K, V),
+    /// See [`OverlayMap::swap`].
+    Swap(K, V),
+    /// See [`OverlayMap::pull`].
+    Pull(K),
+    /// See [`OverlayMap::flip`].
+    Flip(K),
+    /// See [`OverlayMap::remove`].
+    Remove(K),
+}
+
+impl<K, V> Op<K, V> {
+    /// The key the op applies to.
+    pub fn key(&self) -> &K {
+        match self {
+            Op::Push(key, _) | Op::Swap(key, _) => key,
+            Op::Pull(key) | Op::Flip(key) | Op::Remove(key) => key,
+        }
+    }
+}
+
+/// The return value of an applied [`Op`], matching the return value of the corresponding
+/// [`OverlayMap`] method.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OpResult<V> {
+    /// Whether the key already had a foreground value.
+    Push(bool),
+    /// The evicted background value, if any.
+    Swap(Option<V>),
+    /// The pulled foreground value, if any.
+    Pull(Option<V>),
+    /// Flips return nothing.
+    Flip,
+    /// The removed entry, if any.
+    Remove(Option<Overlay<V>>),
+}
+
+impl<K, V, S, A, M, O> OverlayMap<K, V, S, A, M, O>
+where
+    M: OverlayStorage<K, V>,
+    O: OverlayObserver<K, V>,
+{
+    /// Applies an [`Op`] to the map, as if the corresponding method had been called.
+    ///
+    /// ```
+    /// use overlay_map::{Op, OpResult, OverlayMap};
+    ///
+    /// let mut map = OverlayMap::new();
+    /// assert_eq!(map.apply(Op::Push("a", 1)), OpResult::Push(false));
+    /// assert_eq!(map.apply(Op::Push("a", 2)), OpResult::Push(true));
+    /// assert_eq!(map.apply(Op::Swap("a", 3)), OpResult::Swap(Some(1)));
+    /// assert_eq!(map.apply(Op::Pull("a")), OpResult::Pull(Some(3)));
+    /// assert_eq!(map.fg(&"a"), Some(&2));
+    /// ```
+    pub fn apply(&mut self, op: Op<K, V>) -> OpResult<V> {
+        match op {
+            Op::Push(key, value) => OpResult::Push(self.push(key, value)),
+            Op::Swap(key, value) => OpResult::Swap(self.swap(key, value)),
+            Op::Pull(key) => OpResult::Pull(self.pull(&key)),
+            Op::Flip(key) => {
+                self.flip(&key);
+                OpResult::Flip
+            }
+            Op::Remove(key) => OpResult::Remove(self.remove(&key)),
+        }
+    }
+
+    /// Applies a sequence of [`Op`]s in order, returning the result of each.
+    pub fn apply_batch<I>(&mut self, ops: I) -> Vec<OpResult<V>>
+    where
+        I: IntoIterator<Item = Op<K, V>>,
+    {
+        ops.into_iter().map(|op| self.apply(op)).collect()
+    }
+}
+
+/// An [`OverlayMap`] that records every transition made through it as an [`Op`].
+///
+/// The recorded ops can be taken with [`take_ops`](Self::take_ops) and applied to another
+/// map with [`OverlayMap::apply_batch`] to bring it to the same state, whether that map is a
+/// replica or a fresh copy in a deterministic test.
+///
+/// Conditional transitions are recorded as the unconditional op they performed, and only if
+/// they performed one. Keys and values are cloned into the recording, so both must be
+/// `Clone`. The map can be read through `Deref`.
+///
+/// # Example
+///
+/// ```
+/// use overlay_map::{Op, OverlayMap, RecordingOverlayMap};
+///
+/// let mut primary = RecordingOverlayMap::new(OverlayMap::new());
+/// primary.push("a", 1);
+/// primary.push_if(&"a", |v| Some(v + 1));
+/// primary.flip(&"a");
+///
+/// assert_eq!(
+///     primary.ops(),
+///     &[Op::Push("a", 1), Op::Push("a", 2), Op::Flip("a")]
+/// );
+///
+/// let mut replica = OverlayMap::new();
+/// replica.apply_batch(primary.take_ops());
+/// assert_eq!(&replica, primary.map());
+/// ```
+#[derive(Debug, Clone)]
+pub struct RecordingOverlayMap<K, V, S = DefaultHashBuilder>
+where
+    K: Eq + Hash,
+{
+    map: OverlayMap<K, V, S>,
+    ops: Vec<Op<K, V>>,
+}
+
+impl<K, V, S> RecordingOverlayMap<K, V, S>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    S: BuildHasher,
+{
+    /// Starts recording the transitions made to `map`. Its current contents are not recorded.
+    pub fn new(map: OverlayMap<K, V, S>) -> Self {
+        Self {
+            map,
+            ops: Vec::new(),
+        }
+    }
+
+    /// The map being recorded.
+    pub fn map(&self) -> &OverlayMap<K, V, S> {
+        &self.map
+    }
+
+    /// The ops recorded so far.
+    pub fn ops(&self) -> &[Op<K, V>] {
+        &self.ops
+    }
+
+    /// Takes the ops recorded so far, leaving the recording empty.
+    pub fn take_ops(&mut self) -> Vec<Op<K, V>> {
+        std::mem::take(&mut self.ops)
+    }
+
+    /// Stops recording, returning the map and the ops recorded since the last
+    /// [`take_ops`](Self::take_ops).
+    pub fn into_parts(self) -> (OverlayMap<K, V, S>, Vec<Op<K, V>>) {
+        (self.map, self.ops)
+    }
+
+    /// Applies and records an op.
+    ///
+    /// See [`OverlayMap::apply`].
+    pub fn apply(&mut self, op: Op<K, V>) -> OpResult<V> {
+        self.ops.push(op.clone());
+        self.map.apply(op)
+    }
+
+    /// Push a value into the foreground layer and record it.
+    ///
+    /// See [`OverlayMap::push`].
+    pub fn push(&mut self, key: K, value: V) -> bool {
+        let pushed = self.map.push(key.clone(), value.clone());
+        self.ops.push(Op::Push(key, value));
+        pushed
+    }
+
+    /// Conditionally push a new value based on the current value, recording the push if it
+    /// happens.
+    ///
+    /// See [`OverlayMap::push_if`].
+    pub fn push_if<F>(&mut self, key: &K, predicate: F) -> bool
+    where
+        F: FnOnce(&V) -> Option<V>,
+    {
+        let Some(value) = self.map.fg(key).and_then(predicate) else {
+            return false;
+        };
+        self.push(key.clone(), value)
+    }
+
+    /// Swap a value into the foreground layer and record it.
+    ///
+    /// See [`OverlayMap::swap`].
+    pub fn swap(&mut self, key: K, value: V) -> Option<V> {
+        let evicted = self.map.swap(key.clone(), value.clone());
+        self.ops.push(Op::Swap(key, value));
+        evicted
+    }
+
+    /// Pull the foreground value for a key and record it.
+    ///
+    /// See [`OverlayMap::pull`].
+    pub fn pull(&mut self, key: &K) -> Option<V> {
+        self.ops.push(Op::Pull(key.clone()));
+        self.map.pull(key)
+    }
+
+    /// Conditionally pull the foreground value for a key, recording the pull if it happens.
+    ///
+    /// See [`OverlayMap::pull_if`].
+    pub fn pull_if<F>(&mut self, key: &K, predicate: F) -> Option<V>
+    where
+        F: FnOnce(&V) -> bool,
+    {
+        let pulled = self.map.pull_if(key, predicate)?;
+        self.ops.push(Op::Pull(key.clone()));
+        Some(pulled)
+    }
+
+    /// Flip the foreground and background values for a key and record it.
+    ///
+    /// See [`OverlayMap::flip`].
+    pub fn flip(&mut self, key: &K) {
+        self.ops.push(Op::Flip(key.clone()));
+        self.map.flip(key);
+    }
+
+    /// Remove a key and both of its values, and record it.
+    ///
+    /// See [`OverlayMap::remove`].
+    pub fn remove(&mut self, key: &K) -> Option<Overlay<V>> {
+        self.ops.push(Op::Remove(key.clone()));
+        self.map.remove(key)
+    }
+}
+
+impl<K, V, S> Deref for RecordingOverlayMap<K, V, S>
+where
+    K: Eq + Hash,
+{
+    type Target = OverlayMap<K, V, S>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaying_a_recording_reproduces_the_map() {
+        let mut recording = RecordingOverlayMap::new(OverlayMap::<u32, u32>::new());
+        let mut rng = 0x2545_f491_u32;
+        for _ in 0..500 {
+            rng ^= rng << 13;
+            rng ^= rng >> 17;
+            rng ^= rng << 5;
+            let key = rng % 16;
+            match rng % 7 {
+                0 | 1 => {
+                    recording.push(key, rng);
+                }
+                2 => {
+                    recording.swap(key, rng);
+                }
+                3 => {
+                    recording.pull(&key);
+                }
+                4 => recording.flip(&key),
+                5 => {
+                    recording.pull_if(&key, |v| v % 2 == 0);
+                }
+                _ => {
+                    recording.remove(&key);
+                }
+            }
+        }
+
+        let (map, ops) = recording.into_parts();
+        let mut replica = OverlayMap::new();
+        let results = replica.apply_batch(ops.clone());
+        assert_eq!(replica, map);
+        assert_eq!(results.len(), ops.len());
+        assert!(ops.iter().zip(&results).all(|(op, result)| matches!(
+            (op, result),
+            (Op::Push(..), OpResult::Push(_))
+                | (Op::Swap(..), OpResult::Swap(_))
+                | (Op::Pull(_), OpResult::Pull(_))
+                | (Op::Flip(_), OpResult::Flip)
+                | (Op::Remove(_), OpResult::Remove(_))
+        )));
+    }
+}
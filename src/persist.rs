@@ -0,0 +1,981 @@
+//! Durable [`OverlayMap`]s backed by a snapshot file and a write-ahead journal.
+//!
+//! Both files start with a magic number, a format version and an epoch. The snapshot holds
+//! every entry with both layers, followed by a CRC-32 of the whole file. The journal holds one
+//! record per operation, each prefixed with its length and a CRC-32 of its payload.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    hash::{BuildHasher, Hash},
+    io::{self, Write},
+    ops::Deref,
+    path::{Path, PathBuf},
+};
+
+use hashbrown::{DefaultHashBuilder, HashMap};
+
+use crate::{Op, OpResult, Overlay, OverlayMap};
+
+const SNAPSHOT_FILE: &str = "snapshot.bin";
+const SNAPSHOT_TMP_FILE: &str = "snapshot.bin.tmp";
+const JOURNAL_FILE: &str = "journal.bin";
+const JOURNAL_TMP_FILE: &str = "journal.bin.tmp";
+
+const SNAPSHOT_MAGIC: &[u8; 4] = b"OVMS";
+const JOURNAL_MAGIC: &[u8; 4] = b"OVMJ";
+const FORMAT_VERSION: u16 = 1;
+
+/// Magic, version and epoch.
+const HEADER_LEN: usize = 4 + 2 + 8;
+/// Payload length and checksum in front of every journal record.
+const RECORD_HEADER_LEN: usize = 4 + 4;
+
+const OP_PUSH: u8 = 0;
+const OP_SWAP: u8 = 1;
+const OP_PULL: u8 = 2;
+const OP_FLIP: u8 = 3;
+const OP_REMOVE: u8 = 4;
+
+/// Types that can be written to and read back from a snapshot or journal.
+///
+/// The encoding is little-endian and length-prefixed, and is part of the versioned file
+/// format read by [`OverlayMap::recover`].
+///
+/// Implemented for the integer and floating point types, `bool`, `char`, [`String`],
+/// [`Vec`], [`Box`], [`Option`] and pairs. Implement it for your own types by encoding their
+/// fields in order.
+///
+/// ```
+/// use std::io;
+///
+/// use overlay_map::Persist;
+///
+/// struct Point {
+///     x: i32,
+///     y: i32,
+/// }
+///
+/// impl Persist for Point {
+///     fn encode(&self, out: &mut Vec<u8>) {
+///         self.x.encode(out);
+///         self.y.encode(out);
+///     }
+///
+///     fn decode(input: &mut &[u8]) -> io::Result<Self> {
+///         Ok(Point {
+///             x: i32::decode(input)?,
+///             y: i32::decode(input)?,
+///         })
+///     }
+/// }
+///
+/// let mut bytes = Vec::new();
+/// Point { x: 3, y: -4 }.encode(&mut bytes);
+/// let point = Point::decode(&mut bytes.as_slice()).unwrap();
+/// assert_eq!((point.x, point.y), (3, -4));
+/// ```
+pub trait Persist: Sized {
+    /// Appends the encoded value to `out`.
+    fn encode(&self, out: &mut Vec<u8>);
+
+    /// Decodes a value from the front of `input`, advancing it past the bytes read.
+    ///
+    /// Returns an [`InvalidData`](io::ErrorKind::InvalidData) error if the input is truncated
+    /// or malformed.
+    fn decode(input: &mut &[u8]) -> io::Result<Self>;
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn take<'a>(input: &mut &'a [u8], len: usize) -> io::Result<&'a [u8]> {
+    if input.len() < len {
+        return Err(invalid("unexpected end of data"));
+    }
+    let (head, tail) = input.split_at(len);
+    *input = tail;
+    Ok(head)
+}
+
+fn take_array<const N: usize>(input: &mut &[u8]) -> io::Result<[u8; N]> {
+    Ok(take(input, N)?.try_into().expect("slice has length N"))
+}
+
+fn decode_len(input: &mut &[u8]) -> io::Result<usize> {
+    usize::try_from(u64::decode(input)?).map_err(|_| invalid("length does not fit in usize"))
+}
+
+macro_rules! impl_persist_le {
+    ($($ty:ty),*) => {
+        $(impl Persist for $ty {
+            #[inline]
+            fn encode(&self, out: &mut Vec<u8>) {
+                out.extend_from_slice(&self.to_le_bytes());
+            }
+
+            #[inline]
+            fn decode(input: &mut &[u8]) -> io::Result<Self> {
+                take_array(input).map(<$ty>::from_le_bytes)
+            }
+        })*
+    };
+}
+
+impl_persist_le!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128, f32, f64);
+
+impl Persist for usize {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (*self as u64).encode(out);
+    }
+
+    fn decode(input: &mut &[u8]) -> io::Result<Self> {
+        decode_len(input)
+    }
+}
+
+impl Persist for isize {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (*self as i64).encode(out);
+    }
+
+    fn decode(input: &mut &[u8]) -> io::Result<Self> {
+        isize::try_from(i64::decode(input)?).map_err(|_| invalid("value does not fit in isize"))
+    }
+}
+
+impl Persist for bool {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(*self as u8);
+    }
+
+    fn decode(input: &mut &[u8]) -> io::Result<Self> {
+        match u8::decode(input)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(invalid("invalid bool")),
+        }
+    }
+}
+
+impl Persist for char {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (*self as u32).encode(out);
+    }
+
+    fn decode(input: &mut &[u8]) -> io::Result<Self> {
+        char::from_u32(u32::decode(input)?).ok_or_else(|| invalid("invalid char"))
+    }
+}
+
+impl Persist for String {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (self.len() as u64).encode(out);
+        out.extend_from_slice(self.as_bytes());
+    }
+
+    fn decode(input: &mut &[u8]) -> io::Result<Self> {
+        let len = decode_len(input)?;
+        let bytes = take(input, len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| invalid("invalid UTF-8"))
+    }
+}
+
+impl<T: Persist> Persist for Vec<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (self.len() as u64).encode(out);
+        for item in self {
+            item.encode(out);
+        }
+    }
+
+    fn decode(input: &mut &[u8]) -> io::Result<Self> {
+        let len = decode_len(input)?;
+        // A corrupt length must not trigger a huge allocation up front.
+        let mut items = Vec::with_capacity(len.min(input.len()));
+        for _ in 0..len {
+            items.push(T::decode(input)?);
+        }
+        Ok(items)
+    }
+}
+
+impl<T: Persist> Persist for Box<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        T::encode(self, out);
+    }
+
+    fn decode(input: &mut &[u8]) -> io::Result<Self> {
+        T::decode(input).map(Box::new)
+    }
+}
+
+impl<T: Persist> Persist for Option<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Some(value) => {
+                out.push(1);
+                value.encode(out);
+            }
+            None => out.push(0),
+        }
+    }
+
+    fn decode(input: &mut &[u8]) -> io::Result<Self> {
+        match u8::decode(input)? {
+            0 => Ok(None),
+            1 => T::decode(input).map(Some),
+            _ => Err(invalid("invalid option tag")),
+        }
+    }
+}
+
+impl<A: Persist, B: Persist> Persist for (A, B) {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.0.encode(out);
+        self.1.encode(out);
+    }
+
+    fn decode(input: &mut &[u8]) -> io::Result<Self> {
+        Ok((A::decode(input)?, B::decode(input)?))
+    }
+}
+
+/// CRC-32 (IEEE) lookup table, built at compile time.
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |crc, &byte| {
+        CRC_TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+fn encode_header(out: &mut Vec<u8>, magic: &[u8; 4], epoch: u64) {
+    out.extend_from_slice(magic);
+    FORMAT_VERSION.encode(out);
+    epoch.encode(out);
+}
+
+/// Reads a file header, returning its epoch.
+fn decode_header(input: &mut &[u8], magic: &[u8; 4]) -> io::Result<u64> {
+    if take(input, 4)? != magic {
+        return Err(invalid("bad magic number"));
+    }
+    if u16::decode(input)? != FORMAT_VERSION {
+        return Err(invalid("unsupported format version"));
+    }
+    u64::decode(input)
+}
+
+/// Flushes a rename or file creation in `dir` to disk. Not every platform can open a
+/// directory for syncing, so failures are ignored.
+fn sync_dir(dir: &Path) {
+    if let Ok(dir) = File::open(dir) {
+        let _ = dir.sync_all();
+    }
+}
+
+fn read_optional(path: &Path) -> io::Result<Option<Vec<u8>>> {
+    match fs::read(path) {
+        Ok(bytes) => Ok(Some(bytes)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Encodes an op from its parts, so that callers holding only references can journal it.
+fn encode_op<K: Persist, V: Persist>(out: &mut Vec<u8>, tag: u8, key: &K, value: Option<&V>) {
+    out.push(tag);
+    key.encode(out);
+    if let Some(value) = value {
+        value.encode(out);
+    }
+}
+
+impl<K: Persist, V: Persist> Persist for Op<K, V> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Op::Push(key, value) => encode_op(out, OP_PUSH, key, Some(value)),
+            Op::Swap(key, value) => encode_op(out, OP_SWAP, key, Some(value)),
+            Op::Pull(key) => encode_op::<K, V>(out, OP_PULL, key, None),
+            Op::Flip(key) => encode_op::<K, V>(out, OP_FLIP, key, None),
+            Op::Remove(key) => encode_op::<K, V>(out, OP_REMOVE, key, None),
+        }
+    }
+
+    fn decode(input: &mut &[u8]) -> io::Result<Self> {
+        Ok(match u8::decode(input)? {
+            OP_PUSH => Op::Push(K::decode(input)?, V::decode(input)?),
+            OP_SWAP => Op::Swap(K::decode(input)?, V::decode(input)?),
+            OP_PULL => Op::Pull(K::decode(input)?),
+            OP_FLIP => Op::Flip(K::decode(input)?),
+            OP_REMOVE => Op::Remove(K::decode(input)?),
+            _ => return Err(invalid("unknown journal operation")),
+        })
+    }
+}
+
+/// Decodes a journal record's payload, which must hold exactly one op.
+fn decode_record<K: Persist, V: Persist>(mut payload: &[u8]) -> io::Result<Op<K, V>> {
+    let op = Op::decode(&mut payload)?;
+    if !payload.is_empty() {
+        return Err(invalid("trailing bytes in journal record"));
+    }
+    Ok(op)
+}
+
+/// Frames an encoded op with its length and checksum.
+///
+/// Fails with [`io::ErrorKind::InvalidInput`] if the payload is too long for its length field.
+fn frame_record(payload: Vec<u8>) -> io::Result<Vec<u8>> {
+    let len = u32::try_from(payload.len()).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "journal record is 4 GiB or larger",
+        )
+    })?;
+    let mut record = Vec::with_capacity(RECORD_HEADER_LEN + payload.len());
+    len.encode(&mut record);
+    crc32(&payload).encode(&mut record);
+    record.extend_from_slice(&payload);
+    Ok(record)
+}
+
+/// The state of a persistence directory after replaying it.
+struct Recovered<K, V, S>
+where
+    K: Eq + Hash,
+{
+    map: OverlayMap<K, V, S>,
+    epoch: u64,
+    /// Length of the journal's valid prefix, or `None` if the journal is missing, stale or
+    /// has an unreadable header and must be recreated.
+    journal_len: Option<u64>,
+}
+
+fn recover_dir<K, V, S>(dir: &Path, hasher: S) -> io::Result<Recovered<K, V, S>>
+where
+    K: Eq + Hash + Persist,
+    V: Persist,
+    S: BuildHasher,
+{
+    let (mut map, epoch) = match read_optional(&dir.join(SNAPSHOT_FILE))? {
+        Some(bytes) => read_snapshot(&bytes, hasher)?,
+        None => (OverlayMap::from_storage(HashMap::with_hasher(hasher)), 0),
+    };
+
+    let journal = read_optional(&dir.join(JOURNAL_FILE))?.unwrap_or_default();
+    let mut input = journal.as_slice();
+    // A missing or torn header means the journal was being created when the process stopped;
+    // an older epoch means its records are already part of the snapshot.
+    match decode_header(&mut input, JOURNAL_MAGIC) {
+        Ok(journal_epoch) if journal_epoch == epoch => {}
+        Ok(journal_epoch) if journal_epoch > epoch => {
+            return Err(invalid("journal is newer than snapshot"));
+        }
+        _ => {
+            return Ok(Recovered {
+                map,
+                epoch,
+                journal_len: None,
+            });
+        }
+    }
+
+    let mut valid_len = HEADER_LEN;
+    // Replay until the first record that is incomplete or fails its checksum.
+    while let Some(payload) = next_record(&mut input) {
+        let Ok(op) = decode_record::<K, V>(payload) else {
+            break;
+        };
+        map.apply(op);
+        valid_len += RECORD_HEADER_LEN + payload.len();
+    }
+
+    Ok(Recovered {
+        map,
+        epoch,
+        journal_len: Some(valid_len as u64),
+    })
+}
+
+/// Splits the next intact record payload off the front of the journal.
+fn next_record<'a>(input: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let mut cursor = *input;
+    let len = u32::decode(&mut cursor).ok()? as usize;
+    let checksum = u32::decode(&mut cursor).ok()?;
+    let payload = take(&mut cursor, len).ok()?;
+    if crc32(payload) != checksum {
+        return None;
+    }
+    *input = cursor;
+    Some(payload)
+}
+
+fn read_snapshot<K, V, S>(bytes: &[u8], hasher: S) -> io::Result<(OverlayMap<K, V, S>, u64)>
+where
+    K: Eq + Hash + Persist,
+    V: Persist,
+    S: BuildHasher,
+{
+    if bytes.len() < 4 {
+        return Err(invalid("snapshot is truncated"));
+    }
+    let (body, checksum) = bytes.split_at(bytes.len() - 4);
+    if crc32(body) != u32::decode(&mut &checksum[..])? {
+        return Err(invalid("snapshot checksum mismatch"));
+    }
+
+    let mut input = body;
+    let epoch = decode_header(&mut input, SNAPSHOT_MAGIC)?;
+    let len = decode_len(&mut input)?;
+    let mut map = HashMap::with_capacity_and_hasher(len.min(input.len()), hasher);
+    for _ in 0..len {
+        let key = K::decode(&mut input)?;
+        let entry = match u8::decode(&mut input)? {
+            1 => Overlay::new_fg(V::decode(&mut input)?),
+            2 => Overlay::new_both(V::decode(&mut input)?, V::decode(&mut input)?),
+            _ => return Err(invalid("invalid layer count")),
+        };
+        map.insert(key, entry);
+    }
+    if !input.is_empty() {
+        return Err(invalid("trailing bytes in snapshot"));
+    }
+    Ok((OverlayMap::from_storage(map), epoch))
+}
+
+fn write_snapshot<K, V, S>(dir: &Path, map: &OverlayMap<K, V, S>, epoch: u64) -> io::Result<()>
+where
+    K: Eq + Hash + Persist,
+    V: Persist,
+    S: BuildHasher,
+{
+    let mut bytes = Vec::new();
+    encode_header(&mut bytes, SNAPSHOT_MAGIC, epoch);
+    (map.len() as u64).encode(&mut bytes);
+    for (key, entry) in map.map.iter() {
+        key.encode(&mut bytes);
+        match entry.bg() {
+            Some(bg) => {
+                bytes.push(2);
+                entry.fg_unchecked().encode(&mut bytes);
+                bg.encode(&mut bytes);
+            }
+            None => {
+                bytes.push(1);
+                entry.fg_unchecked().encode(&mut bytes);
+            }
+        }
+    }
+    crc32(&bytes).encode(&mut bytes);
+
+    let tmp = dir.join(SNAPSHOT_TMP_FILE);
+    let mut file = File::create(&tmp)?;
+    file.write_all(&bytes)?;
+    file.sync_all()?;
+    fs::rename(&tmp, dir.join(SNAPSHOT_FILE))?;
+    sync_dir(dir);
+    Ok(())
+}
+
+/// Creates an empty journal for `epoch` under `name`, replacing any existing one.
+fn create_journal(dir: &Path, name: &str, epoch: u64) -> io::Result<File> {
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    encode_header(&mut header, JOURNAL_MAGIC, epoch);
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(dir.join(name))?;
+    file.write_all(&header)?;
+    file.sync_all()?;
+    sync_dir(dir);
+    Ok(file)
+}
+
+impl<K, V, S> OverlayMap<K, V, S>
+where
+    K: Eq + Hash + Persist,
+    V: Persist,
+    S: BuildHasher + Default,
+{
+    /// Rebuilds a map from a persistence directory written by a [`DurableOverlayMap`].
+    ///
+    /// The snapshot is loaded and every intact journal record after it is replayed. A torn
+    /// record at the end of the journal, left by a crash mid-write, is ignored along with
+    /// anything after it. A directory with no files recovers as an empty map.
+    ///
+    /// This only reads the directory. To keep writing to it, use [`DurableOverlayMap::open`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the files cannot be read, or if the snapshot is corrupt.
+    pub fn recover(dir: impl AsRef<Path>) -> io::Result<Self> {
+        recover_dir(dir.as_ref(), S::default()).map(|recovered| recovered.map)
+    }
+}
+
+/// An [`OverlayMap`] that persists every change to a directory on the local file system.
+///
+/// Each [`push`](Self::push), [`swap`](Self::swap), [`pull`](Self::pull),
+/// [`flip`](Self::flip) and [`remove`](Self::remove) is appended to a write-ahead journal as
+/// an [`Op`] before it is applied. Calling
+/// [`checkpoint`](Self::checkpoint) writes a full snapshot of both layers and starts a new,
+/// empty journal. After a crash, [`open`](Self::open) or [`OverlayMap::recover`] rebuild the
+/// map from the snapshot and the journal.
+///
+/// # File layout
+///
+/// The directory holds two files:
+///
+/// - `snapshot.bin`: every entry of the map, with both layers, as of the last checkpoint
+/// - `journal.bin`: every [`Op`] applied since that checkpoint
+///
+/// Each journal record carries its own length and CRC-32 checksum, so a crash can leave at
+/// most one torn record at the end of the journal, which recovery detects and discards.
+/// Snapshots are written to a temporary file and renamed into place, so a crash during a
+/// checkpoint leaves the previous snapshot intact. Both files carry an epoch number, so a
+/// journal that was already folded into a newer snapshot is never replayed twice. Keys and
+/// values are encoded with [`Persist`].
+///
+/// Journal records are handed to the operating system as soon as they are written, which
+/// survives a crash of the process. Call [`sync`](Self::sync) to also survive a power loss.
+///
+/// The map can be read through `Deref`; all mutations must go through this type so that they
+/// are journaled.
+///
+/// # Example
+///
+/// ```
+/// # let dir = std::env::temp_dir().join(format!("overlay-doc-durable-{}", std::process::id()));
+/// # let _ = std::fs::remove_dir_all(&dir);
+/// use overlay_map::{DurableOverlayMap, OverlayMap};
+///
+/// let mut map = DurableOverlayMap::<String, u32>::open(&dir)?;
+/// map.push("hp".to_string(), 100)?;
+/// map.push("hp".to_string(), 80)?;
+/// map.checkpoint()?;
+/// map.flip(&"hp".to_string())?;
+/// drop(map);
+///
+/// let recovered = OverlayMap::<String, u32>::recover(&dir)?;
+/// assert_eq!(recovered.fg(&"hp".to_string()), Some(&100));
+/// assert_eq!(recovered.bg(&"hp".to_string()), Some(&80));
+/// # std::fs::remove_dir_all(&dir)?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct DurableOverlayMap<K, V, S = DefaultHashBuilder>
+where
+    K: Eq + Hash,
+{
+    map: OverlayMap<K, V, S>,
+    dir: PathBuf,
+    /// `None` after a checkpoint that wrote its snapshot but could not put the new journal in
+    /// place, until a later checkpoint succeeds.
+    journal: Option<File>,
+    journal_len: u64,
+    epoch: u64,
+}
+
+impl<K, V> DurableOverlayMap<K, V, DefaultHashBuilder>
+where
+    K: Eq + Hash + Persist,
+    V: Persist,
+{
+    /// Opens a persistence directory using the default hasher, creating it if needed.
+    ///
+    /// See [`open_with_hasher`](Self::open_with_hasher).
+    pub fn open(dir: impl AsRef<Path>) -> io::Result<Self> {
+        Self::open_with_hasher(dir, DefaultHashBuilder::default())
+    }
+}
+
+impl<K, V, S> DurableOverlayMap<K, V, S>
+where
+    K: Eq + Hash + Persist,
+    V: Persist,
+    S: BuildHasher,
+{
+    /// Opens a persistence directory, creating it if needed, and recovers its map using the
+    /// given hasher.
+    ///
+    /// Recovery works as in [`OverlayMap::recover`]. A torn record at the end of the journal
+    /// is cut off, so that new records are appended after the last intact one.
+    pub fn open_with_hasher(dir: impl AsRef<Path>, hasher: S) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let recovered = recover_dir::<K, V, S>(&dir, hasher)?;
+
+        let (journal, journal_len) = match recovered.journal_len {
+            Some(len) => {
+                let file = OpenOptions::new()
+                    .append(true)
+                    .open(dir.join(JOURNAL_FILE))?;
+                file.set_len(len)?;
+                (file, len)
+            }
+            None => (
+                create_journal(&dir, JOURNAL_FILE, recovered.epoch)?,
+                HEADER_LEN as u64,
+            ),
+        };
+
+        Ok(Self {
+            map: recovered.map,
+            dir,
+            journal: Some(journal),
+            journal_len,
+            epoch: recovered.epoch,
+        })
+    }
+
+    /// The persistence directory.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The number of checkpoints taken in this directory.
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// Size of the current journal in bytes, including its header.
+    pub fn journal_len(&self) -> u64 {
+        self.journal_len
+    }
+
+    /// Push a value into the foreground layer, journaling the change first.
+    ///
+    /// See [`OverlayMap::push`].
+    pub fn push(&mut self, key: K, value: V) -> io::Result<bool> {
+        self.append(OP_PUSH, &key, Some(&value))?;
+        Ok(self.map.push(key, value))
+    }
+
+    /// Swap a value into the foreground layer, journaling the change first.
+    ///
+    /// See [`OverlayMap::swap`].
+    pub fn swap(&mut self, key: K, value: V) -> io::Result<Option<V>> {
+        self.append(OP_SWAP, &key, Some(&value))?;
+        Ok(self.map.swap(key, value))
+    }
+
+    /// Pull the foreground value for a key, journaling the change first.
+    ///
+    /// Nothing is journaled if the key is absent.
+    ///
+    /// See [`OverlayMap::pull`].
+    pub fn pull(&mut self, key: &K) -> io::Result<Option<V>> {
+        if self.map.fg(key).is_none() {
+            return Ok(None);
+        }
+        self.append(OP_PULL, key, None)?;
+        Ok(self.map.pull(key))
+    }
+
+    /// Flip the foreground and background values for a key, journaling the change first.
+    ///
+    /// Nothing is journaled if the key does not have both values.
+    ///
+    /// See [`OverlayMap::flip`].
+    pub fn flip(&mut self, key: &K) -> io::Result<()> {
+        if self.map.bg(key).is_none() {
+            return Ok(());
+        }
+        self.append(OP_FLIP, key, None)?;
+        self.map.flip(key);
+        Ok(())
+    }
+
+    /// Remove a key and both of its values, journaling the change first.
+    ///
+    /// Nothing is journaled if the key is absent.
+    ///
+    /// See [`OverlayMap::remove`].
+    pub fn remove(&mut self, key: &K) -> io::Result<Option<Overlay<V>>> {
+        if self.map.fg(key).is_none() {
+            return Ok(None);
+        }
+        self.append(OP_REMOVE, key, None)?;
+        Ok(self.map.remove(key))
+    }
+
+    /// Apply an [`Op`], journaling it first.
+    ///
+    /// See [`OverlayMap::apply`].
+    pub fn apply(&mut self, op: Op<K, V>) -> io::Result<OpResult<V>> {
+        let mut payload = Vec::new();
+        op.encode(&mut payload);
+        self.append_record(frame_record(payload)?)?;
+        Ok(self.map.apply(op))
+    }
+
+    /// Writes a snapshot of the whole map and starts a new, empty journal.
+    ///
+    /// The new journal and snapshot are both written to temporary files first, so the previous
+    /// snapshot and journal stay valid, and in use, until both are complete. In the unlikely
+    /// case that the snapshot is renamed into place but the journal is not, every change is
+    /// still safe in the snapshot, but further changes fail until a checkpoint succeeds.
+    pub fn checkpoint(&mut self) -> io::Result<()> {
+        let epoch = self.epoch + 1;
+        let journal = create_journal(&self.dir, JOURNAL_TMP_FILE, epoch)?;
+        if let Err(err) = write_snapshot(&self.dir, &self.map, epoch) {
+            let _ = fs::remove_file(self.dir.join(JOURNAL_TMP_FILE));
+            return Err(err);
+        }
+
+        // The snapshot holds every journaled change, so the old journal is stale from here on.
+        self.epoch = epoch;
+        self.journal = None;
+        fs::rename(self.dir.join(JOURNAL_TMP_FILE), self.dir.join(JOURNAL_FILE))?;
+        sync_dir(&self.dir);
+        self.journal = Some(journal);
+        self.journal_len = HEADER_LEN as u64;
+        Ok(())
+    }
+
+    /// Flushes the journal to stable storage.
+    pub fn sync(&self) -> io::Result<()> {
+        self.journal()?.sync_data()
+    }
+
+    /// Consumes the durable map, returning the in-memory map.
+    ///
+    /// Changes made after the last journaled operation are not persisted.
+    pub fn into_inner(self) -> OverlayMap<K, V, S> {
+        self.map
+    }
+
+    fn append(&mut self, tag: u8, key: &K, value: Option<&V>) -> io::Result<()> {
+        let mut payload = Vec::new();
+        encode_op(&mut payload, tag, key, value);
+        self.append_record(frame_record(payload)?)
+    }
+
+    fn journal(&self) -> io::Result<&File> {
+        self.journal.as_ref().ok_or_else(|| {
+            io::Error::other("journal was not replaced by the last checkpoint; checkpoint again")
+        })
+    }
+
+    fn append_record(&mut self, record: Vec<u8>) -> io::Result<()> {
+        let mut journal = self.journal()?;
+        if let Err(err) = journal.write_all(&record) {
+            // Cut off a partial write so later records are not stranded behind it.
+            let _ = journal.set_len(self.journal_len);
+            return Err(err);
+        }
+        self.journal_len += record.len() as u64;
+        Ok(())
+    }
+}
+
+impl<K, V, S> Deref for DurableOverlayMap<K, V, S>
+where
+    K: Eq + Hash,
+{
+    type Target = OverlayMap<K, V, S>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    /// A fresh directory under the system temp dir, removed on drop.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            static NEXT: AtomicUsize = AtomicUsize::new(0);
+            let name = format!(
+                "overlay-persist-{}-{}",
+                std::process::id(),
+                NEXT.fetch_add(1, Ordering::Relaxed)
+            );
+            let dir = std::env::temp_dir().join(name);
+            let _ = fs::remove_dir_all(&dir);
+            Self(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    type Map = OverlayMap<u32, String>;
+
+    fn layers(map: &Map) -> Vec<(u32, Option<String>, Option<String>)> {
+        let mut layers: Vec<_> = map
+            .iter()
+            .map(|(k, e)| (*k, e.fg().cloned(), e.bg().cloned()))
+            .collect();
+        layers.sort();
+        layers
+    }
+
+    #[test]
+    fn round_trips_both_layers_through_snapshot_and_journal() {
+        let tmp = TempDir::new();
+        let mut durable = DurableOverlayMap::<u32, String>::open(&tmp.0).unwrap();
+        durable.push(1, "a".into()).unwrap();
+        durable.push(1, "b".into()).unwrap();
+        durable.push(2, "x".into()).unwrap();
+        durable.checkpoint().unwrap();
+        durable.swap(2, "y".into()).unwrap();
+        durable.flip(&1).unwrap();
+        durable.pull(&3).unwrap();
+        durable.push(4, "z".into()).unwrap();
+        durable.apply(Op::Remove(4)).unwrap();
+        durable.remove(&5).unwrap();
+        durable.sync().unwrap();
+
+        let expected = layers(&durable);
+        drop(durable);
+        assert_eq!(layers(&Map::recover(&tmp.0).unwrap()), expected);
+
+        let reopened = DurableOverlayMap::<u32, String>::open(&tmp.0).unwrap();
+        assert_eq!(reopened.epoch(), 1);
+        assert_eq!(layers(&reopened), expected);
+    }
+
+    #[test]
+    fn failed_checkpoint_keeps_journaling_into_the_current_epoch() {
+        let tmp = TempDir::new();
+        let mut durable = DurableOverlayMap::<u32, String>::open(&tmp.0).unwrap();
+        durable.push(1, "a".into()).unwrap();
+
+        // A directory in the way makes creating the new journal fail.
+        fs::create_dir(tmp.0.join(JOURNAL_TMP_FILE)).unwrap();
+        assert!(durable.checkpoint().is_err());
+        fs::remove_dir(tmp.0.join(JOURNAL_TMP_FILE)).unwrap();
+        durable.push(1, "b".into()).unwrap();
+
+        // Same for the snapshot.
+        fs::create_dir(tmp.0.join(SNAPSHOT_TMP_FILE)).unwrap();
+        assert!(durable.checkpoint().is_err());
+        assert!(!tmp.0.join(JOURNAL_TMP_FILE).exists());
+        durable.push(2, "c".into()).unwrap();
+        assert_eq!(durable.epoch(), 0);
+
+        let expected = layers(&durable);
+        drop(durable);
+        assert_eq!(layers(&Map::recover(&tmp.0).unwrap()), expected);
+    }
+
+    #[test]
+    fn truncated_journal_recovers_every_complete_record() {
+        let tmp = TempDir::new();
+        let mut durable = DurableOverlayMap::<u32, String>::open(&tmp.0).unwrap();
+        let mut states = vec![(durable.journal_len(), layers(&durable))];
+        for i in 0..6u32 {
+            durable.push(i % 2, format!("v{i}")).unwrap();
+            states.push((durable.journal_len(), layers(&durable)));
+            if i == 3 {
+                durable.flip(&0).unwrap();
+                states.push((durable.journal_len(), layers(&durable)));
+            }
+            if i == 4 {
+                durable.swap(1, "s".into()).unwrap();
+                states.push((durable.journal_len(), layers(&durable)));
+                durable.pull(&1).unwrap();
+                states.push((durable.journal_len(), layers(&durable)));
+            }
+        }
+        drop(durable);
+
+        let journal = tmp.0.join(JOURNAL_FILE);
+        let full = fs::read(&journal).unwrap();
+        for cut in 0..=full.len() {
+            fs::write(&journal, &full[..cut]).unwrap();
+            let recovered = Map::recover(&tmp.0).unwrap();
+            let expected = states
+                .iter()
+                .rev()
+                .find(|(len, _)| *len <= cut as u64)
+                .map_or_else(Vec::new, |(_, layers)| layers.clone());
+            assert_eq!(layers(&recovered), expected, "journal cut at {cut} bytes");
+        }
+
+        // Reopening cuts off the torn tail, so new records follow the last intact one.
+        fs::write(&journal, &full[..full.len() - 3]).unwrap();
+        let mut durable = DurableOverlayMap::<u32, String>::open(&tmp.0).unwrap();
+        durable.push(7, "after".into()).unwrap();
+        drop(durable);
+        let recovered = Map::recover(&tmp.0).unwrap();
+        assert_eq!(recovered.fg(&7).map(String::as_str), Some("after"));
+        assert_eq!(recovered.len(), 3);
+    }
+
+    #[test]
+    fn corrupt_record_stops_replay() {
+        let tmp = TempDir::new();
+        let mut durable = DurableOverlayMap::<u32, String>::open(&tmp.0).unwrap();
+        durable.push(1, "a".into()).unwrap();
+        let first = durable.journal_len() as usize;
+        durable.push(2, "b".into()).unwrap();
+        durable.push(3, "c".into()).unwrap();
+        drop(durable);
+
+        let journal = tmp.0.join(JOURNAL_FILE);
+        let mut bytes = fs::read(&journal).unwrap();
+        bytes[first + RECORD_HEADER_LEN + 2] ^= 0xFF;
+        fs::write(&journal, bytes).unwrap();
+
+        let recovered = Map::recover(&tmp.0).unwrap();
+        assert_eq!(recovered.len(), 1);
+        assert!(recovered.fg(&1).is_some());
+    }
+
+    #[test]
+    fn stale_journal_is_not_replayed_after_checkpoint_crash() {
+        let tmp = TempDir::new();
+        let mut durable = DurableOverlayMap::<u32, String>::open(&tmp.0).unwrap();
+        durable.push(1, "a".into()).unwrap();
+        durable.push(1, "b".into()).unwrap();
+        let journal = tmp.0.join(JOURNAL_FILE);
+        let old_journal = fs::read(&journal).unwrap();
+        durable.checkpoint().unwrap();
+        drop(durable);
+
+        // Crash after the snapshot rename, before the new journal was created.
+        fs::write(&journal, &old_journal).unwrap();
+        let recovered = Map::recover(&tmp.0).unwrap();
+        assert_eq!(recovered.fg(&1).map(String::as_str), Some("b"));
+        assert_eq!(recovered.bg(&1).map(String::as_str), Some("a"));
+
+        // A torn snapshot is reported rather than silently dropped.
+        let snapshot = tmp.0.join(SNAPSHOT_FILE);
+        let bytes = fs::read(&snapshot).unwrap();
+        fs::write(&snapshot, &bytes[..bytes.len() - 1]).unwrap();
+        let err = Map::recover(&tmp.0).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,554 @@
+use std::{
+    fmt,
+    hash::{BuildHasher, Hash},
+    mem, slice,
+    sync::Arc,
+};
+
+use hashbrown::DefaultHashBuilder;
+
+use crate::Overlay;
+
+/// Bits of the hash consumed by each level of the trie.
+const BITS: u32 = 5;
+const MASK: u64 = (1 << BITS) - 1;
+
+/// A node of the hash array mapped trie.
+///
+/// Leaves hold every entry whose key has the same full hash, so hash collisions are handled by
+/// a short linear scan. Branches hold one child per occupied 5-bit hash fragment, packed in
+/// bitmap order.
+enum Node<K, V> {
+    Branch {
+        bitmap: u32,
+        children: Vec<Arc<Node<K, V>>>,
+    },
+    Leaf {
+        hash: u64,
+        entries: Vec<(K, Overlay<Arc<V>>)>,
+    },
+}
+
+impl<K: Clone, V> Clone for Node<K, V> {
+    fn clone(&self) -> Self {
+        match self {
+            Node::Branch { bitmap, children } => Node::Branch {
+                bitmap: *bitmap,
+                children: children.clone(),
+            },
+            Node::Leaf { hash, entries } => Node::Leaf {
+                hash: *hash,
+                entries: entries.clone(),
+            },
+        }
+    }
+}
+
+#[inline]
+fn fragment(hash: u64, shift: u32) -> u32 {
+    ((hash >> shift) & MASK) as u32
+}
+
+impl<K, V> Node<K, V>
+where
+    K: Eq + Clone,
+{
+    fn empty() -> Self {
+        Node::Branch {
+            bitmap: 0,
+            children: Vec::new(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        match self {
+            Node::Branch { children, .. } => children.is_empty(),
+            Node::Leaf { entries, .. } => entries.is_empty(),
+        }
+    }
+
+    fn get(&self, hash: u64, key: &K) -> Option<&Overlay<Arc<V>>> {
+        let mut node = self;
+        let mut shift = 0;
+        loop {
+            match node {
+                Node::Branch { bitmap, children } => {
+                    let bit = 1 << fragment(hash, shift);
+                    if bitmap & bit == 0 {
+                        return None;
+                    }
+                    node = &children[(bitmap & (bit - 1)).count_ones() as usize];
+                    shift += BITS;
+                }
+                Node::Leaf { hash: h, entries } => {
+                    if *h != hash {
+                        return None;
+                    }
+                    return entries.iter().find(|(k, _)| k == key).map(|(_, e)| e);
+                }
+            }
+        }
+    }
+
+    /// Returns the entry for `key`, inserting an empty one if it is absent, and copying every
+    /// node on the path that is shared with a snapshot.
+    ///
+    /// The flag is `true` if the entry was inserted.
+    fn entry(node: &mut Arc<Self>, hash: u64, shift: u32, key: K) -> (&mut Overlay<Arc<V>>, bool) {
+        let node = Arc::make_mut(node);
+        if let Node::Leaf { hash: h, .. } = node {
+            if *h != hash {
+                // Two different hashes always differ in some fragment, so pushing the leaf one
+                // level down eventually separates them.
+                let leaf_hash = *h;
+                let leaf = mem::replace(node, Node::empty());
+                *node = Node::Branch {
+                    bitmap: 1 << fragment(leaf_hash, shift),
+                    children: vec![Arc::new(leaf)],
+                };
+            }
+        }
+
+        match node {
+            Node::Branch { bitmap, children } => {
+                let bit = 1 << fragment(hash, shift);
+                let pos = (*bitmap & (bit - 1)).count_ones() as usize;
+                if *bitmap & bit == 0 {
+                    *bitmap |= bit;
+                    let leaf = Node::Leaf {
+                        hash,
+                        entries: Vec::with_capacity(1),
+                    };
+                    children.insert(pos, Arc::new(leaf));
+                }
+                Self::entry(&mut children[pos], hash, shift + BITS, key)
+            }
+            Node::Leaf { entries, .. } => match entries.iter().position(|(k, _)| *k == key) {
+                Some(i) => (&mut entries[i].1, false),
+                None => {
+                    entries.push((key, Overlay::new_empty()));
+                    (&mut entries.last_mut().expect("just pushed").1, true)
+                }
+            },
+        }
+    }
+
+    /// Applies `f` to the entry for `key`, if present, and removes the entry if it is left
+    /// empty. Nodes on the path are copied if they are shared, and branches that are left
+    /// holding a single leaf are collapsed into it.
+    ///
+    /// Returns the result of `f` and whether the entry was removed.
+    fn update<R>(
+        node: &mut Arc<Self>,
+        hash: u64,
+        shift: u32,
+        key: &K,
+        f: impl FnOnce(&mut Overlay<Arc<V>>) -> R,
+    ) -> Option<(R, bool)> {
+        match Arc::make_mut(node) {
+            Node::Branch { bitmap, children } => {
+                let bit = 1 << fragment(hash, shift);
+                if *bitmap & bit == 0 {
+                    return None;
+                }
+                let pos = (*bitmap & (bit - 1)).count_ones() as usize;
+                let result = Self::update(&mut children[pos], hash, shift + BITS, key, f)?;
+                if children[pos].is_empty() {
+                    children.remove(pos);
+                    *bitmap &= !bit;
+                } else if let Node::Branch {
+                    children: inner, ..
+                } = &*children[pos]
+                {
+                    if let [only] = inner.as_slice() {
+                        if matches!(**only, Node::Leaf { .. }) {
+                            children[pos] = Arc::clone(only);
+                        }
+                    }
+                }
+                Some(result)
+            }
+            Node::Leaf { hash: h, entries } => {
+                if *h != hash {
+                    return None;
+                }
+                let i = entries.iter().position(|(k, _)| k == key)?;
+                let result = f(&mut entries[i].1);
+                let removed = entries[i].1.is_empty();
+                if removed {
+                    entries.swap_remove(i);
+                }
+                Some((result, removed))
+            }
+        }
+    }
+}
+
+/// A persistent [`OverlayMap`](crate::OverlayMap) with structural sharing and O(1) snapshots.
+///
+/// The entries are stored in a hash array mapped trie whose nodes are reference counted.
+/// [`snapshot`](Self::snapshot) (and `clone`) copies a single pointer, and the snapshot and
+/// the original then share every node. A later push, swap, pull or flip copies only the
+/// nodes on the path to the touched key — at most a dozen small nodes — and leaves the rest
+/// shared, so each version costs memory in proportion to what changed in it.
+///
+/// Every key has the usual foreground and background layers of an [`Overlay`]. Values are
+/// stored as `Arc<V>` so that versions can share them as well, which is why
+/// [`pull`](Self::pull) and [`swap`](Self::swap) hand back an `Arc<V>`. Keys are cloned
+/// when a node holding them is copied, so they should be cheap to clone.
+///
+/// # Example
+///
+/// ```
+/// use overlay_map::PersistentOverlayMap;
+///
+/// let mut world = PersistentOverlayMap::new();
+/// world.push("pos", 0);
+///
+/// // Branch the simulation.
+/// let mut branch = world.snapshot();
+/// branch.push("pos", 10);
+/// world.push("pos", -10);
+///
+/// assert_eq!(world.fg(&"pos"), Some(&-10));
+/// assert_eq!(branch.fg(&"pos"), Some(&10));
+/// assert_eq!(branch.bg(&"pos"), Some(&0));
+/// ```
+pub struct PersistentOverlayMap<K, V, S = DefaultHashBuilder> {
+    root: Arc<Node<K, V>>,
+    len: usize,
+    hasher: S,
+}
+
+impl<K, V> PersistentOverlayMap<K, V, DefaultHashBuilder>
+where
+    K: Eq + Hash + Clone,
+{
+    /// Creates a new, empty `PersistentOverlayMap` using the default hasher.
+    pub fn new() -> Self {
+        Self::with_hasher(DefaultHashBuilder::default())
+    }
+}
+
+impl<K, V, S> PersistentOverlayMap<K, V, S>
+where
+    K: Eq + Hash + Clone,
+    S: BuildHasher,
+{
+    /// Creates an empty `PersistentOverlayMap` that will use the given hasher.
+    pub fn with_hasher(hasher: S) -> Self {
+        Self {
+            root: Arc::new(Node::empty()),
+            len: 0,
+            hasher,
+        }
+    }
+
+    /// Returns a snapshot of the map in O(1).
+    ///
+    /// The snapshot and the map are independent: changes to either are not visible in the
+    /// other. They share all of their nodes until one of them is changed.
+    pub fn snapshot(&self) -> Self
+    where
+        S: Clone,
+    {
+        Self {
+            root: Arc::clone(&self.root),
+            len: self.len,
+            hasher: self.hasher.clone(),
+        }
+    }
+
+    /// Returns `true` if both maps are versions that share their entire structure, such as a
+    /// map and an unmodified snapshot of it.
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.root, &other.root)
+    }
+
+    /// Number of unique keys in the map.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Check if the map is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    #[inline]
+    fn get(&self, key: &K) -> Option<&Overlay<Arc<V>>> {
+        self.root.get(self.hasher.hash_one(key), key)
+    }
+
+    /// Get an immutable reference to the foreground value associated with the key.
+    #[inline]
+    pub fn fg(&self, key: &K) -> Option<&V> {
+        self.get(key).and_then(|entry| entry.fg()).map(Arc::as_ref)
+    }
+
+    /// Get an immutable reference to the background value associated with the key.
+    #[inline]
+    pub fn bg(&self, key: &K) -> Option<&V> {
+        self.get(key).and_then(|entry| entry.bg()).map(Arc::as_ref)
+    }
+
+    /// Push a value into the foreground layer, preserving the previous value in the
+    /// background.
+    ///
+    /// Returns `true` if there was already a foreground value.
+    ///
+    /// See [`OverlayMap::push`](crate::OverlayMap::push).
+    pub fn push(&mut self, key: K, value: V) -> bool {
+        self.swap_arc(key, Arc::new(value)).0
+    }
+
+    /// Swap a value into the foreground layer, returning the evicted background value if
+    /// present.
+    ///
+    /// See [`OverlayMap::swap`](crate::OverlayMap::swap).
+    pub fn swap(&mut self, key: K, value: V) -> Option<Arc<V>> {
+        self.swap_arc(key, Arc::new(value)).1
+    }
+
+    fn swap_arc(&mut self, key: K, value: Arc<V>) -> (bool, Option<Arc<V>>) {
+        let hash = self.hasher.hash_one(&key);
+        let (entry, inserted) = Node::entry(&mut self.root, hash, 0, key);
+        let evicted = entry.swap(value);
+        self.len += inserted as usize;
+        (!inserted, evicted)
+    }
+
+    /// Pulls the foreground value for a key, promoting the background to foreground.
+    ///
+    /// The key is removed once it has no values left.
+    ///
+    /// See [`OverlayMap::pull`](crate::OverlayMap::pull).
+    pub fn pull(&mut self, key: &K) -> Option<Arc<V>> {
+        // Look the key up first, so a miss does not copy any shared nodes.
+        self.get(key)?;
+        let hash = self.hasher.hash_one(key);
+        let (pulled, removed) = Node::update(&mut self.root, hash, 0, key, Overlay::pull)?;
+        self.len -= removed as usize;
+        pulled
+    }
+
+    /// Flips the foreground and background values for the given key, if both are present.
+    ///
+    /// See [`OverlayMap::flip`](crate::OverlayMap::flip).
+    pub fn flip(&mut self, key: &K) {
+        if !self.get(key).is_some_and(Overlay::is_full) {
+            return;
+        }
+        let hash = self.hasher.hash_one(key);
+        Node::update(&mut self.root, hash, 0, key, Overlay::flip_unchecked);
+    }
+
+    /// Get an iterator over the entries of the map, in no particular order.
+    pub fn iter(&self) -> PersistentOverlayIter<'_, K, V> {
+        PersistentOverlayIter {
+            stack: vec![slice::from_ref(&self.root).iter()],
+            leaf: [].iter(),
+        }
+    }
+}
+
+impl<K, V, S> Clone for PersistentOverlayMap<K, V, S>
+where
+    K: Eq + Hash + Clone,
+    S: BuildHasher + Clone,
+{
+    /// Equivalent to [`snapshot`](Self::snapshot).
+    fn clone(&self) -> Self {
+        self.snapshot()
+    }
+}
+
+impl<K, V, S> Default for PersistentOverlayMap<K, V, S>
+where
+    K: Eq + Hash + Clone,
+    S: BuildHasher + Default,
+{
+    fn default() -> Self {
+        Self::with_hasher(S::default())
+    }
+}
+
+impl<K, V, S> fmt::Debug for PersistentOverlayMap<K, V, S>
+where
+    K: Eq + Hash + Clone + fmt::Debug,
+    V: fmt::Debug,
+    S: BuildHasher,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(self.iter().map(|(k, e)| (k, (e.fg(), e.bg()))))
+            .finish()
+    }
+}
+
+impl<K, V, S> Extend<(K, V)> for PersistentOverlayMap<K, V, S>
+where
+    K: Eq + Hash + Clone,
+    S: BuildHasher,
+{
+    /// Pushes each `(key, value)` pair in order.
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.push(key, value);
+        }
+    }
+}
+
+impl<K, V, S> FromIterator<(K, V)> for PersistentOverlayMap<K, V, S>
+where
+    K: Eq + Hash + Clone,
+    S: BuildHasher + Default,
+{
+    /// Creates a map by pushing each `(key, value)` pair in order.
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut map = Self::default();
+        map.extend(iter);
+        map
+    }
+}
+
+/// An iterator over the entries of a [`PersistentOverlayMap`].
+///
+/// Created by [`PersistentOverlayMap::iter`].
+pub struct PersistentOverlayIter<'a, K, V> {
+    stack: Vec<slice::Iter<'a, Arc<Node<K, V>>>>,
+    leaf: slice::Iter<'a, (K, Overlay<Arc<V>>)>,
+}
+
+impl<'a, K, V> Iterator for PersistentOverlayIter<'a, K, V> {
+    type Item = (&'a K, &'a Overlay<Arc<V>>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((key, entry)) = self.leaf.next() {
+                return Some((key, entry));
+            }
+            let node = loop {
+                let top = self.stack.last_mut()?;
+                match top.next() {
+                    Some(node) => break node,
+                    None => {
+                        self.stack.pop();
+                    }
+                }
+            };
+            match &**node {
+                Node::Branch { children, .. } => self.stack.push(children.iter()),
+                Node::Leaf { entries, .. } => self.leaf = entries.iter(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::hash::{BuildHasherDefault, Hasher};
+
+    use super::*;
+    use crate::OverlayMap;
+
+    /// Sends every key to the same hash, so all entries collide in one leaf.
+    #[derive(Default)]
+    struct Collide;
+
+    impl Hasher for Collide {
+        fn finish(&self) -> u64 {
+            7
+        }
+
+        fn write(&mut self, _: &[u8]) {}
+    }
+
+    fn layers<S: BuildHasher>(
+        map: &PersistentOverlayMap<u32, u32, S>,
+    ) -> Vec<(u32, Option<u32>, Option<u32>)> {
+        let mut layers: Vec<_> = map
+            .iter()
+            .map(|(k, e)| (*k, e.fg().map(|v| **v), e.bg().map(|v| **v)))
+            .collect();
+        layers.sort();
+        layers
+    }
+
+    fn run_against_overlay_map<S: BuildHasher + Default + Clone>() {
+        let mut persistent = PersistentOverlayMap::<u32, u32, S>::default();
+        let mut reference = OverlayMap::<u32, u32>::new();
+        let mut snapshots = Vec::new();
+        let mut rng = 0x9e37_79b9_u32;
+        for step in 0..3000 {
+            rng ^= rng << 13;
+            rng ^= rng >> 17;
+            rng ^= rng << 5;
+            let key = rng % 200;
+            match rng % 5 {
+                0 | 1 => assert_eq!(persistent.push(key, step), reference.push(key, step)),
+                2 => assert_eq!(
+                    persistent.swap(key, step).map(|v| *v),
+                    reference.swap(key, step)
+                ),
+                3 => assert_eq!(persistent.pull(&key).map(|v| *v), reference.pull(&key)),
+                _ => {
+                    persistent.flip(&key);
+                    reference.flip(&key);
+                }
+            }
+            if step % 500 == 0 {
+                snapshots.push((persistent.snapshot(), layers(&persistent)));
+            }
+        }
+
+        let mut expected: Vec<_> = reference
+            .iter()
+            .map(|(k, e)| (*k, e.fg().copied(), e.bg().copied()))
+            .collect();
+        expected.sort();
+        assert_eq!(layers(&persistent), expected);
+        assert_eq!(persistent.len(), reference.len());
+
+        for (snapshot, layers_then) in snapshots {
+            assert_eq!(layers(&snapshot), layers_then);
+        }
+    }
+
+    #[test]
+    fn matches_overlay_map_and_preserves_snapshots() {
+        run_against_overlay_map::<DefaultHashBuilder>();
+        run_against_overlay_map::<BuildHasherDefault<Collide>>();
+    }
+
+    #[test]
+    fn untouched_subtrees_stay_shared() {
+        let mut map: PersistentOverlayMap<u32, u32> = (0..1000).map(|i| (i, i)).collect();
+        let snapshot = map.snapshot();
+        assert!(map.ptr_eq(&snapshot));
+
+        map.pull(&5000);
+        map.flip(&1);
+        assert!(map.ptr_eq(&snapshot), "misses must not copy nodes");
+
+        map.push(1, 2);
+        assert!(!map.ptr_eq(&snapshot));
+        let (
+            Node::Branch { children: ours, .. },
+            Node::Branch {
+                children: theirs, ..
+            },
+        ) = (&*map.root, &*snapshot.root)
+        else {
+            panic!("root is a branch");
+        };
+        let shared = ours
+            .iter()
+            .zip(theirs)
+            .filter(|(a, b)| Arc::ptr_eq(a, b))
+            .count();
+        assert_eq!(shared, ours.len() - 1);
+        assert_eq!(snapshot.fg(&1), Some(&1));
+        assert_eq!(map.bg(&1), Some(&1));
+    }
+}
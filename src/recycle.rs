@@ -0,0 +1,141 @@
+use crate::OverlayObserver;
+
+/// An [`OverlayObserver`] that keeps evicted values for reuse by
+/// [`OverlayMap::push_with`](crate::OverlayMap::push_with).
+///
+/// Every value the map drops through [`on_evict_bg`](OverlayObserver::on_evict_bg) is kept in
+/// the pool, up to `max_len` values; beyond that, evicted values are dropped as usual. Values
+/// pulled out of the map are returned to the caller, and can be handed back with
+/// [`put`](Self::put) once the caller is done with them.
+///
+/// This is meant for large, reusable values such as `Vec<u8>` or `String` buffers, where
+/// refilling an old allocation is much cheaper than freeing it and allocating a new one.
+///
+/// # Example
+///
+/// ```
+/// use overlay_map::{OverlayMap, RecyclePool};
+///
+/// let mut map = OverlayMap::new().with_observer(RecyclePool::new(8));
+/// map.push("a", String::from("one"));
+/// map.push("a", String::from("two"));
+/// map.push("a", String::from("three")); // evicts "one" into the pool
+/// assert_eq!(map.observer().len(), 1);
+///
+/// map.push_with("b", |recycled| {
+///     let mut s = recycled.unwrap();
+///     s.clear();
+///     s.push_str("reused");
+///     s
+/// });
+/// assert_eq!(map.fg(&"b").map(String::as_str), Some("reused"));
+/// assert!(map.observer().is_empty());
+/// ```
+#[derive(Debug, Clone)]
+pub struct RecyclePool<V> {
+    values: Vec<V>,
+    max_len: usize,
+}
+
+impl<V> RecyclePool<V> {
+    /// Creates an empty pool that keeps at most `max_len` values.
+    pub fn new(max_len: usize) -> Self {
+        Self {
+            values: Vec::new(),
+            max_len,
+        }
+    }
+
+    /// Number of values currently in the pool.
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Returns `true` if the pool holds no values.
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Maximum number of values the pool keeps.
+    pub fn max_len(&self) -> usize {
+        self.max_len
+    }
+
+    /// Adds a value to the pool, dropping it instead if the pool is full.
+    ///
+    /// Returns `true` if the value was kept.
+    pub fn put(&mut self, value: V) -> bool {
+        if self.values.len() < self.max_len {
+            self.values.push(value);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Takes the most recently added value out of the pool.
+    pub fn take(&mut self) -> Option<V> {
+        self.values.pop()
+    }
+
+    /// Drops every value in the pool.
+    pub fn clear(&mut self) {
+        self.values.clear();
+    }
+}
+
+impl<V> Default for RecyclePool<V> {
+    /// Creates a pool that keeps at most 64 values.
+    fn default() -> Self {
+        Self::new(64)
+    }
+}
+
+impl<K, V> OverlayObserver<K, V> for RecyclePool<V> {
+    #[inline]
+    fn on_evict_bg(&mut self, _key: &K, value: V) {
+        self.put(value);
+    }
+
+    #[inline]
+    fn recycle(&mut self, _key: &K) -> Option<V> {
+        self.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::OverlayMap;
+
+    #[test]
+    fn evicted_buffers_are_refilled_without_allocating() {
+        let mut map = OverlayMap::new().with_observer(RecyclePool::new(1));
+        let buffer = |byte| {
+            move |recycled: Option<Vec<u8>>| {
+                let mut buf = recycled.unwrap_or_else(|| Vec::with_capacity(256));
+                buf.clear();
+                buf.resize(256, byte);
+                buf
+            }
+        };
+
+        map.push_with(0, buffer(0));
+        map.push_with(0, buffer(1));
+        map.push_with(0, buffer(2));
+        map.push(0, vec![3]);
+        map.push(0, vec![4]);
+        assert_eq!(map.observer().len(), 1);
+
+        let pooled = map.observer().values[0].as_ptr();
+        map.push_with(1, buffer(9));
+        assert_eq!(map.fg(&1).map(|buf| buf.as_ptr()), Some(pooled));
+        assert!(map.observer().is_empty());
+
+        map.push(1, vec![]);
+        map.push(1, vec![]);
+        map.push(1, vec![]);
+        assert_eq!(map.observer().len(), 1);
+        assert!(!map.observer_mut().put(vec![]));
+    }
+}
@@ -0,0 +1,676 @@
+use std::{
+    hash::{BuildHasher, Hash},
+    mem::MaybeUninit,
+    ptr,
+};
+
+use hashbrown::{DefaultHashBuilder, HashMap, hash_map::RawEntryMut};
+
+/// A fixed-depth value container that keeps up to `N` versions of a value.
+///
+/// `OverlayStack<T, N>` generalises [`Overlay<T>`](crate::Overlay) to more than two layers.
+/// The most recent value is the **foreground**, the one before it is the **background**, and
+/// older values sit deeper in the stack. Layers are stored in a ring buffer, so pushing, pulling
+/// and swapping never move existing values in memory — once the stack is full, the oldest value
+/// is dropped (or returned, in the case of [`swap`](Self::swap)) to make room. The one exception
+/// is [`flip`](Self::flip), which exchanges the two top values in place.
+///
+/// [`Overlay<T>`](crate::Overlay) remains the optimised `N = 2` case and should be preferred
+/// when one layer of history is enough.
+///
+/// # Examples
+///
+/// ```
+/// use overlay_map::OverlayStack;
+///
+/// let mut entry = OverlayStack::<_, 3>::new_fg("a");
+/// entry.push("b");
+/// entry.push("c");
+///
+/// assert_eq!(entry.fg(), Some(&"c"));
+/// assert_eq!(entry.bg(), Some(&"b"));
+/// assert_eq!(entry.get(2), Some(&"a"));
+///
+/// entry.push("d"); // "a" is dropped
+/// assert_eq!(entry.iter().collect::<Vec<_>>(), vec![&"d", &"c", &"b"]);
+/// ```
+#[derive(Debug)]
+pub struct OverlayStack<T, const N: usize> {
+    top: usize,
+    len: usize,
+    slots: [MaybeUninit<T>; N],
+}
+
+impl<T, const N: usize> OverlayStack<T, N> {
+    /// Creates a new `OverlayStack` with no values.
+    ///
+    /// ```
+    /// use overlay_map::OverlayStack;
+    ///
+    /// let entry: OverlayStack<&str, 4> = OverlayStack::new_empty();
+    /// assert!(entry.is_empty());
+    /// assert_eq!(entry.fg(), None);
+    /// ```
+    pub fn new_empty() -> Self {
+        const { assert!(N > 0, "OverlayStack must have at least one layer") };
+        Self {
+            top: 0,
+            len: 0,
+            slots: [const { MaybeUninit::uninit() }; N],
+        }
+    }
+
+    /// Creates a new `OverlayStack` with a foreground value and no history.
+    ///
+    /// ```
+    /// use overlay_map::OverlayStack;
+    ///
+    /// let entry = OverlayStack::<_, 4>::new_fg("fg");
+    /// assert_eq!(entry.fg(), Some(&"fg"));
+    /// assert_eq!(entry.bg(), None);
+    /// ```
+    pub fn new_fg(val: T) -> Self {
+        let mut stack = Self::new_empty();
+        stack.slots[0] = MaybeUninit::new(val);
+        stack.len = 1;
+        stack
+    }
+
+    /// The maximum number of layers this stack can hold.
+    #[inline]
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// The number of layers currently present.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if no layers are present.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns `true` if all `N` layers are present, meaning the next push will drop the oldest.
+    #[inline]
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    /// Returns a reference to the layer at the given depth, where `0` is the foreground and
+    /// `1` is the background.
+    ///
+    /// ```
+    /// use overlay_map::OverlayStack;
+    ///
+    /// let mut entry = OverlayStack::<_, 4>::new_fg(1);
+    /// entry.push(2);
+    /// assert_eq!(entry.get(0), Some(&2));
+    /// assert_eq!(entry.get(1), Some(&1));
+    /// assert_eq!(entry.get(2), None);
+    /// ```
+    #[inline]
+    pub fn get(&self, depth: usize) -> Option<&T> {
+        if depth < self.len {
+            Some(unsafe { self.slots[self.index(depth)].assume_init_ref() })
+        } else {
+            None
+        }
+    }
+
+    /// Returns a reference to the current foreground value, if present.
+    #[inline]
+    pub fn fg(&self) -> Option<&T> {
+        self.get(0)
+    }
+
+    /// Returns a reference to the foreground value **without checking** if it is present.
+    ///
+    /// # Safety
+    /// This function **assumes** the stack is not empty. Calling this on an empty stack results
+    /// in **undefined behavior**.
+    #[inline]
+    pub fn fg_unchecked(&self) -> &T {
+        unsafe { self.slots[self.top].assume_init_ref() }
+    }
+
+    /// Returns a reference to the background value, if present.
+    #[inline]
+    pub fn bg(&self) -> Option<&T> {
+        self.get(1)
+    }
+
+    /// Push a value into the foreground layer, moving every existing layer one level deeper.
+    ///
+    /// If the stack is already full, the oldest layer is dropped to make room. No cloning is
+    /// performed at any point.
+    ///
+    /// ```
+    /// use overlay_map::OverlayStack;
+    ///
+    /// let mut entry = OverlayStack::<_, 2>::new_fg("a");
+    /// entry.push("b");
+    /// entry.push("c"); // "a" is dropped
+    ///
+    /// assert_eq!(entry.fg(), Some(&"c"));
+    /// assert_eq!(entry.bg(), Some(&"b"));
+    /// ```
+    #[inline]
+    pub fn push(&mut self, val: T) {
+        self.top = self.next(self.top);
+        if self.len == N {
+            unsafe { self.slots[self.top].assume_init_drop() };
+        } else {
+            self.len += 1;
+        }
+        self.slots[self.top] = MaybeUninit::new(val);
+    }
+
+    /// Pull the current foreground value out, promoting the background to foreground.
+    ///
+    /// Returns `None` if the stack is empty.
+    ///
+    /// ```
+    /// use overlay_map::OverlayStack;
+    ///
+    /// let mut entry = OverlayStack::<_, 3>::new_fg("a");
+    /// entry.push("b");
+    ///
+    /// assert_eq!(entry.pull(), Some("b"));
+    /// assert_eq!(entry.fg(), Some(&"a"));
+    /// ```
+    #[inline]
+    pub fn pull(&mut self) -> Option<T> {
+        if self.len == 0 {
+            None
+        } else {
+            Some(self.pull_unchecked())
+        }
+    }
+
+    /// Pull the current foreground value without checking if it is present.
+    ///
+    /// # Safety
+    /// The caller must ensure the stack is not empty. If it is, this will result in undefined
+    /// behavior.
+    #[inline]
+    pub fn pull_unchecked(&mut self) -> T {
+        let val = unsafe { self.slots[self.top].assume_init_read() };
+        self.top = self.prev(self.top);
+        self.len -= 1;
+        val
+    }
+
+    /// Swap in a new foreground value, returning the oldest layer if the stack was full.
+    ///
+    /// The slot of the evicted layer is reused for the new value, so nothing else moves. If
+    /// the stack was not full, this behaves like [`push`](Self::push) and `None` is returned.
+    ///
+    /// ```
+    /// use overlay_map::OverlayStack;
+    ///
+    /// let mut entry = OverlayStack::<_, 3>::new_fg("a");
+    /// entry.push("b");
+    /// assert_eq!(entry.swap("c"), None);
+    /// assert_eq!(entry.swap("d"), Some("a"));
+    /// assert_eq!(entry.iter().collect::<Vec<_>>(), vec![&"d", &"c", &"b"]);
+    /// ```
+    #[inline]
+    pub fn swap(&mut self, val: T) -> Option<T> {
+        if self.len == N {
+            self.top = self.next(self.top);
+            let evicted = unsafe { self.slots[self.top].assume_init_read() };
+            self.slots[self.top] = MaybeUninit::new(val);
+            Some(evicted)
+        } else {
+            self.push(val);
+            None
+        }
+    }
+
+    /// Exchanges the foreground and background layers, if both are present.
+    ///
+    /// Deeper layers are left untouched. If fewer than two layers are present, the stack
+    /// remains unchanged.
+    ///
+    /// Unlike [`Overlay::flip`](crate::Overlay::flip), which only toggles a flag, this swaps
+    /// the two values in memory: the ring buffer orders layers by slot, so the foreground and
+    /// background can't trade places without the deeper layers losing their order.
+    ///
+    /// ```
+    /// use overlay_map::OverlayStack;
+    ///
+    /// let mut entry = OverlayStack::<_, 3>::new_fg("a");
+    /// entry.push("b");
+    /// entry.flip();
+    ///
+    /// assert_eq!(entry.fg(), Some(&"a"));
+    /// assert_eq!(entry.bg(), Some(&"b"));
+    /// ```
+    #[inline]
+    pub fn flip(&mut self) {
+        if self.len >= 2 {
+            let bg = self.prev(self.top);
+            let base = self.slots.as_mut_ptr();
+            unsafe { ptr::swap(base.add(self.top), base.add(bg)) };
+        }
+    }
+
+    /// Clears the stack, dropping every layer.
+    #[inline]
+    pub fn clear(&mut self) {
+        while self.pull().is_some() {}
+    }
+
+    /// Get an iterator over the layers, from the foreground to the oldest.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &T> {
+        (0..self.len).map(|depth| unsafe { self.slots[self.index(depth)].assume_init_ref() })
+    }
+
+    #[inline]
+    fn index(&self, depth: usize) -> usize {
+        (self.top + N - depth) % N
+    }
+
+    #[inline]
+    fn next(&self, idx: usize) -> usize {
+        if idx + 1 == N { 0 } else { idx + 1 }
+    }
+
+    #[inline]
+    fn prev(&self, idx: usize) -> usize {
+        if idx == 0 { N - 1 } else { idx - 1 }
+    }
+}
+
+impl<T, const N: usize> Default for OverlayStack<T, N> {
+    fn default() -> Self {
+        Self::new_empty()
+    }
+}
+
+impl<T, const N: usize> From<T> for OverlayStack<T, N> {
+    fn from(value: T) -> Self {
+        Self::new_fg(value)
+    }
+}
+
+impl<T: Clone, const N: usize> Clone for OverlayStack<T, N> {
+    fn clone(&self) -> Self {
+        let mut clone = Self::new_empty();
+        for val in self.iter().rev() {
+            clone.push(val.clone());
+        }
+        clone
+    }
+}
+
+impl<T: PartialEq, const N: usize> PartialEq for OverlayStack<T, N> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.iter().eq(other.iter())
+    }
+}
+
+impl<T: Eq, const N: usize> Eq for OverlayStack<T, N> {}
+
+impl<T, const N: usize> Drop for OverlayStack<T, N> {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+pub struct OverlayStackIntoIter<T, const N: usize> {
+    stack: OverlayStack<T, N>,
+}
+
+impl<T, const N: usize> Iterator for OverlayStackIntoIter<T, N> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.stack.pull()
+    }
+}
+
+impl<T, const N: usize> IntoIterator for OverlayStack<T, N> {
+    type Item = T;
+    type IntoIter = OverlayStackIntoIter<T, N>;
+
+    /// Creates an iterator over the layers, from the foreground to the oldest.
+    ///
+    /// ```
+    /// use overlay_map::OverlayStack;
+    ///
+    /// let mut entry = OverlayStack::<_, 3>::new_fg("a");
+    /// entry.push("b");
+    /// let values: Vec<_> = entry.into_iter().collect();
+    /// assert_eq!(values, vec!["b", "a"]);
+    /// ```
+    fn into_iter(self) -> Self::IntoIter {
+        OverlayStackIntoIter { stack: self }
+    }
+}
+
+/// A map where each key holds up to `N` versions of its value.
+///
+/// `OverlayStackMap` is the [`OverlayStack`] counterpart of [`OverlayMap`](crate::OverlayMap):
+/// pushing a value for an existing key moves every previous version one level deeper instead
+/// of discarding all but the last one. Keys are removed once their last version is pulled.
+///
+/// # Example
+///
+/// ```
+/// use overlay_map::OverlayStackMap;
+///
+/// let mut map = OverlayStackMap::<_, _, 3>::new();
+/// map.push("doc", "v1");
+/// map.push("doc", "v2");
+/// map.push("doc", "v3");
+///
+/// assert_eq!(map.fg(&"doc"), Some(&"v3"));
+/// assert_eq!(map.get(&"doc", 2), Some(&"v1"));
+///
+/// assert_eq!(map.pull(&"doc"), Some("v3"));
+/// assert_eq!(map.pull(&"doc"), Some("v2"));
+/// assert_eq!(map.fg(&"doc"), Some(&"v1"));
+/// ```
+#[derive(Debug, Default)]
+pub struct OverlayStackMap<K, V, const N: usize, S = DefaultHashBuilder>
+where
+    K: Eq + Hash,
+{
+    map: HashMap<K, OverlayStack<V, N>, S>,
+}
+
+impl<K, V, const N: usize> OverlayStackMap<K, V, N, DefaultHashBuilder>
+where
+    K: Eq + Hash,
+{
+    /// Creates a new, empty `OverlayStackMap` using the default hasher.
+    pub fn new() -> Self {
+        Self::with_hasher(DefaultHashBuilder::default())
+    }
+}
+
+impl<K, V, const N: usize, S> OverlayStackMap<K, V, N, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher + Default,
+{
+    /// Creates an empty `OverlayStackMap` with the specified capacity and default hasher.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_and_hasher(capacity, Default::default())
+    }
+
+    /// Creates an empty `OverlayStackMap` that will use the given hasher.
+    pub fn with_hasher(hasher: S) -> Self {
+        Self {
+            map: HashMap::with_hasher(hasher),
+        }
+    }
+
+    /// Creates an empty `OverlayStackMap` with the specified capacity and hasher.
+    pub fn with_capacity_and_hasher(capacity: usize, hasher: S) -> Self {
+        Self {
+            map: HashMap::with_capacity_and_hasher(capacity, hasher),
+        }
+    }
+
+    /// Number of unique keys in the map.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Check if the map is empty.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Get an immutable reference to the foreground value associated with the key.
+    #[inline]
+    pub fn fg(&self, key: &K) -> Option<&V> {
+        self.map.get(key).map(|entry| entry.fg_unchecked())
+    }
+
+    /// Get an immutable reference to the background value associated with the key.
+    #[inline]
+    pub fn bg(&self, key: &K) -> Option<&V> {
+        self.map.get(key).and_then(|entry| entry.bg())
+    }
+
+    /// Get an immutable reference to the value at the given depth for the key, where `0` is
+    /// the foreground.
+    #[inline]
+    pub fn get(&self, key: &K, depth: usize) -> Option<&V> {
+        self.map.get(key).and_then(|entry| entry.get(depth))
+    }
+
+    /// Get the full stack of versions associated with the key.
+    #[inline]
+    pub fn stack(&self, key: &K) -> Option<&OverlayStack<V, N>> {
+        self.map.get(key)
+    }
+
+    /// Push a value into the foreground layer, moving the previous versions one level deeper.
+    ///
+    /// The oldest version is dropped if the key already holds `N` versions.
+    ///
+    /// Returns `true` if there was already a foreground value.
+    #[inline]
+    pub fn push(&mut self, key: K, value: V) -> bool {
+        match self.map.raw_entry_mut().from_key(&key) {
+            RawEntryMut::Occupied(mut occupied) => {
+                occupied.get_mut().push(value);
+                true
+            }
+            RawEntryMut::Vacant(vacant) => {
+                vacant.insert(key, OverlayStack::new_fg(value));
+                false
+            }
+        }
+    }
+
+    /// Conditionally push a new value into the foreground based on the current value.
+    ///
+    /// Returns `true` if a new value was pushed.
+    pub fn push_if<F>(&mut self, key: &K, predicate: F) -> bool
+    where
+        F: FnOnce(&V) -> Option<V>,
+    {
+        let entry = match self.map.get_mut(key) {
+            Some(e) => e,
+            None => return false,
+        };
+
+        match predicate(entry.fg_unchecked()) {
+            Some(new) => {
+                entry.push(new);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Pulls the foreground value for a key, promoting the next version to foreground.
+    ///
+    /// The key is removed from the map once its last version is pulled.
+    #[inline]
+    pub fn pull(&mut self, key: &K) -> Option<V> {
+        match self.map.raw_entry_mut().from_key(key) {
+            RawEntryMut::Occupied(mut occupied) => {
+                let entry = occupied.get_mut();
+                let evicted = entry.pull_unchecked();
+                if entry.is_empty() {
+                    occupied.remove();
+                }
+                Some(evicted)
+            }
+            RawEntryMut::Vacant(_) => None,
+        }
+    }
+
+    /// Conditionally pulls the foreground value for a key if the predicate matches it.
+    pub fn pull_if<F>(&mut self, key: &K, predicate: F) -> Option<V>
+    where
+        F: FnOnce(&V) -> bool,
+    {
+        match self.map.raw_entry_mut().from_key(key) {
+            RawEntryMut::Occupied(mut occupied) => {
+                let entry = occupied.get_mut();
+                if predicate(entry.fg_unchecked()) {
+                    let evicted = entry.pull_unchecked();
+                    if entry.is_empty() {
+                        occupied.remove();
+                    }
+                    Some(evicted)
+                } else {
+                    None
+                }
+            }
+            RawEntryMut::Vacant(_) => None,
+        }
+    }
+
+    /// Swap a value into the foreground layer, returning the oldest version if the key
+    /// already held `N` versions.
+    #[inline]
+    pub fn swap(&mut self, key: K, value: V) -> Option<V> {
+        match self.map.raw_entry_mut().from_key(&key) {
+            RawEntryMut::Occupied(mut occupied) => occupied.get_mut().swap(value),
+            RawEntryMut::Vacant(vacant) => {
+                vacant.insert(key, OverlayStack::new_fg(value));
+                None
+            }
+        }
+    }
+
+    /// Conditionally swap a new value into the foreground based on the current value.
+    ///
+    /// The evicted oldest version is returned if present.
+    pub fn swap_if<F>(&mut self, key: &K, predicate: F) -> Option<V>
+    where
+        F: FnOnce(&V) -> Option<V>,
+    {
+        let entry = self.map.get_mut(key)?;
+        match predicate(entry.fg_unchecked()) {
+            Some(new) => entry.swap(new),
+            None => None,
+        }
+    }
+
+    /// Exchanges the foreground and background versions for the given key, if present.
+    pub fn flip(&mut self, key: &K) {
+        if let Some(entry) = self.map.get_mut(key) {
+            entry.flip();
+        }
+    }
+
+    /// Extends the map with a sequence of key-value pairs, counting foreground replacements.
+    pub fn extend_count<I>(&mut self, iter: I) -> usize
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let mut replaced = 0;
+        for (key, val) in iter {
+            replaced += self.push(key, val) as usize;
+        }
+        replaced
+    }
+}
+
+impl<K, V, const N: usize, S> Clone for OverlayStackMap<K, V, N, S>
+where
+    K: Clone + Eq + Hash,
+    V: Clone,
+    S: Clone + BuildHasher,
+{
+    fn clone(&self) -> Self {
+        Self {
+            map: self.map.clone(),
+        }
+    }
+}
+
+impl<K, V, const N: usize, S> PartialEq for OverlayStackMap<K, V, N, S>
+where
+    K: Eq + Hash,
+    V: PartialEq,
+    S: BuildHasher,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.map == other.map
+    }
+}
+
+impl<K, V, const N: usize, S> Eq for OverlayStackMap<K, V, N, S>
+where
+    K: Eq + Hash,
+    V: Eq,
+    S: BuildHasher,
+{
+}
+
+impl<K, V, const N: usize, S> Extend<(K, V)> for OverlayStackMap<K, V, N, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher + Default,
+{
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (k, v) in iter {
+            self.push(k, v);
+        }
+    }
+}
+
+impl<K, V, const N: usize, S> IntoIterator for OverlayStackMap<K, V, N, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    type Item = (K, OverlayStack<V, N>);
+    type IntoIter = hashbrown::hash_map::IntoIter<K, OverlayStack<V, N>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.map.into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ring_wraps_and_drops_oldest() {
+        let mut stack = OverlayStack::<String, 3>::new_empty();
+        for i in 0..10 {
+            stack.push(i.to_string());
+        }
+        assert_eq!(stack.len(), 3);
+        assert_eq!(stack.iter().collect::<Vec<_>>(), ["9", "8", "7"]);
+
+        stack.flip();
+        assert_eq!(stack.swap("10".into()).as_deref(), Some("7"));
+        assert_eq!(
+            stack.clone().into_iter().collect::<Vec<_>>(),
+            ["10", "8", "9"]
+        );
+
+        assert_eq!(stack.pull().as_deref(), Some("10"));
+        stack.push("11".into());
+        assert_eq!(stack.iter().collect::<Vec<_>>(), ["11", "8", "9"]);
+    }
+
+    #[test]
+    fn map_removes_key_after_last_pull() {
+        let mut map = OverlayStackMap::<&str, i32, 4>::new();
+        map.extend([("k", 1), ("k", 2), ("k", 3)]);
+        assert_eq!(map.get(&"k", 2), Some(&1));
+
+        assert_eq!(map.pull(&"k"), Some(3));
+        assert_eq!(map.pull(&"k"), Some(2));
+        assert_eq!(map.pull(&"k"), Some(1));
+        assert!(map.is_empty());
+    }
+}
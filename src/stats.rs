@@ -0,0 +1,411 @@
+use std::{
+    mem::{size_of, size_of_val},
+    rc::Rc,
+    sync::Arc,
+};
+
+use crate::{Overlay, OverlayMap, OverlayObserver, OverlayStorage};
+
+/// An [`OverlayObserver`] that counts every state transition of a map.
+///
+/// Attach it with [`OverlayMap::with_observer`] to turn statistics on; maps without it pay
+/// nothing. For the number of entries with and without a background, which is a property of
+/// the map rather than of its history, see [`OverlayMap::occupancy`].
+///
+/// # Example
+///
+/// ```
+/// use overlay_map::{OverlayMap, OverlayStats};
+///
+/// let mut map = OverlayMap::new().with_observer(OverlayStats::new());
+/// map.push("a", 1);
+/// map.push("a", 2);
+/// map.push("a", 3);
+/// map.swap("a", 4);
+/// map.pull(&"a");
+/// map.pull(&"a");
+///
+/// let stats = map.observer();
+/// assert_eq!((stats.inserts, stats.pushes, stats.swaps), (1, 2, 1));
+/// assert_eq!((stats.evictions, stats.pulls, stats.removals), (1, 2, 1));
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OverlayStats {
+    /// Keys inserted into the map.
+    pub inserts: u64,
+    /// Foreground values pushed onto existing keys, excluding swaps.
+    pub pushes: u64,
+    /// Foreground values swapped into existing keys.
+    pub swaps: u64,
+    /// Foreground values replaced in place, keeping the background.
+    pub replacements: u64,
+    /// Background values set beneath an existing foreground.
+    pub bg_sets: u64,
+    /// Background values taken out from under their foreground.
+    pub bg_takes: u64,
+    /// Values dropped by the map without being returned to the caller.
+    pub evictions: u64,
+    /// Foreground values pulled out of the map.
+    pub pulls: u64,
+    /// Foreground and background flips.
+    pub flips: u64,
+    /// Keys removed from the map.
+    pub removals: u64,
+}
+
+impl OverlayStats {
+    /// Creates a collector with every counter at zero.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Resets every counter to zero.
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+impl<K, V> OverlayObserver<K, V> for OverlayStats {
+    #[inline]
+    fn on_insert(&mut self, _key: &K, _value: &V) {
+        self.inserts += 1;
+    }
+
+    #[inline]
+    fn on_push(&mut self, _key: &K, _value: &V) {
+        self.pushes += 1;
+    }
+
+    #[inline]
+    fn on_swap(&mut self, _key: &K, _value: &V) {
+        self.swaps += 1;
+    }
+
+    #[inline]
+    fn on_replace_fg(&mut self, _key: &K, _value: &V) {
+        self.replacements += 1;
+    }
+
+    #[inline]
+    fn on_set_bg(&mut self, _key: &K, _value: &V) {
+        self.bg_sets += 1;
+    }
+
+    #[inline]
+    fn on_take_bg(&mut self, _key: &K, _value: &V) {
+        self.bg_takes += 1;
+    }
+
+    #[inline]
+    fn on_evict_bg(&mut self, _key: &K, _value: V) {
+        self.evictions += 1;
+    }
+
+    #[inline]
+    fn on_pull(&mut self, _key: &K, _value: &V) {
+        self.pulls += 1;
+    }
+
+    #[inline]
+    fn on_flip(&mut self, _key: &K) {
+        self.flips += 1;
+    }
+
+    #[inline]
+    fn on_remove(&mut self, _key: &K) {
+        self.removals += 1;
+    }
+}
+
+/// The number of entries in a map with and without a background value.
+///
+/// Returned by [`OverlayMap::occupancy`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Occupancy {
+    /// Entries holding only a foreground value.
+    pub fg_only: usize,
+    /// Entries holding both a foreground and a background value.
+    pub with_bg: usize,
+}
+
+impl Occupancy {
+    /// Total number of entries.
+    pub fn entries(&self) -> usize {
+        self.fg_only + self.with_bg
+    }
+}
+
+/// Approximate heap memory used by a map, broken down by where it lives.
+///
+/// Returned by [`OverlayMap::heap_usage`]. All figures are in bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HeapUsage {
+    /// The storage's slots, sized for its capacity, each holding a key and both layers inline.
+    pub table: usize,
+    /// Heap memory owned by the keys.
+    pub keys: usize,
+    /// Heap memory owned by the foreground values.
+    pub fg: usize,
+    /// Heap memory owned by the background values.
+    pub bg: usize,
+}
+
+impl HeapUsage {
+    /// Total heap bytes.
+    pub fn total(&self) -> usize {
+        self.table + self.keys + self.fg + self.bg
+    }
+}
+
+/// Types that can report approximately how many bytes of heap memory they own.
+///
+/// The figure excludes `size_of::<Self>()` itself, which is counted by whatever contains the
+/// value, and ignores allocator overhead. Shared pointers such as [`Rc`] and [`Arc`] report
+/// nothing, since the allocation is not owned by any single handle.
+///
+/// ```
+/// use std::mem::size_of;
+///
+/// use overlay_map::HeapSize;
+///
+/// let names = vec![String::from("ab"), String::with_capacity(10)];
+/// assert_eq!(names.heap_size(), names.capacity() * size_of::<String>() + 2 + 10);
+/// assert_eq!(42u64.heap_size(), 0);
+/// ```
+pub trait HeapSize {
+    /// Heap bytes owned by this value.
+    fn heap_size(&self) -> usize;
+}
+
+macro_rules! impl_heap_size_zero {
+    ($($ty:ty),*) => {
+        $(impl HeapSize for $ty {
+            #[inline]
+            fn heap_size(&self) -> usize {
+                0
+            }
+        })*
+    };
+}
+
+impl_heap_size_zero!(
+    (),
+    bool,
+    char,
+    u8,
+    u16,
+    u32,
+    u64,
+    u128,
+    usize,
+    i8,
+    i16,
+    i32,
+    i64,
+    i128,
+    isize,
+    f32,
+    f64,
+    str
+);
+
+impl<T: ?Sized> HeapSize for &T {
+    #[inline]
+    fn heap_size(&self) -> usize {
+        0
+    }
+}
+
+impl<T: ?Sized> HeapSize for Rc<T> {
+    #[inline]
+    fn heap_size(&self) -> usize {
+        0
+    }
+}
+
+impl<T: ?Sized> HeapSize for Arc<T> {
+    #[inline]
+    fn heap_size(&self) -> usize {
+        0
+    }
+}
+
+impl HeapSize for String {
+    #[inline]
+    fn heap_size(&self) -> usize {
+        self.capacity()
+    }
+}
+
+impl<T: HeapSize> HeapSize for Vec<T> {
+    fn heap_size(&self) -> usize {
+        self.capacity() * size_of::<T>() + self.iter().map(HeapSize::heap_size).sum::<usize>()
+    }
+}
+
+impl<T: HeapSize> HeapSize for [T] {
+    fn heap_size(&self) -> usize {
+        self.iter().map(HeapSize::heap_size).sum()
+    }
+}
+
+impl<T: HeapSize + ?Sized> HeapSize for Box<T> {
+    fn heap_size(&self) -> usize {
+        size_of_val::<T>(self) + T::heap_size(self)
+    }
+}
+
+impl<T: HeapSize> HeapSize for Option<T> {
+    #[inline]
+    fn heap_size(&self) -> usize {
+        self.as_ref().map_or(0, HeapSize::heap_size)
+    }
+}
+
+impl<A: HeapSize, B: HeapSize> HeapSize for (A, B) {
+    #[inline]
+    fn heap_size(&self) -> usize {
+        self.0.heap_size() + self.1.heap_size()
+    }
+}
+
+impl<T: HeapSize> HeapSize for Overlay<T> {
+    /// Heap bytes owned by both layers; the layers themselves are stored inline.
+    fn heap_size(&self) -> usize {
+        self.iter().map(HeapSize::heap_size).sum()
+    }
+}
+
+impl<K, V, S, A, M, O> OverlayMap<K, V, S, A, M, O>
+where
+    M: OverlayStorage<K, V>,
+    O: OverlayObserver<K, V>,
+{
+    /// Counts the entries with and without a background value.
+    ///
+    /// This walks every entry, so it is meant for periodic reporting rather than hot paths.
+    ///
+    /// ```
+    /// use overlay_map::{Occupancy, OverlayMap};
+    ///
+    /// let mut map = OverlayMap::new();
+    /// map.push("a", 1);
+    /// map.push("a", 2);
+    /// map.push("b", 1);
+    /// map.push("c", 1);
+    ///
+    /// assert_eq!(map.occupancy(), Occupancy { fg_only: 2, with_bg: 1 });
+    /// ```
+    pub fn occupancy(&self) -> Occupancy {
+        let with_bg = self.iter().filter(|(_, entry)| entry.is_full()).count();
+        Occupancy {
+            fg_only: self.len() - with_bg,
+            with_bg,
+        }
+    }
+
+    /// Estimates the heap memory used by the map, its keys and both value layers.
+    ///
+    /// The table figure is the storage's [`capacity`](Self::capacity) times the size of one
+    /// inline `(K, Overlay<V>)` slot. It does not include the backend's own bookkeeping, such
+    /// as hash control bytes or tree node headers, so treat it as a lower bound.
+    ///
+    /// ```
+    /// use overlay_map::OverlayMap;
+    ///
+    /// let mut map = OverlayMap::<u32, String>::new();
+    /// map.push(1, String::with_capacity(100));
+    /// map.push(1, String::with_capacity(40));
+    ///
+    /// let usage = map.heap_usage();
+    /// assert_eq!((usage.keys, usage.fg, usage.bg), (0, 40, 100));
+    /// assert!(usage.table > 0);
+    /// ```
+    pub fn heap_usage(&self) -> HeapUsage
+    where
+        K: HeapSize,
+        V: HeapSize,
+    {
+        let mut usage = HeapUsage {
+            table: self.capacity() * size_of::<(K, Overlay<V>)>(),
+            ..HeapUsage::default()
+        };
+        for (key, entry) in self.iter() {
+            usage.keys += key.heap_size();
+            usage.fg += entry.fg().map_or(0, HeapSize::heap_size);
+            usage.bg += entry.bg().map_or(0, HeapSize::heap_size);
+        }
+        usage
+    }
+}
+
+impl<K, V, S, A, M, O> HeapSize for OverlayMap<K, V, S, A, M, O>
+where
+    K: HeapSize,
+    V: HeapSize,
+    M: OverlayStorage<K, V>,
+    O: OverlayObserver<K, V>,
+{
+    fn heap_size(&self) -> usize {
+        self.heap_usage().total()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counters_and_gauges_track_the_map() {
+        let mut map = OverlayMap::new().with_observer(OverlayStats::new());
+        map.extend([(1, vec![1u8; 8]), (2, vec![2; 8]), (1, vec![3; 8])]);
+        map.flip(&1);
+        map.flip(&2);
+        assert_eq!(map.swap_if(&2, |_| Some(vec![4; 8])), None);
+        map.push(2, vec![5; 8]);
+
+        let stats = *map.observer();
+        assert_eq!(
+            stats,
+            OverlayStats {
+                inserts: 2,
+                pushes: 2,
+                swaps: 1,
+                replacements: 0,
+                bg_sets: 0,
+                bg_takes: 0,
+                evictions: 1,
+                pulls: 0,
+                flips: 1,
+                removals: 0,
+            }
+        );
+        assert_eq!(
+            map.occupancy(),
+            Occupancy {
+                fg_only: 0,
+                with_bg: 2
+            }
+        );
+
+        let usage = map.heap_usage();
+        assert_eq!((usage.keys, usage.fg, usage.bg), (0, 16, 16));
+        assert_eq!(map.heap_size(), usage.total());
+
+        map.observer_mut().reset();
+        assert_eq!(*map.observer(), OverlayStats::default());
+    }
+
+    #[test]
+    fn heap_size_of_nested_values() {
+        let boxed: Box<str> = "hello".into();
+        assert_eq!(boxed.heap_size(), 5);
+        assert_eq!(
+            Box::new(String::with_capacity(3)).heap_size(),
+            size_of::<String>() + 3
+        );
+        assert_eq!(Some(String::with_capacity(7)).heap_size(), 7);
+        assert_eq!(Arc::new(String::with_capacity(7)).heap_size(), 0);
+        assert_eq!(Overlay::new_both(vec![0u32; 2], vec![0; 3]).heap_size(), 20);
+    }
+}
@@ -0,0 +1,551 @@
+use std::{
+    alloc::Layout,
+    hash::{BuildHasher, Hash},
+    slice,
+};
+
+use hashbrown::{DefaultHashBuilder, HashMap, HashTable, TryReserveError, hash_map::RawEntryMut};
+
+use crate::{Allocator, Overlay};
+
+/// The state of the entry passed to the callback of [`OverlayStorage::upsert`].
+pub enum Upsert<'a, V> {
+    /// The key is present: its overlay, and the value being upserted.
+    Occupied(&'a mut Overlay<V>, V),
+    /// The key is absent: the value about to be inserted as its foreground.
+    Vacant(&'a V),
+}
+
+/// A keyed store of [`Overlay`]s that an [`OverlayMap`](crate::OverlayMap) can be built on.
+///
+/// `OverlayMap` implements every push/pull/swap/flip transition once, on top of this trait,
+/// so the choice of backend only decides how keys are looked up and in which order they are
+/// iterated. Implementations are provided for:
+///
+/// - hashbrown's [`HashMap`], the default, for the fastest lookups
+/// - [`BTreeMap`](std::collections::BTreeMap), for sorted keys and range queries
+/// - [`InsertionOrdered`], for deterministic iteration in insertion order
+///
+/// Backends never need to enforce the map's invariants themselves: `OverlayMap` only ever
+/// stores overlays that have a foreground, and relies on [`update`](Self::update) to drop
+/// entries that become empty.
+///
+/// # Safety
+///
+/// `OverlayMap` reads the foreground of the overlays a backend hands out without checking
+/// that it exists. Every overlay yielded by [`get`](Self::get), [`get_mut`](Self::get_mut),
+/// [`upsert`](Self::upsert), [`get_or_insert_with`](Self::get_or_insert_with),
+/// [`update`](Self::update), [`iter`](Self::iter),
+/// [`retain`](Self::retain) and `into_iter` must therefore be the one last stored under that
+/// key, and not since removed. Implementations must not create, alter or swap overlays on
+/// their own, `update` must remove the entry when `f` leaves it empty, and `retain` must
+/// remove exactly the entries for which `f` returns `false`.
+pub unsafe trait OverlayStorage<K, V>: IntoIterator<Item = (K, Overlay<V>)> {
+    /// Iterator over borrowed entries, in the backend's iteration order.
+    type Iter<'a>: Iterator<Item = (&'a K, &'a Overlay<V>)>
+    where
+        Self: 'a,
+        K: 'a,
+        V: 'a;
+
+    /// Number of entries in the store.
+    fn len(&self) -> usize;
+
+    /// Returns `true` if the store holds no entries.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the overlay stored under `key`.
+    fn get(&self, key: &K) -> Option<&Overlay<V>>;
+
+    /// Returns a mutable reference to the overlay stored under `key`.
+    fn get_mut(&mut self, key: &K) -> Option<&mut Overlay<V>>;
+
+    /// Stores `overlay` under `key`, returning the overlay it replaced.
+    fn insert(&mut self, key: K, overlay: Overlay<V>) -> Option<Overlay<V>>;
+
+    /// Removes and returns the overlay stored under `key`.
+    fn remove(&mut self, key: &K) -> Option<Overlay<V>>;
+
+    /// Calls `f` with the key and the overlay stored under it, or stores `value` as the
+    /// foreground of a new overlay if the key is absent.
+    ///
+    /// If the key is present, `f` receives [`Upsert::Occupied`] and takes ownership of
+    /// `value`. Otherwise `f` receives [`Upsert::Vacant`] and `value` is inserted once it
+    /// returns. Returns the result of `f`.
+    fn upsert<R>(&mut self, key: K, value: V, f: impl FnOnce(&K, Upsert<'_, V>) -> R) -> R;
+
+    /// Returns the overlay stored under `key`, first storing the result of `f` if the key is
+    /// absent.
+    fn get_or_insert_with(&mut self, key: K, f: impl FnOnce(&K) -> Overlay<V>) -> &mut Overlay<V>;
+
+    /// Calls `f` with the overlay stored under `key`, removing the entry afterwards if it was
+    /// left empty.
+    ///
+    /// Returns `None` if the key is absent.
+    fn update<R>(&mut self, key: &K, f: impl FnOnce(&mut Overlay<V>) -> Option<R>) -> Option<R>;
+
+    /// Returns an iterator over the stored entries.
+    fn iter(&self) -> Self::Iter<'_>;
+
+    /// Number of entries the store can hold without reallocating.
+    ///
+    /// Backends that allocate per entry, such as `BTreeMap`, report their length.
+    fn capacity(&self) -> usize {
+        self.len()
+    }
+
+    /// Reserves room for at least `additional` more entries, if the backend supports it.
+    fn reserve(&mut self, additional: usize) {
+        let _ = additional;
+    }
+
+    /// Tries to reserve room for at least `additional` more entries, returning an error
+    /// instead of aborting if the allocation fails.
+    ///
+    /// Backends that cannot allocate ahead of time always succeed, and may still abort when
+    /// an entry is inserted.
+    fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        let _ = additional;
+        Ok(())
+    }
+
+    /// Shrinks the allocated capacity as close to the current length as possible.
+    fn shrink_to_fit(&mut self) {}
+
+    /// Shrinks the allocated capacity to at least `min_capacity` entries.
+    fn shrink_to(&mut self, min_capacity: usize) {
+        let _ = min_capacity;
+    }
+
+    /// Keeps only the entries for which `f` returns `true`, preserving iteration order.
+    fn retain(&mut self, f: impl FnMut(&K, &mut Overlay<V>) -> bool);
+
+    /// Removes every entry.
+    fn clear(&mut self);
+}
+
+// SAFETY: hashbrown's `HashMap` is a plain map; every method forwards to it.
+unsafe impl<K, V, S, A> OverlayStorage<K, V> for HashMap<K, Overlay<V>, S, A>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+    A: Allocator,
+{
+    type Iter<'a>
+        = hashbrown::hash_map::Iter<'a, K, Overlay<V>>
+    where
+        Self: 'a,
+        K: 'a,
+        V: 'a;
+
+    #[inline]
+    fn len(&self) -> usize {
+        HashMap::len(self)
+    }
+
+    #[inline]
+    fn get(&self, key: &K) -> Option<&Overlay<V>> {
+        HashMap::get(self, key)
+    }
+
+    #[inline]
+    fn get_mut(&mut self, key: &K) -> Option<&mut Overlay<V>> {
+        HashMap::get_mut(self, key)
+    }
+
+    #[inline]
+    fn insert(&mut self, key: K, overlay: Overlay<V>) -> Option<Overlay<V>> {
+        HashMap::insert(self, key, overlay)
+    }
+
+    #[inline]
+    fn remove(&mut self, key: &K) -> Option<Overlay<V>> {
+        HashMap::remove(self, key)
+    }
+
+    #[inline]
+    fn upsert<R>(&mut self, key: K, value: V, f: impl FnOnce(&K, Upsert<'_, V>) -> R) -> R {
+        match self.raw_entry_mut().from_key(&key) {
+            RawEntryMut::Occupied(mut entry) => f(&key, Upsert::Occupied(entry.get_mut(), value)),
+            RawEntryMut::Vacant(entry) => {
+                let result = f(&key, Upsert::Vacant(&value));
+                entry.insert(key, Overlay::new_fg(value));
+                result
+            }
+        }
+    }
+
+    #[inline]
+    fn get_or_insert_with(&mut self, key: K, f: impl FnOnce(&K) -> Overlay<V>) -> &mut Overlay<V> {
+        match self.raw_entry_mut().from_key(&key) {
+            RawEntryMut::Occupied(entry) => entry.into_mut(),
+            RawEntryMut::Vacant(entry) => {
+                let overlay = f(&key);
+                entry.insert(key, overlay).1
+            }
+        }
+    }
+
+    #[inline]
+    fn update<R>(&mut self, key: &K, f: impl FnOnce(&mut Overlay<V>) -> Option<R>) -> Option<R> {
+        match self.raw_entry_mut().from_key(key) {
+            RawEntryMut::Occupied(mut entry) => {
+                let result = f(entry.get_mut());
+                if entry.get().is_empty() {
+                    entry.remove();
+                }
+                result
+            }
+            RawEntryMut::Vacant(_) => None,
+        }
+    }
+
+    #[inline]
+    fn iter(&self) -> Self::Iter<'_> {
+        HashMap::iter(self)
+    }
+
+    #[inline]
+    fn capacity(&self) -> usize {
+        HashMap::capacity(self)
+    }
+
+    #[inline]
+    fn reserve(&mut self, additional: usize) {
+        HashMap::reserve(self, additional)
+    }
+
+    #[inline]
+    fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        HashMap::try_reserve(self, additional)
+    }
+
+    #[inline]
+    fn shrink_to_fit(&mut self) {
+        HashMap::shrink_to_fit(self)
+    }
+
+    #[inline]
+    fn shrink_to(&mut self, min_capacity: usize) {
+        HashMap::shrink_to(self, min_capacity)
+    }
+
+    #[inline]
+    fn retain(&mut self, f: impl FnMut(&K, &mut Overlay<V>) -> bool) {
+        HashMap::retain(self, f)
+    }
+
+    #[inline]
+    fn clear(&mut self) {
+        HashMap::clear(self)
+    }
+}
+
+/// An [`OverlayStorage`] backend that iterates in insertion order.
+///
+/// Entries are kept in a contiguous vector with a hash index on the side, so lookups cost the
+/// same as a hash map, while iteration always follows the order in which keys were first
+/// inserted. This makes the output of an [`OverlayMap`](crate::OverlayMap) deterministic,
+/// which is useful for snapshot tests.
+///
+/// # Performance
+///
+/// Removing an entry shifts every later entry down and renumbers their indices to preserve
+/// the order, so [`remove`](OverlayStorage::remove), and any pull or merge that removes the
+/// last value of a key, costs `O(n)` rather than the `O(1)` of a hash map. Pulls that leave a
+/// key with a background do not remove it. Prefer the default backend for maps with heavy
+/// key churn.
+///
+/// Use it through the [`InsertionOrderedOverlayMap`](crate::InsertionOrderedOverlayMap) alias:
+///
+/// ```
+/// use overlay_map::InsertionOrderedOverlayMap;
+///
+/// let mut map = InsertionOrderedOverlayMap::<_, _>::default();
+/// map.push("zebra", 1);
+/// map.push("apple", 2);
+/// map.push("mango", 3);
+/// map.pull(&"apple");
+/// map.push("apple", 4);
+///
+/// let keys: Vec<_> = map.iter().map(|(k, _)| *k).collect();
+/// assert_eq!(keys, vec!["zebra", "mango", "apple"]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct InsertionOrdered<K, V, S = DefaultHashBuilder> {
+    entries: Vec<(K, Overlay<V>)>,
+    indices: HashTable<usize>,
+    hasher: S,
+}
+
+impl<K, V> InsertionOrdered<K, V, DefaultHashBuilder> {
+    /// Creates an empty `InsertionOrdered` store using the default hasher.
+    pub fn new() -> Self {
+        Self::with_hasher(DefaultHashBuilder::default())
+    }
+}
+
+impl<K, V, S> InsertionOrdered<K, V, S> {
+    /// Creates an empty `InsertionOrdered` store that will use the given hasher.
+    pub fn with_hasher(hasher: S) -> Self {
+        Self {
+            entries: Vec::new(),
+            indices: HashTable::new(),
+            hasher,
+        }
+    }
+
+    /// Creates an empty `InsertionOrdered` store with the specified capacity and hasher.
+    pub fn with_capacity_and_hasher(capacity: usize, hasher: S) -> Self {
+        Self {
+            entries: Vec::with_capacity(capacity),
+            indices: HashTable::with_capacity(capacity),
+            hasher,
+        }
+    }
+}
+
+impl<K, V, S> InsertionOrdered<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    #[inline]
+    fn find(&self, key: &K) -> Option<usize> {
+        let hash = self.hasher.hash_one(key);
+        self.indices
+            .find(hash, |&i| self.entries[i].0 == *key)
+            .copied()
+    }
+
+    fn remove_at(&mut self, index: usize) -> (K, Overlay<V>) {
+        let hash = self.hasher.hash_one(&self.entries[index].0);
+        if let Ok(entry) = self.indices.find_entry(hash, |&i| i == index) {
+            entry.remove();
+        }
+        for i in self.indices.iter_mut() {
+            if *i > index {
+                *i -= 1;
+            }
+        }
+        self.entries.remove(index)
+    }
+
+    fn rebuild_indices(&mut self) {
+        self.indices.clear();
+        for (index, (key, _)) in self.entries.iter().enumerate() {
+            let hash = self.hasher.hash_one(key);
+            let (entries, hasher) = (&self.entries, &self.hasher);
+            self.indices
+                .insert_unique(hash, index, |&i| hasher.hash_one(&entries[i].0));
+        }
+    }
+
+    fn push_entry(&mut self, key: K, overlay: Overlay<V>) {
+        let hash = self.hasher.hash_one(&key);
+        let index = self.entries.len();
+        self.entries.push((key, overlay));
+
+        let (entries, hasher) = (&self.entries, &self.hasher);
+        self.indices
+            .insert_unique(hash, index, |&i| hasher.hash_one(&entries[i].0));
+    }
+}
+
+impl<K, V, S> Default for InsertionOrdered<K, V, S>
+where
+    S: Default,
+{
+    fn default() -> Self {
+        Self::with_hasher(S::default())
+    }
+}
+
+impl<K, V, S> PartialEq for InsertionOrdered<K, V, S>
+where
+    K: Eq + Hash,
+    V: PartialEq,
+    S: BuildHasher,
+{
+    /// Two stores are equal if they hold the same entries, regardless of their order.
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self
+                .entries
+                .iter()
+                .all(|(key, overlay)| other.get(key) == Some(overlay))
+    }
+}
+
+impl<K, V, S> Eq for InsertionOrdered<K, V, S>
+where
+    K: Eq + Hash,
+    V: Eq,
+    S: BuildHasher,
+{
+}
+
+impl<K, V, S> IntoIterator for InsertionOrdered<K, V, S> {
+    type Item = (K, Overlay<V>);
+    type IntoIter = std::vec::IntoIter<(K, Overlay<V>)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
+    }
+}
+
+type EntryRef<'a, K, V> = fn(&'a (K, Overlay<V>)) -> (&'a K, &'a Overlay<V>);
+
+// SAFETY: entries are only stored, replaced and removed on request, and `update` and
+// `retain` remove exactly the entries they are asked to.
+unsafe impl<K, V, S> OverlayStorage<K, V> for InsertionOrdered<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    type Iter<'a>
+        = std::iter::Map<slice::Iter<'a, (K, Overlay<V>)>, EntryRef<'a, K, V>>
+    where
+        Self: 'a,
+        K: 'a,
+        V: 'a;
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[inline]
+    fn get(&self, key: &K) -> Option<&Overlay<V>> {
+        self.find(key).map(|i| &self.entries[i].1)
+    }
+
+    #[inline]
+    fn get_mut(&mut self, key: &K) -> Option<&mut Overlay<V>> {
+        self.find(key).map(|i| &mut self.entries[i].1)
+    }
+
+    fn insert(&mut self, key: K, overlay: Overlay<V>) -> Option<Overlay<V>> {
+        match self.find(&key) {
+            Some(i) => Some(std::mem::replace(&mut self.entries[i].1, overlay)),
+            None => {
+                self.push_entry(key, overlay);
+                None
+            }
+        }
+    }
+
+    fn remove(&mut self, key: &K) -> Option<Overlay<V>> {
+        let index = self.find(key)?;
+        Some(self.remove_at(index).1)
+    }
+
+    #[inline]
+    fn upsert<R>(&mut self, key: K, value: V, f: impl FnOnce(&K, Upsert<'_, V>) -> R) -> R {
+        match self.find(&key) {
+            Some(i) => f(&key, Upsert::Occupied(&mut self.entries[i].1, value)),
+            None => {
+                let result = f(&key, Upsert::Vacant(&value));
+                self.push_entry(key, Overlay::new_fg(value));
+                result
+            }
+        }
+    }
+
+    fn get_or_insert_with(&mut self, key: K, f: impl FnOnce(&K) -> Overlay<V>) -> &mut Overlay<V> {
+        let index = match self.find(&key) {
+            Some(i) => i,
+            None => {
+                let overlay = f(&key);
+                self.push_entry(key, overlay);
+                self.entries.len() - 1
+            }
+        };
+        &mut self.entries[index].1
+    }
+
+    fn update<R>(&mut self, key: &K, f: impl FnOnce(&mut Overlay<V>) -> Option<R>) -> Option<R> {
+        let index = self.find(key)?;
+        let result = f(&mut self.entries[index].1);
+        if self.entries[index].1.is_empty() {
+            self.remove_at(index);
+        }
+        result
+    }
+
+    #[inline]
+    fn iter(&self) -> Self::Iter<'_> {
+        self.entries.iter().map(|(key, overlay)| (key, overlay))
+    }
+
+    fn capacity(&self) -> usize {
+        self.entries.capacity().min(self.indices.capacity())
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        self.entries.reserve(additional);
+        let (entries, hasher) = (&self.entries, &self.hasher);
+        self.indices
+            .reserve(additional, |&i| hasher.hash_one(&entries[i].0));
+    }
+
+    fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        if self.entries.try_reserve(additional).is_err() {
+            let wanted = self.entries.len().saturating_add(additional);
+            return Err(match Layout::array::<(K, Overlay<V>)>(wanted) {
+                Ok(layout) => TryReserveError::AllocError { layout },
+                Err(_) => TryReserveError::CapacityOverflow,
+            });
+        }
+        let (entries, hasher) = (&self.entries, &self.hasher);
+        self.indices
+            .try_reserve(additional, |&i| hasher.hash_one(&entries[i].0))
+    }
+
+    fn shrink_to_fit(&mut self) {
+        self.shrink_to(0);
+    }
+
+    fn shrink_to(&mut self, min_capacity: usize) {
+        self.entries.shrink_to(min_capacity);
+        let (entries, hasher) = (&self.entries, &self.hasher);
+        self.indices
+            .shrink_to(min_capacity, |&i| hasher.hash_one(&entries[i].0));
+    }
+
+    fn retain(&mut self, mut f: impl FnMut(&K, &mut Overlay<V>) -> bool) {
+        let len = self.entries.len();
+        self.entries.retain_mut(|(key, overlay)| f(key, overlay));
+        if self.entries.len() != len {
+            self.rebuild_indices();
+        }
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.indices.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insertion_ordered_reindexes_after_removal() {
+        let mut store = InsertionOrdered::<u32, u32>::new();
+        for i in 0..8 {
+            store.insert(i, Overlay::new_fg(i * 10));
+        }
+        assert!(store.remove(&3).is_some());
+        assert_eq!(store.update(&5, |entry| entry.pull()), Some(50));
+
+        let keys: Vec<_> = store.iter().map(|(k, _)| *k).collect();
+        assert_eq!(keys, vec![0, 1, 2, 4, 6, 7]);
+        for key in keys {
+            assert_eq!(store.get(&key).and_then(Overlay::fg), Some(&(key * 10)));
+        }
+    }
+}
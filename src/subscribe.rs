@@ -0,0 +1,563 @@
+use std::{
+    collections::VecDeque,
+    hash::{BuildHasher, Hash},
+    ops::Deref,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    task::Waker,
+};
+
+use hashbrown::{DefaultHashBuilder, HashMap};
+
+use crate::{Overlay, OverlayMap};
+
+/// The transition that produced a [`Change`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChangeKind {
+    /// See [`OverlayMap::push`].
+    Push,
+    /// See [`OverlayMap::swap`].
+    Swap,
+    /// See [`OverlayMap::pull`].
+    Pull,
+    /// See [`OverlayMap::flip`].
+    Flip,
+    /// See [`OverlayMap::remove`].
+    Remove,
+}
+
+/// A change to one key of a [`WatchedOverlayMap`], as delivered to a [`Subscription`].
+///
+/// `fg` and `bg` are the key's layers just after the change, so `bg` holds the value that
+/// was current before a push or swap. Both are `None` once the key has been removed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change<K, V> {
+    /// The key that changed.
+    pub key: K,
+    /// The transition that changed it.
+    pub kind: ChangeKind,
+    /// The new foreground value.
+    pub fg: Option<V>,
+    /// The background value after the change.
+    pub bg: Option<V>,
+}
+
+struct State<K, V> {
+    queue: VecDeque<Change<K, V>>,
+    waker: Option<Waker>,
+    closed: bool,
+}
+
+/// The channel shared between a [`WatchedOverlayMap`] and one [`Subscription`].
+struct Channel<K, V> {
+    state: Mutex<State<K, V>>,
+    ready: Condvar,
+}
+
+impl<K, V> Channel<K, V> {
+    fn new() -> Arc<Self> {
+        Arc::new(Self {
+            state: Mutex::new(State {
+                queue: VecDeque::new(),
+                waker: None,
+                closed: false,
+            }),
+            ready: Condvar::new(),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State<K, V>> {
+        // A panicking subscriber cannot leave the queue half-updated, so poisoning is ignored.
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Returns `false` once the subscription has been dropped, leaving the hub's handle as
+    /// the only one.
+    fn is_live(self: &Arc<Self>) -> bool {
+        Arc::strong_count(self) > 1
+    }
+
+    /// Returns `false` if the subscription has been dropped.
+    fn send(self: &Arc<Self>, change: Change<K, V>) -> bool {
+        if !self.is_live() {
+            return false;
+        }
+        let mut state = self.lock();
+        state.queue.push_back(change);
+        let waker = state.waker.take();
+        drop(state);
+        self.ready.notify_one();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+        true
+    }
+
+    fn close(&self) {
+        let mut state = self.lock();
+        state.closed = true;
+        let waker = state.waker.take();
+        drop(state);
+        self.ready.notify_all();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// A stream of [`Change`]s from a [`WatchedOverlayMap`], for one key or for the whole map.
+///
+/// Changes are queued until they are received, without limit. There are three ways to
+/// receive them:
+///
+/// - [`try_recv`](Self::try_recv) returns the next queued change without waiting
+/// - [`recv`](Self::recv) and [`blocking_iter`](Self::blocking_iter) wait for the next change,
+///   for subscribers on a different thread from the map
+/// - with the `stream` feature, `Subscription` implements `futures_core::Stream`
+///
+/// Blocking receives and the stream end once the map has been dropped and every queued change has
+/// been received.
+pub struct Subscription<K, V> {
+    channel: Arc<Channel<K, V>>,
+}
+
+impl<K, V> Subscription<K, V> {
+    /// Returns the next queued change, if any, without waiting.
+    pub fn try_recv(&self) -> Option<Change<K, V>> {
+        self.channel.lock().queue.pop_front()
+    }
+
+    /// Waits for the next change.
+    ///
+    /// Returns `None` once the map has been dropped and the queue is empty. Calling this on
+    /// the thread that owns the map blocks forever if nothing is queued; use
+    /// [`try_recv`](Self::try_recv) there instead.
+    pub fn recv(&self) -> Option<Change<K, V>> {
+        let mut state = self.channel.lock();
+        loop {
+            if let Some(change) = state.queue.pop_front() {
+                return Some(change);
+            }
+            if state.closed {
+                return None;
+            }
+            state = self
+                .channel
+                .ready
+                .wait(state)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    /// A blocking iterator over the changes, calling [`recv`](Self::recv) for each one.
+    pub fn blocking_iter(&self) -> BlockingIter<'_, K, V> {
+        BlockingIter { subscription: self }
+    }
+
+    /// Returns `true` if the map has been dropped, so no further changes will be queued.
+    pub fn is_closed(&self) -> bool {
+        self.channel.lock().closed
+    }
+}
+
+/// A blocking iterator over the changes of a [`Subscription`].
+///
+/// Created by [`Subscription::blocking_iter`]; it ends once the map has been dropped and every
+/// queued change has been received.
+pub struct BlockingIter<'a, K, V> {
+    subscription: &'a Subscription<K, V>,
+}
+
+impl<K, V> Iterator for BlockingIter<'_, K, V> {
+    type Item = Change<K, V>;
+
+    fn next(&mut self) -> Option<Change<K, V>> {
+        self.subscription.recv()
+    }
+}
+
+#[cfg(feature = "stream")]
+impl<K, V> futures_core::Stream for Subscription<K, V> {
+    type Item = Change<K, V>;
+
+    fn poll_next(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Change<K, V>>> {
+        use std::task::Poll;
+
+        let mut state = self.channel.lock();
+        if let Some(change) = state.queue.pop_front() {
+            return Poll::Ready(Some(change));
+        }
+        if state.closed {
+            return Poll::Ready(None);
+        }
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+/// An [`OverlayMap`] that notifies subscribers whenever a key changes.
+///
+/// [`subscribe`](Self::subscribe) watches a single key and
+/// [`subscribe_all`](Self::subscribe_all) watches every key. Each push, swap, pull, flip or
+/// remove that changes the map delivers a [`Change`] carrying the key's new foreground and
+/// background to the matching subscriptions. Operations that change nothing, such as pulling
+/// an absent key, are not reported.
+///
+/// Every delivered change holds its own clone of the key and both layers, so keys and values
+/// must be `Clone`; wrap large values in an `Arc` to make this cheap. The map can be read
+/// through `Deref`.
+///
+/// # Example
+///
+/// ```
+/// use overlay_map::{ChangeKind, WatchedOverlayMap};
+///
+/// let mut map = WatchedOverlayMap::new();
+/// let theme = map.subscribe("theme");
+/// let all = map.subscribe_all();
+///
+/// map.push("theme", "light");
+/// map.push("theme", "dark");
+/// map.push("font", "mono");
+///
+/// let change = theme.try_recv().unwrap();
+/// assert_eq!((change.fg, change.bg), (Some("light"), None));
+/// let change = theme.try_recv().unwrap();
+/// assert_eq!((change.fg, change.bg), (Some("dark"), Some("light")));
+/// assert!(theme.try_recv().is_none());
+///
+/// let keys: Vec<_> = std::iter::from_fn(|| all.try_recv()).map(|c| c.key).collect();
+/// assert_eq!(keys, ["theme", "theme", "font"]);
+/// ```
+pub struct WatchedOverlayMap<K, V, S = DefaultHashBuilder>
+where
+    K: Eq + Hash,
+{
+    map: OverlayMap<K, V, S>,
+    hub: Hub<K, V>,
+}
+
+/// The subscriptions of a [`WatchedOverlayMap`], closed when it is dropped.
+struct Hub<K, V> {
+    by_key: HashMap<K, Vec<Arc<Channel<K, V>>>>,
+    all: Vec<Arc<Channel<K, V>>>,
+    /// Number of channels held, including those of dropped subscriptions.
+    channels: usize,
+    /// Channel count at which the next `subscribe` sweeps out dropped subscriptions.
+    sweep_at: usize,
+}
+
+/// Smallest channel count that triggers a sweep.
+const MIN_SWEEP: usize = 16;
+
+impl<K, V> Hub<K, V> {
+    fn new() -> Self {
+        Self {
+            by_key: HashMap::new(),
+            all: Vec::new(),
+            channels: 0,
+            sweep_at: MIN_SWEEP,
+        }
+    }
+
+    /// Drops the channels of dropped subscriptions, including those of keys that have not
+    /// changed since.
+    ///
+    /// The next sweep is scheduled once the number of channels has doubled, so sweeping is
+    /// amortized `O(1)` per subscription.
+    fn sweep(&mut self) {
+        self.by_key.retain(|_, channels| {
+            channels.retain(Channel::is_live);
+            !channels.is_empty()
+        });
+        self.all.retain(Channel::is_live);
+        self.channels = self.all.len() + self.by_key.values().map(Vec::len).sum::<usize>();
+        self.sweep_at = (self.channels * 2).max(MIN_SWEEP);
+    }
+}
+
+impl<K, V> Drop for Hub<K, V> {
+    /// Closes every subscription, so blocked iterators and pending streams finish.
+    fn drop(&mut self) {
+        for channel in self.all.iter().chain(self.by_key.values().flatten()) {
+            channel.close();
+        }
+    }
+}
+
+impl<K, V> WatchedOverlayMap<K, V, DefaultHashBuilder>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+{
+    /// Creates a new, empty `WatchedOverlayMap` using the default hasher.
+    pub fn new() -> Self {
+        Self::from_map(OverlayMap::new())
+    }
+}
+
+impl<K, V, S> WatchedOverlayMap<K, V, S>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    S: BuildHasher,
+{
+    /// Starts watching an existing map. Its current contents are not reported.
+    pub fn from_map(map: OverlayMap<K, V, S>) -> Self {
+        Self {
+            map,
+            hub: Hub::new(),
+        }
+    }
+
+    /// The map being watched.
+    pub fn map(&self) -> &OverlayMap<K, V, S> {
+        &self.map
+    }
+
+    /// Stops watching, returning the map. Every subscription is closed.
+    pub fn into_map(self) -> OverlayMap<K, V, S> {
+        self.map
+    }
+
+    /// Subscribes to the changes of a single key.
+    pub fn subscribe(&mut self, key: K) -> Subscription<K, V> {
+        let channel = self.channel();
+        self.hub
+            .by_key
+            .entry(key)
+            .or_default()
+            .push(Arc::clone(&channel));
+        Subscription { channel }
+    }
+
+    /// Subscribes to the changes of every key.
+    pub fn subscribe_all(&mut self) -> Subscription<K, V> {
+        let channel = self.channel();
+        self.hub.all.push(Arc::clone(&channel));
+        Subscription { channel }
+    }
+
+    /// The number of live subscriptions.
+    pub fn subscriber_count(&self) -> usize {
+        self.hub
+            .all
+            .iter()
+            .chain(self.hub.by_key.values().flatten())
+            .filter(|channel| channel.is_live())
+            .count()
+    }
+
+    /// Creates the channel for a new subscription, sweeping out dropped ones first if enough
+    /// have accumulated.
+    fn channel(&mut self) -> Arc<Channel<K, V>> {
+        if self.hub.channels >= self.hub.sweep_at {
+            self.hub.sweep();
+        }
+        self.hub.channels += 1;
+        Channel::new()
+    }
+
+    /// Push a value into the foreground layer and notify subscribers.
+    ///
+    /// See [`OverlayMap::push`].
+    pub fn push(&mut self, key: K, value: V) -> bool {
+        let pushed = self.map.push(key.clone(), value);
+        self.notify(key, ChangeKind::Push);
+        pushed
+    }
+
+    /// Swap a value into the foreground layer and notify subscribers, returning the evicted
+    /// background value if present.
+    ///
+    /// See [`OverlayMap::swap`].
+    pub fn swap(&mut self, key: K, value: V) -> Option<V> {
+        let evicted = self.map.swap(key.clone(), value);
+        self.notify(key, ChangeKind::Swap);
+        evicted
+    }
+
+    /// Pull the foreground value for a key and notify subscribers if it was present.
+    ///
+    /// See [`OverlayMap::pull`].
+    pub fn pull(&mut self, key: &K) -> Option<V> {
+        let pulled = self.map.pull(key)?;
+        self.notify(key.clone(), ChangeKind::Pull);
+        Some(pulled)
+    }
+
+    /// Flip the foreground and background values for a key and notify subscribers if both
+    /// were present.
+    ///
+    /// See [`OverlayMap::flip`].
+    pub fn flip(&mut self, key: &K) {
+        if self.map.bg(key).is_some() {
+            self.map.flip(key);
+            self.notify(key.clone(), ChangeKind::Flip);
+        }
+    }
+
+    /// Remove a key and both of its values, and notify subscribers if it was present.
+    ///
+    /// See [`OverlayMap::remove`].
+    pub fn remove(&mut self, key: &K) -> Option<Overlay<V>> {
+        let removed = self.map.remove(key)?;
+        self.notify(key.clone(), ChangeKind::Remove);
+        Some(removed)
+    }
+
+    fn notify(&mut self, key: K, kind: ChangeKind) {
+        let subscribers = self.hub.by_key.get_mut(&key);
+        if subscribers.is_none() && self.hub.all.is_empty() {
+            return;
+        }
+        let change = Change {
+            fg: self.map.fg(&key).cloned(),
+            bg: self.map.bg(&key).cloned(),
+            key,
+            kind,
+        };
+
+        let before = self.hub.all.len();
+        if let Some(subscribers) = subscribers {
+            let len = subscribers.len();
+            subscribers.retain(|channel| channel.send(change.clone()));
+            self.hub.channels -= len - subscribers.len();
+            if subscribers.is_empty() {
+                self.hub.by_key.remove(&change.key);
+            }
+        }
+        self.hub.all.retain(|channel| channel.send(change.clone()));
+        self.hub.channels -= before - self.hub.all.len();
+    }
+}
+
+impl<K, V, S> Default for WatchedOverlayMap<K, V, S>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    S: BuildHasher + Default,
+{
+    fn default() -> Self {
+        Self::from_map(OverlayMap::with_hasher(S::default()))
+    }
+}
+
+impl<K, V, S> Deref for WatchedOverlayMap<K, V, S>
+where
+    K: Eq + Hash,
+{
+    type Target = OverlayMap<K, V, S>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn delivers_post_change_layers_to_matching_subscribers() {
+        let mut map = WatchedOverlayMap::<&str, u32>::new();
+        let a = map.subscribe("a");
+        let all = map.subscribe_all();
+        drop(map.subscribe("a"));
+
+        map.push("a", 1);
+        map.push("a", 2);
+        map.flip(&"a");
+        map.flip(&"b");
+        map.pull(&"b");
+        assert_eq!(map.swap("a", 3), Some(2));
+        map.pull(&"a");
+        map.push("b", 9);
+        map.remove(&"a");
+        assert_eq!(map.subscriber_count(), 2);
+
+        let seen: Vec<_> = std::iter::from_fn(|| a.try_recv())
+            .map(|c| (c.kind, c.fg, c.bg))
+            .collect();
+        assert_eq!(
+            seen,
+            [
+                (ChangeKind::Push, Some(1), None),
+                (ChangeKind::Push, Some(2), Some(1)),
+                (ChangeKind::Flip, Some(1), Some(2)),
+                (ChangeKind::Swap, Some(3), Some(1)),
+                (ChangeKind::Pull, Some(1), None),
+                (ChangeKind::Remove, None, None),
+            ]
+        );
+        assert_eq!(std::iter::from_fn(|| all.try_recv()).count(), 7);
+    }
+
+    #[test]
+    fn blocking_iterator_ends_when_map_is_dropped() {
+        let mut map = WatchedOverlayMap::<u32, u32>::new();
+        let all = map.subscribe_all();
+        let reader = thread::spawn(move || all.blocking_iter().map(|c| c.fg).collect::<Vec<_>>());
+
+        for i in 0..100 {
+            map.push(i % 3, i);
+        }
+        drop(map);
+
+        let seen = reader.join().unwrap();
+        assert_eq!(seen.len(), 100);
+        assert_eq!(seen.last(), Some(&Some(99)));
+    }
+
+    #[cfg(feature = "stream")]
+    #[test]
+    fn stream_yields_changes_on_a_local_executor() {
+        use futures::{StreamExt, executor::LocalPool, task::LocalSpawnExt};
+
+        let mut map = WatchedOverlayMap::<&str, u32>::new();
+        let mut hp = map.subscribe("hp");
+        let mut pool = LocalPool::new();
+        let seen = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+
+        let sink = seen.clone();
+        pool.spawner()
+            .spawn_local(async move {
+                while let Some(change) = hp.next().await {
+                    sink.borrow_mut().push(change.fg);
+                }
+            })
+            .unwrap();
+
+        pool.run_until_stalled();
+        assert!(seen.borrow().is_empty());
+
+        map.push("hp", 100);
+        map.push("mp", 5);
+        map.push("hp", 80);
+        pool.run_until_stalled();
+        assert_eq!(*seen.borrow(), [Some(100), Some(80)]);
+
+        drop(map);
+        pool.run();
+        assert_eq!(seen.borrow().len(), 2);
+    }
+
+    #[test]
+    fn dropped_subscriptions_to_quiet_keys_are_swept() {
+        let mut map = WatchedOverlayMap::<u32, u32>::new();
+        let kept = map.subscribe(0);
+        for i in 0..1_000 {
+            drop(map.subscribe(i % 4));
+            drop(map.subscribe_all());
+            assert_eq!(map.subscriber_count(), 1);
+        }
+        assert!(map.hub.channels < 2 * MIN_SWEEP);
+
+        map.push(0, 1);
+        assert_eq!(kept.try_recv().map(|c| c.fg), Some(Some(1)));
+    }
+}
@@ -0,0 +1,536 @@
+use std::{
+    collections::VecDeque,
+    hash::{BuildHasher, Hash},
+    mem::size_of,
+    ops::Deref,
+};
+
+use hashbrown::DefaultHashBuilder;
+
+use crate::{Overlay, OverlayMap};
+
+/// One recorded transition, holding whatever is needed to reverse it.
+///
+/// The stash moves between the map and the step as the step is undone and redone, so no value
+/// is cloned after it was first recorded:
+///
+/// - `Push`: the background the push evicted while done, the pushed value while undone
+/// - `Pull`: the pulled value while done, nothing while undone
+/// - `Remove`: the removed entry while done, nothing while undone
+#[derive(Debug)]
+enum Step<K, V> {
+    Push(K, Option<V>),
+    Pull(K, Option<V>),
+    Flip(K),
+    Remove(K, Option<Overlay<V>>),
+}
+
+impl<K, V> Step<K, V>
+where
+    K: Eq + Hash + Clone,
+{
+    fn undo<S: BuildHasher>(&mut self, map: &mut OverlayMap<K, V, S>) {
+        match self {
+            Step::Push(key, stash) => {
+                let evicted = stash.take();
+                *stash = map.pull(key);
+                if let Some(evicted) = evicted {
+                    map.push(key.clone(), evicted);
+                    map.flip(key);
+                }
+            }
+            Step::Pull(key, stash) => {
+                if let Some(pulled) = stash.take() {
+                    map.push(key.clone(), pulled);
+                }
+            }
+            Step::Flip(key) => map.flip(key),
+            Step::Remove(key, stash) => {
+                if let Some(entry) = stash.take() {
+                    map.map.insert(key.clone(), entry);
+                }
+            }
+        }
+    }
+
+    fn redo<S: BuildHasher>(&mut self, map: &mut OverlayMap<K, V, S>) {
+        match self {
+            Step::Push(key, stash) => {
+                if let Some(value) = stash.take() {
+                    *stash = map.swap(key.clone(), value);
+                }
+            }
+            Step::Pull(key, stash) => *stash = map.pull(key),
+            Step::Flip(key) => map.flip(key),
+            Step::Remove(key, stash) => *stash = map.map.remove(key),
+        }
+    }
+}
+
+/// A group of steps undone and redone together, with its estimated size in bytes.
+#[derive(Debug)]
+struct Group<K, V> {
+    steps: Vec<Step<K, V>>,
+    bytes: usize,
+}
+
+/// An [`OverlayMap`] with unlimited undo and redo.
+///
+/// Every push, swap, pull, flip and remove made through this type is recorded, so it can be
+/// reversed with [`undo`](Self::undo) and reapplied with [`redo`](Self::redo). The map is
+/// read through `Deref`, and each key keeps its usual foreground and background, so after an
+/// undo the [`fg`](OverlayMap::fg) and [`bg`](OverlayMap::bg) views are exactly what they were
+/// before the undone change.
+///
+/// By default every change is its own undo step. Changes made between
+/// [`begin_group`](Self::begin_group) and [`end_group`](Self::end_group) are undone and redone
+/// as one step. Making a new change discards everything that could be redone.
+///
+/// Undo history keeps the values a change displaced, moving them back and forth between the
+/// map and the history rather than cloning them. The exceptions are values the map hands back
+/// to the caller — from [`pull`](Self::pull), [`swap`](Self::swap) and
+/// [`remove`](Self::remove) — which are cloned into the history, so those methods require
+/// `V: Clone`.
+///
+/// # Memory cap
+///
+/// [`with_memory_cap`](Self::with_memory_cap) bounds the estimated size of the history.
+/// When it is exceeded, the oldest undo groups are forgotten. Each recorded change is counted
+/// at its inline size; values owning heap memory can be accounted for with
+/// [`with_weigher`](Self::with_weigher).
+///
+/// # Example
+///
+/// ```
+/// use overlay_map::UndoOverlayMap;
+///
+/// let mut doc = UndoOverlayMap::new();
+/// doc.push("title", "Draft");
+///
+/// doc.begin_group();
+/// doc.push("title", "Final");
+/// doc.push("author", "Ada");
+/// doc.end_group();
+///
+/// assert!(doc.undo());
+/// assert_eq!(doc.fg(&"title"), Some(&"Draft"));
+/// assert_eq!(doc.fg(&"author"), None);
+///
+/// assert!(doc.redo());
+/// assert_eq!(doc.fg(&"title"), Some(&"Final"));
+/// assert_eq!(doc.bg(&"title"), Some(&"Draft"));
+/// ```
+#[derive(Debug)]
+pub struct UndoOverlayMap<K, V, S = DefaultHashBuilder>
+where
+    K: Eq + Hash,
+{
+    map: OverlayMap<K, V, S>,
+    undo: VecDeque<Group<K, V>>,
+    redo: Vec<Group<K, V>>,
+    open: Option<Group<K, V>>,
+    depth: usize,
+    bytes: usize,
+    memory_cap: usize,
+    weigher: fn(&K, &V) -> usize,
+}
+
+impl<K, V> UndoOverlayMap<K, V, DefaultHashBuilder>
+where
+    K: Eq + Hash + Clone,
+{
+    /// Creates a new, empty `UndoOverlayMap` with no memory cap, using the default hasher.
+    pub fn new() -> Self {
+        Self::with_hasher(DefaultHashBuilder::default())
+    }
+}
+
+impl<K, V, S> UndoOverlayMap<K, V, S>
+where
+    K: Eq + Hash + Clone,
+    S: BuildHasher,
+{
+    /// Creates an empty `UndoOverlayMap` with no memory cap that will use the given hasher.
+    pub fn with_hasher(hasher: S) -> Self
+    where
+        S: Default,
+    {
+        Self::from_map(OverlayMap::with_hasher(hasher))
+    }
+
+    /// Starts recording history on top of an existing map. Its current contents cannot be
+    /// undone.
+    pub fn from_map(map: OverlayMap<K, V, S>) -> Self {
+        Self {
+            map,
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            open: None,
+            depth: 0,
+            bytes: 0,
+            memory_cap: usize::MAX,
+            weigher: |_, _| 0,
+        }
+    }
+
+    /// Limits the estimated size of the undo and redo history to `bytes`.
+    ///
+    /// ```
+    /// use overlay_map::UndoOverlayMap;
+    ///
+    /// let mut map = UndoOverlayMap::new().with_memory_cap(1024);
+    /// for i in 0..10_000 {
+    ///     map.push(i % 8, i);
+    /// }
+    /// assert!(map.history_bytes() <= 1024);
+    /// assert!(map.undo_len() < 10_000);
+    /// ```
+    pub fn with_memory_cap(mut self, bytes: usize) -> Self {
+        self.memory_cap = bytes;
+        self.trim();
+        self
+    }
+
+    /// Adds the heap memory owned by keys and values to the size of the history.
+    ///
+    /// `weigher` is called once for every value stored in the history, when it is recorded.
+    ///
+    /// ```
+    /// use overlay_map::{HeapSize, UndoOverlayMap};
+    ///
+    /// let mut map = UndoOverlayMap::<u32, String>::new()
+    ///     .with_weigher(|key, value| key.heap_size() + value.heap_size())
+    ///     .with_memory_cap(4096);
+    /// map.push(1, "a".repeat(1000));
+    /// map.push(1, "b".repeat(1000));
+    /// map.push(1, "c".repeat(1000)); // records the evicted "a"s
+    /// assert!(map.history_bytes() >= 1000);
+    /// ```
+    pub fn with_weigher(mut self, weigher: fn(&K, &V) -> usize) -> Self {
+        self.weigher = weigher;
+        self
+    }
+
+    /// The map, without its history.
+    pub fn map(&self) -> &OverlayMap<K, V, S> {
+        &self.map
+    }
+
+    /// Consumes the history, returning the map.
+    pub fn into_map(self) -> OverlayMap<K, V, S> {
+        self.map
+    }
+
+    /// The number of groups that can be undone.
+    pub fn undo_len(&self) -> usize {
+        self.undo.len()
+            + self
+                .open
+                .as_ref()
+                .map_or(0, |g| !g.steps.is_empty() as usize)
+    }
+
+    /// The number of groups that can be redone.
+    pub fn redo_len(&self) -> usize {
+        self.redo.len()
+    }
+
+    /// The estimated size of the undo and redo history, in bytes.
+    pub fn history_bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Forgets all undo and redo history, keeping the map as it is.
+    pub fn clear_history(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.open = None;
+        self.depth = 0;
+        self.bytes = 0;
+    }
+
+    /// Starts a group of changes that are undone and redone together.
+    ///
+    /// Groups may be nested; only the outermost group is recorded.
+    pub fn begin_group(&mut self) {
+        self.depth += 1;
+        if self.open.is_none() {
+            self.open = Some(Group {
+                steps: Vec::new(),
+                bytes: 0,
+            });
+        }
+    }
+
+    /// Ends the group started by the matching [`begin_group`](Self::begin_group).
+    ///
+    /// Does nothing if no group is open.
+    pub fn end_group(&mut self) {
+        if self.depth == 0 {
+            return;
+        }
+        self.depth -= 1;
+        if self.depth == 0 {
+            self.close_group();
+        }
+    }
+
+    /// Reverses the most recent group of changes, ending any open group first.
+    ///
+    /// Returns `false` if there was nothing to undo.
+    pub fn undo(&mut self) -> bool {
+        self.depth = 0;
+        self.close_group();
+        let Some(mut group) = self.undo.pop_back() else {
+            return false;
+        };
+        for step in group.steps.iter_mut().rev() {
+            step.undo(&mut self.map);
+        }
+        self.redo.push(group);
+        true
+    }
+
+    /// Reapplies the most recently undone group of changes.
+    ///
+    /// Returns `false` if there was nothing to redo.
+    pub fn redo(&mut self) -> bool {
+        let Some(mut group) = self.redo.pop() else {
+            return false;
+        };
+        for step in &mut group.steps {
+            step.redo(&mut self.map);
+        }
+        self.undo.push_back(group);
+        true
+    }
+
+    /// Push a value into the foreground layer, recording the change.
+    ///
+    /// See [`OverlayMap::push`].
+    pub fn push(&mut self, key: K, value: V) -> bool {
+        let existed = self.map.fg(&key).is_some();
+        let evicted = self.map.swap(key.clone(), value);
+        self.record(Step::Push(key, evicted));
+        existed
+    }
+
+    /// Swap a value into the foreground layer, recording the change and returning the evicted
+    /// background value if present.
+    ///
+    /// See [`OverlayMap::swap`].
+    pub fn swap(&mut self, key: K, value: V) -> Option<V>
+    where
+        V: Clone,
+    {
+        let evicted = self.map.swap(key.clone(), value);
+        self.record(Step::Push(key, evicted.clone()));
+        evicted
+    }
+
+    /// Pull the foreground value for a key, recording the change.
+    ///
+    /// See [`OverlayMap::pull`].
+    pub fn pull(&mut self, key: &K) -> Option<V>
+    where
+        V: Clone,
+    {
+        let pulled = self.map.pull(key)?;
+        self.record(Step::Pull(key.clone(), Some(pulled.clone())));
+        Some(pulled)
+    }
+
+    /// Flip the foreground and background values for a key, recording the change if both
+    /// are present.
+    ///
+    /// See [`OverlayMap::flip`].
+    pub fn flip(&mut self, key: &K) {
+        if self.map.bg(key).is_some() {
+            self.map.flip(key);
+            self.record(Step::Flip(key.clone()));
+        }
+    }
+
+    /// Remove a key and both of its values, recording the change.
+    ///
+    /// See [`OverlayMap::remove`].
+    pub fn remove(&mut self, key: &K) -> Option<Overlay<V>>
+    where
+        V: Clone,
+    {
+        let removed = self.map.remove(key)?;
+        self.record(Step::Remove(key.clone(), Some(removed.clone())));
+        Some(removed)
+    }
+
+    fn record(&mut self, step: Step<K, V>) {
+        let bytes = size_of::<Step<K, V>>() + self.weigh(&step);
+        self.bytes += bytes;
+        for group in self.redo.drain(..) {
+            self.bytes -= group.bytes;
+        }
+
+        match &mut self.open {
+            Some(group) => {
+                group.steps.push(step);
+                group.bytes += bytes;
+            }
+            None => self.undo.push_back(Group {
+                steps: vec![step],
+                bytes,
+            }),
+        }
+        self.trim();
+    }
+
+    fn weigh(&self, step: &Step<K, V>) -> usize {
+        let weigh = self.weigher;
+        match step {
+            // Once undone, a push stashes the pushed value instead of the evicted one, so both
+            // are counted.
+            Step::Push(key, stash) => {
+                let pushed = self.map.fg(key).map_or(0, |value| weigh(key, value));
+                pushed + stash.as_ref().map_or(0, |value| weigh(key, value))
+            }
+            Step::Pull(key, stash) => stash.as_ref().map_or(0, |value| weigh(key, value)),
+            Step::Flip(_) => 0,
+            Step::Remove(key, stash) => stash
+                .iter()
+                .flat_map(|entry| entry.iter())
+                .map(|value| weigh(key, value))
+                .sum(),
+        }
+    }
+
+    fn close_group(&mut self) {
+        if let Some(group) = self.open.take() {
+            if group.steps.is_empty() {
+                return;
+            }
+            self.undo.push_back(group);
+            self.trim();
+        }
+    }
+
+    /// Forgets the oldest undo groups until the history fits the memory cap.
+    fn trim(&mut self) {
+        while self.bytes > self.memory_cap {
+            let Some(group) = self.undo.pop_front() else {
+                break;
+            };
+            self.bytes -= group.bytes;
+        }
+    }
+}
+
+impl<K, V, S> Default for UndoOverlayMap<K, V, S>
+where
+    K: Eq + Hash + Clone,
+    S: BuildHasher + Default,
+{
+    fn default() -> Self {
+        Self::with_hasher(S::default())
+    }
+}
+
+impl<K, V, S> Deref for UndoOverlayMap<K, V, S>
+where
+    K: Eq + Hash,
+{
+    type Target = OverlayMap<K, V, S>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layers(map: &OverlayMap<u8, String>) -> Vec<(u8, Option<String>, Option<String>)> {
+        let mut layers: Vec<_> = map
+            .iter()
+            .map(|(k, e)| (*k, e.fg().cloned(), e.bg().cloned()))
+            .collect();
+        layers.sort();
+        layers
+    }
+
+    #[test]
+    fn undo_and_redo_restore_every_intermediate_state() {
+        let mut map = UndoOverlayMap::<u8, String>::new();
+        let mut states = vec![layers(&map)];
+        let s = |v: &str| v.to_string();
+
+        map.push(1, s("a"));
+        states.push(layers(&map));
+        map.push(1, s("b"));
+        states.push(layers(&map));
+        map.push(1, s("c"));
+        states.push(layers(&map));
+        map.swap(1, s("d"));
+        states.push(layers(&map));
+        map.flip(&1);
+        states.push(layers(&map));
+        map.pull(&1);
+        states.push(layers(&map));
+        map.push(2, s("x"));
+        states.push(layers(&map));
+        map.remove(&1);
+        states.push(layers(&map));
+        map.pull(&2);
+        states.push(layers(&map));
+
+        for expected in states.iter().rev().skip(1) {
+            assert!(map.undo());
+            assert_eq!(&layers(&map), expected);
+        }
+        assert!(!map.undo());
+
+        for expected in states.iter().skip(1) {
+            assert!(map.redo());
+            assert_eq!(&layers(&map), expected);
+        }
+        assert!(!map.redo());
+    }
+
+    #[test]
+    fn groups_and_new_changes_clear_redo() {
+        let mut map = UndoOverlayMap::<u8, u8>::new();
+        map.begin_group();
+        map.push(1, 1);
+        map.begin_group();
+        map.push(2, 2);
+        map.end_group();
+        map.push(3, 3);
+        map.end_group();
+        map.end_group();
+        assert_eq!(map.undo_len(), 1);
+
+        assert!(map.undo());
+        assert!(map.is_empty());
+        assert_eq!(map.redo_len(), 1);
+
+        map.push(4, 4);
+        assert_eq!(map.redo_len(), 0);
+        assert!(!map.redo());
+
+        map.flip(&4);
+        assert_eq!(map.undo_len(), 1);
+        map.clear_history();
+        assert_eq!((map.undo_len(), map.history_bytes()), (0, 0));
+    }
+
+    #[test]
+    fn memory_cap_drops_oldest_groups() {
+        let step = size_of::<Step<u8, u8>>();
+        let mut map = UndoOverlayMap::<u8, u8>::new().with_memory_cap(3 * step);
+        for i in 0..5 {
+            map.push(i, i);
+        }
+        assert_eq!(map.undo_len(), 3);
+        while map.undo() {}
+        assert_eq!(map.len(), 2);
+    }
+}
@@ -0,0 +1,262 @@
+use std::ops::Index;
+
+use crate::Overlay;
+
+/// A dense, integer-indexed two-layered collection where each index holds a current
+/// (foreground) and optional historical (background) value.
+///
+/// `OverlayVec` offers the same push/pull/swap/flip semantics as
+/// [`OverlayMap<usize, T>`](crate::OverlayMap), but stores every [`Overlay<T>`] contiguously
+/// and addresses it directly by index, so no hashing is involved. It is intended for keys
+/// that are small, dense integers such as entity IDs or tile indices.
+///
+/// The backing storage grows to fit the largest index ever pushed, and unoccupied indices
+/// below it cost one empty `Overlay<T>` each. An index is occupied while it has a foreground
+/// value, exactly like a key in an `OverlayMap`.
+///
+/// # Example
+///
+/// ```
+/// use overlay_map::OverlayVec;
+///
+/// let mut tiles = OverlayVec::new();
+/// tiles.push_at(3, "grass");
+/// tiles.push_at(3, "water");
+/// tiles.push_at(7, "sand");
+///
+/// assert_eq!(tiles.fg(3), Some(&"water"));
+/// assert_eq!(tiles.bg(3), Some(&"grass"));
+/// assert_eq!(tiles.len(), 2);
+///
+/// let occupied: Vec<_> = tiles.iter().map(|(i, _)| i).collect();
+/// assert_eq!(occupied, vec![3, 7]);
+///
+/// assert_eq!(tiles.pull_at(7), Some("sand"));
+/// assert_eq!(tiles.fg(7), None);
+/// ```
+#[derive(Debug, Clone)]
+pub struct OverlayVec<T> {
+    slots: Vec<Overlay<T>>,
+    len: usize,
+}
+
+impl<T> OverlayVec<T> {
+    /// Creates a new, empty `OverlayVec`.
+    pub fn new() -> Self {
+        Self {
+            slots: Vec::new(),
+            len: 0,
+        }
+    }
+
+    /// Creates an empty `OverlayVec` with room for indices below `capacity` without
+    /// reallocating.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            slots: Vec::with_capacity(capacity),
+            len: 0,
+        }
+    }
+
+    /// Number of occupied indices.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Check if no index is occupied.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns `true` if the index has a foreground value.
+    #[inline]
+    pub fn contains(&self, index: usize) -> bool {
+        self.fg(index).is_some()
+    }
+
+    /// Get an immutable reference to the foreground value at the index.
+    ///
+    /// Returns `None` if the index is not occupied.
+    #[inline]
+    pub fn fg(&self, index: usize) -> Option<&T> {
+        self.slots.get(index).and_then(|entry| entry.fg())
+    }
+
+    /// Get an immutable reference to the background value at the index.
+    ///
+    /// Returns `None` if the index has no background value.
+    #[inline]
+    pub fn bg(&self, index: usize) -> Option<&T> {
+        self.slots.get(index).and_then(|entry| entry.bg())
+    }
+
+    #[inline]
+    fn slot_mut(&mut self, index: usize) -> &mut Overlay<T> {
+        if index >= self.slots.len() {
+            self.slots.resize_with(index + 1, Overlay::new_empty);
+        }
+        &mut self.slots[index]
+    }
+
+    /// Push a value into the foreground at the index, preserving the previous value in the
+    /// background.
+    ///
+    /// The storage grows if the index is beyond its current end.
+    ///
+    /// Returns `true` if there was already a foreground value.
+    #[inline]
+    pub fn push_at(&mut self, index: usize, value: T) -> bool {
+        let entry = self.slot_mut(index);
+        let occupied = !entry.is_empty();
+        entry.push(value);
+        self.len += !occupied as usize;
+        occupied
+    }
+
+    /// Pulls the foreground value at the index, promoting the background to foreground if
+    /// present.
+    ///
+    /// The index stops being occupied once it has no values left.
+    ///
+    /// Returns `None` if the index was not occupied.
+    #[inline]
+    pub fn pull_at(&mut self, index: usize) -> Option<T> {
+        let entry = self.slots.get_mut(index)?;
+        let pulled = entry.pull()?;
+        if entry.is_empty() {
+            self.len -= 1;
+        }
+        Some(pulled)
+    }
+
+    /// Swap a value into the foreground at the index, returning the evicted background value
+    /// if present.
+    ///
+    /// The storage grows if the index is beyond its current end.
+    #[inline]
+    pub fn swap_at(&mut self, index: usize, value: T) -> Option<T> {
+        let entry = self.slot_mut(index);
+        let occupied = !entry.is_empty();
+        let evicted = entry.swap(value);
+        self.len += !occupied as usize;
+        evicted
+    }
+
+    /// Flips the foreground and background values at the index, if it has both.
+    pub fn flip_at(&mut self, index: usize) {
+        if let Some(entry) = self.slots.get_mut(index) {
+            entry.flip();
+        }
+    }
+
+    /// Get an iterator over the occupied indices and their entries, in ascending index order.
+    ///
+    /// ```
+    /// use overlay_map::OverlayVec;
+    ///
+    /// let mut vec = OverlayVec::new();
+    /// vec.push_at(4, 'b');
+    /// vec.push_at(1, 'a');
+    /// vec.push_at(4, 'c');
+    ///
+    /// let layers: Vec<_> = vec.iter().map(|(i, e)| (i, e.fg(), e.bg())).collect();
+    /// assert_eq!(layers, vec![(1, Some(&'a'), None), (4, Some(&'c'), Some(&'b'))]);
+    /// ```
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (usize, &Overlay<T>)> {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| !entry.is_empty())
+    }
+
+    /// Removes every value, keeping the allocated storage.
+    pub fn clear(&mut self) {
+        self.slots.clear();
+        self.len = 0;
+    }
+}
+
+impl<T> Default for OverlayVec<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: PartialEq> PartialEq for OverlayVec<T> {
+    /// Two `OverlayVec`s are equal if the same indices are occupied with equal entries,
+    /// regardless of how far their storage has grown.
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.iter().eq(other.iter())
+    }
+}
+
+impl<T: Eq> Eq for OverlayVec<T> {}
+
+impl<T> Extend<(usize, T)> for OverlayVec<T> {
+    /// Pushes each `(index, value)` pair in order.
+    fn extend<I: IntoIterator<Item = (usize, T)>>(&mut self, iter: I) {
+        for (index, value) in iter {
+            self.push_at(index, value);
+        }
+    }
+}
+
+impl<T> FromIterator<(usize, T)> for OverlayVec<T> {
+    /// Creates an `OverlayVec` by pushing each `(index, value)` pair in order.
+    ///
+    /// ```
+    /// use overlay_map::OverlayVec;
+    ///
+    /// let vec: OverlayVec<_> = [(0, "a"), (2, "b"), (0, "c")].into_iter().collect();
+    /// assert_eq!(vec.fg(0), Some(&"c"));
+    /// assert_eq!(vec.bg(0), Some(&"a"));
+    /// assert_eq!(vec.len(), 2);
+    /// ```
+    fn from_iter<I: IntoIterator<Item = (usize, T)>>(iter: I) -> Self {
+        let mut vec = Self::new();
+        vec.extend(iter);
+        vec
+    }
+}
+
+impl<T> Index<usize> for OverlayVec<T> {
+    type Output = T;
+
+    /// Returns a reference to the foreground value at the index.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the index is not occupied.
+    #[inline]
+    fn index(&self, index: usize) -> &T {
+        self.fg(index).expect("index not occupied in OverlayVec")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn len_tracks_occupied_indices() {
+        let mut vec = OverlayVec::new();
+        assert!(!vec.push_at(5, 1));
+        assert!(vec.push_at(5, 2));
+        assert_eq!(vec.swap_at(5, 3), Some(1));
+        assert_eq!(vec.swap_at(2, 9), None);
+        assert_eq!(vec.len(), 2);
+
+        vec.flip_at(5);
+        assert_eq!((vec.fg(5), vec.bg(5)), (Some(&2), Some(&3)));
+
+        assert_eq!(vec.pull_at(5), Some(2));
+        assert_eq!(vec.pull_at(5), Some(3));
+        assert_eq!(vec.pull_at(5), None);
+        assert_eq!(vec.pull_at(100), None);
+        assert_eq!(vec.len(), 1);
+
+        let mut other = OverlayVec::with_capacity(16);
+        other.push_at(2, 9);
+        assert_eq!(vec, other);
+    }
+}